@@ -0,0 +1,94 @@
+use social_auth_types::{ErrorCode, ErrorResponse, UpstreamErrorResponse};
+use std::fmt;
+use std::time::Duration;
+
+#[derive(Debug)]
+pub enum Error {
+    /// the server answered with an error
+    Api {
+        response: Box<ErrorResponse>,
+        /// how long to wait before trying again, sent with rate limit errors
+        retry_after: Option<Duration>,
+    },
+    /// the server answered with something that isn't from the api, e.g. the error page of a proxy
+    UnexpectedResponse { status: u16, body: String },
+    /// the server couldn't be reached or its response couldn't be read
+    Http(reqwest::Error),
+    InvalidUrl(url::ParseError),
+}
+
+impl Error {
+    /// the stable code of the error if the api sent one
+    pub fn code(&self) -> Option<ErrorCode> {
+        match self {
+            Error::Api { response, .. } => Some(response.code),
+            _ => None,
+        }
+    }
+
+    /// the http status the server answered with
+    pub fn status(&self) -> Option<u16> {
+        match self {
+            Error::Api { response, .. } => Some(response.status),
+            Error::UnexpectedResponse { status, .. } => Some(*status),
+            Error::Http(e) => e.status().map(|status| status.as_u16()),
+            Error::InvalidUrl(_) => None,
+        }
+    }
+
+    pub fn retry_after(&self) -> Option<Duration> {
+        match self {
+            Error::Api { retry_after, .. } => *retry_after,
+            _ => None,
+        }
+    }
+
+    /// what twitch or twitter answered with if they caused the error
+    pub fn upstream(&self) -> Option<&UpstreamErrorResponse> {
+        match self {
+            Error::Api { response, .. } => response.upstream.as_ref(),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Api { response, .. } => write!(
+                f,
+                "{} ({}): {}",
+                response.code.name(),
+                response.status,
+                response.message
+            ),
+            Error::UnexpectedResponse { status, body } => {
+                write!(f, "unexpected response with status {}: {}", status, body)
+            }
+            Error::Http(e) => write!(f, "request failed: {}", e),
+            Error::InvalidUrl(e) => write!(f, "invalid url: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Http(e) => Some(e),
+            Error::InvalidUrl(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<reqwest::Error> for Error {
+    fn from(err: reqwest::Error) -> Self {
+        Error::Http(err)
+    }
+}
+
+impl From<url::ParseError> for Error {
+    fn from(err: url::ParseError) -> Self {
+        Error::InvalidUrl(err)
+    }
+}
@@ -0,0 +1,182 @@
+//! Client for the `/api/v1` of a social_auth server.
+//!
+//! ```no_run
+//! # async fn run() -> Result<(), social_auth_client::Error> {
+//! let client = social_auth_client::Client::new("https://auth.example.com", "<id>.<secret>")?;
+//! let channel_id = client.twitch_login_to_id("onestay", None).await?;
+//! # Ok(())
+//! # }
+//! ```
+
+mod error;
+
+pub use error::Error;
+pub use social_auth_types::*;
+
+use reqwest::header::{AUTHORIZATION, RETRY_AFTER};
+use reqwest::{Method, RequestBuilder, Response};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::time::Duration;
+use url::Url;
+
+/// What to look for in the audit log, every field that is set has to match.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct AuditQuery {
+    /// username or api key id
+    pub actor: Option<String>,
+    pub action: Option<String>,
+    pub service: Option<String>,
+    pub target: Option<String>,
+    /// unix timestamps, both inclusive
+    pub since: Option<u64>,
+    pub until: Option<u64>,
+    /// at most 1000, 100 if not set
+    pub limit: Option<usize>,
+}
+
+/// A client for one server, authenticated with one api key.
+#[derive(Clone)]
+pub struct Client {
+    http: reqwest::Client,
+    base_url: Url,
+    api_key: String,
+}
+
+impl Client {
+    /// `base_url` is where the server is reachable, `api_key` is created on its dashboard
+    pub fn new(base_url: &str, api_key: &str) -> Result<Self, Error> {
+        Self::with_http_client(reqwest::Client::new(), base_url, api_key)
+    }
+
+    /// same as `new` but sends the requests with `http`, e.g. to set timeouts or a proxy
+    pub fn with_http_client(http: reqwest::Client, base_url: &str, api_key: &str) -> Result<Self, Error> {
+        let base_url = Url::parse(&format!("{}/api/v1/", base_url.trim_end_matches('/')))?;
+        Ok(Client {
+            http,
+            base_url,
+            api_key: api_key.to_string(),
+        })
+    }
+
+    fn request(&self, method: Method, path: &str, query: &[(&str, Option<&str>)]) -> Result<RequestBuilder, Error> {
+        let mut url = self.base_url.join(path)?;
+        for (name, value) in query {
+            if let Some(value) = value {
+                url.query_pairs_mut().append_pair(name, value);
+            }
+        }
+
+        Ok(self
+            .http
+            .request(method, url)
+            .header(AUTHORIZATION, &self.api_key))
+    }
+
+    /// sends `request` and turns error responses into `Error::Api`
+    async fn send(&self, request: RequestBuilder) -> Result<Response, Error> {
+        let res = request.send().await?;
+        if res.status().is_success() {
+            return Ok(res);
+        }
+
+        let status = res.status().as_u16();
+        let retry_after = res
+            .headers()
+            .get(RETRY_AFTER)
+            .and_then(|value| value.to_str().ok()?.parse().ok())
+            .map(Duration::from_secs);
+        let body = res.text().await?;
+        Err(match serde_json::from_str::<ErrorResponse>(&body) {
+            Ok(response) => Error::Api {
+                response: Box::new(response),
+                retry_after,
+            },
+            Err(_) => Error::UnexpectedResponse { status, body },
+        })
+    }
+
+    async fn json<T: DeserializeOwned>(&self, request: RequestBuilder) -> Result<T, Error> {
+        Ok(self.send(request).await?.json().await?)
+    }
+
+    /// which services have connected accounts
+    pub async fn avail(&self) -> Result<CheckAvailResponse, Error> {
+        self.json(self.request(Method::GET, "avail", &[])?).await
+    }
+
+    /// checks every connected token with its service
+    pub async fn status(&self) -> Result<HashMap<String, ServiceStatus>, Error> {
+        let res: GenericApiResponse<_> = self.json(self.request(Method::GET, "status", &[])?).await?;
+        Ok(res.data)
+    }
+
+    /// the token of a connected account as the service returned it.
+    /// `account` is optional if only one account of `service` is connected
+    pub async fn token(&self, service: &str, account: Option<&str>) -> Result<serde_json::Value, Error> {
+        let request = self.request(Method::GET, "auth", &[("service", Some(service)), ("account", account)])?;
+        self.json(request).await
+    }
+
+    /// revokes the token of an account and forgets it, needs the `manage_accounts` scope
+    pub async fn disconnect(&self, service: &str, account: Option<&str>) -> Result<(), Error> {
+        let request = self.request(Method::DELETE, "auth", &[("service", Some(service)), ("account", account)])?;
+        self.send(request).await?;
+        Ok(())
+    }
+
+    /// needs the `tweet` scope
+    pub async fn tweet(&self, tweet: &PostTweetRequest) -> Result<(), Error> {
+        self.send(self.request(Method::POST, "tweet", &[])?.json(tweet)).await?;
+        Ok(())
+    }
+
+    /// the id of the twitch channel `login`
+    pub async fn twitch_login_to_id(&self, login: &str, account: Option<&str>) -> Result<String, Error> {
+        let request = self.request(
+            Method::GET,
+            "twitch/login_to_id",
+            &[("login", Some(login)), ("account", account)],
+        )?;
+        let res: GenericApiResponse<_> = self.json(request).await?;
+        Ok(res.data)
+    }
+
+    /// sets the title and game of a channel, needs the `twitch_update` scope
+    pub async fn twitch_update(&self, update: &TwitchUpdateRequest) -> Result<(), Error> {
+        self.send(self.request(Method::POST, "twitch/update", &[])?.json(update))
+            .await?;
+        Ok(())
+    }
+
+    /// runs a commercial of `length` seconds, needs the `twitch_commercial` scope
+    pub async fn twitch_commercial(
+        &self,
+        login: &str,
+        length: u16,
+        account: Option<&str>,
+    ) -> Result<TwitchAdJson, Error> {
+        let length = length.to_string();
+        let request = self.request(
+            Method::POST,
+            "twitch/commercial",
+            &[("login", Some(login)), ("length", Some(&length)), ("account", account)],
+        )?;
+        self.json(request).await
+    }
+
+    /// the outcome of the last refresh of a twitch token, None if it wasn't refreshed yet
+    pub async fn twitch_refresh_status(&self, account: Option<&str>) -> Result<Option<RefreshStatus>, Error> {
+        let request = self.request(Method::GET, "twitch/refresh_status", &[("account", account)])?;
+        let res: GenericApiResponse<_> = self.json(request).await?;
+        Ok(res.data)
+    }
+
+    /// entries of the audit log, newest first, needs the `read_audit` scope
+    pub async fn audit(&self, query: &AuditQuery) -> Result<Vec<AuditEntry>, Error> {
+        let request = self.request(Method::GET, "audit", &[])?.query(query);
+        let res: GenericApiResponse<_> = self.json(request).await?;
+        Ok(res.data)
+    }
+}
@@ -0,0 +1,59 @@
+use serde::{Deserialize, Serialize};
+
+/// Who did something.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Actor {
+    /// a user of the dashboard
+    User { username: String },
+    ApiKey { id: String, name: String },
+    /// `social_auth-admin` run on the server
+    Cli,
+}
+
+impl Actor {
+    pub fn user(username: &str) -> Self {
+        Actor::User {
+            username: username.to_string(),
+        }
+    }
+
+    pub fn api_key(id: &str, name: &str) -> Self {
+        Actor::ApiKey {
+            id: id.to_string(),
+            name: name.to_string(),
+        }
+    }
+
+    /// the username, the id of the api key or `cli`
+    pub fn id(&self) -> &str {
+        match self {
+            Actor::User { username } => username,
+            Actor::ApiKey { id, .. } => id,
+            Actor::Cli => "cli",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum Outcome {
+    Success,
+    Failure { message: String },
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct AuditEntry {
+    pub timestamp: u64,
+    pub actor: Actor,
+    /// e.g. `tweet` or `connect_account`
+    pub action: String,
+    /// the service the action was for
+    pub service: Option<String>,
+    /// the connected account, api key or user the action was done to
+    pub target: Option<String>,
+    pub outcome: Outcome,
+}
@@ -0,0 +1,129 @@
+use serde::{Deserialize, Serialize};
+
+/// Machine readable error codes, api clients can match on them and they don't change.
+///
+/// New codes can be added, older clients read them as `Unknown`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[serde(rename_all = "snake_case")]
+#[non_exhaustive]
+pub enum ErrorCode {
+    InvalidRequest,
+    Unauthorized,
+    Forbidden,
+    NotFound,
+    AccountNotConnected,
+    MissingUpstreamScope,
+    Conflict,
+    RateLimited,
+    UpstreamRateLimited,
+    UpstreamUnauthorized,
+    UpstreamError,
+    UpstreamUnavailable,
+    UpstreamTimeout,
+    Internal,
+    /// a code this version doesn't know yet, the server never sends it
+    #[serde(other)]
+    Unknown,
+}
+
+impl ErrorCode {
+    /// every code the server sends
+    pub const ALL: [ErrorCode; 14] = [
+        ErrorCode::InvalidRequest,
+        ErrorCode::Unauthorized,
+        ErrorCode::Forbidden,
+        ErrorCode::NotFound,
+        ErrorCode::AccountNotConnected,
+        ErrorCode::MissingUpstreamScope,
+        ErrorCode::Conflict,
+        ErrorCode::RateLimited,
+        ErrorCode::UpstreamRateLimited,
+        ErrorCode::UpstreamUnauthorized,
+        ErrorCode::UpstreamError,
+        ErrorCode::UpstreamUnavailable,
+        ErrorCode::UpstreamTimeout,
+        ErrorCode::Internal,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            ErrorCode::InvalidRequest => "invalid_request",
+            ErrorCode::Unauthorized => "unauthorized",
+            ErrorCode::Forbidden => "forbidden",
+            ErrorCode::NotFound => "not_found",
+            ErrorCode::AccountNotConnected => "account_not_connected",
+            ErrorCode::MissingUpstreamScope => "missing_upstream_scope",
+            ErrorCode::Conflict => "conflict",
+            ErrorCode::RateLimited => "rate_limited",
+            ErrorCode::UpstreamRateLimited => "upstream_rate_limited",
+            ErrorCode::UpstreamUnauthorized => "upstream_unauthorized",
+            ErrorCode::UpstreamError => "upstream_error",
+            ErrorCode::UpstreamUnavailable => "upstream_unavailable",
+            ErrorCode::UpstreamTimeout => "upstream_timeout",
+            ErrorCode::Internal => "internal",
+            ErrorCode::Unknown => "unknown",
+        }
+    }
+
+    /// the http status responses with this code have, for `Unknown` the status of the response has to be used
+    pub fn status(&self) -> u16 {
+        match self {
+            ErrorCode::InvalidRequest => 400,
+            ErrorCode::Unauthorized => 401,
+            ErrorCode::Forbidden | ErrorCode::MissingUpstreamScope => 403,
+            ErrorCode::NotFound => 404,
+            ErrorCode::AccountNotConnected | ErrorCode::Conflict => 409,
+            ErrorCode::RateLimited | ErrorCode::UpstreamRateLimited => 429,
+            ErrorCode::UpstreamUnauthorized | ErrorCode::UpstreamError | ErrorCode::UpstreamUnavailable => 502,
+            ErrorCode::UpstreamTimeout => 504,
+            ErrorCode::Internal | ErrorCode::Unknown => 500,
+        }
+    }
+
+    /// what went wrong and what a client can do about it
+    pub fn description(&self) -> &'static str {
+        match self {
+            ErrorCode::InvalidRequest => "the request is malformed or has an invalid value",
+            ErrorCode::Unauthorized => "no api key was sent or it doesn't exist",
+            ErrorCode::Forbidden => "the api key is missing the scope the route needs",
+            ErrorCode::NotFound => "the route or something the request refers to doesn't exist",
+            ErrorCode::AccountNotConnected => "no account of the service is connected or not the requested one, connect it on the dashboard",
+            ErrorCode::MissingUpstreamScope => "the connected account didn't grant a scope the request needs, connect it again with that scope",
+            ErrorCode::Conflict => "the request conflicts with the current state, e.g. something with that name already exists",
+            ErrorCode::RateLimited => "the api key exceeded its rate limit, try again after Retry-After seconds",
+            ErrorCode::UpstreamRateLimited => "twitch or twitter are rate limiting us, try again after Retry-After seconds if it is set",
+            ErrorCode::UpstreamUnauthorized => "twitch or twitter rejected the token of the account, connect it again",
+            ErrorCode::UpstreamError => "twitch or twitter answered with an error, `upstream` has the details",
+            ErrorCode::UpstreamUnavailable => "twitch or twitter couldn't be reached",
+            ErrorCode::UpstreamTimeout => "twitch or twitter didn't answer in time",
+            ErrorCode::Internal => "something went wrong on our side",
+            ErrorCode::Unknown => "a code that was added after this client was built",
+        }
+    }
+}
+
+/// The service part of an error response that was caused by twitch or twitter.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct UpstreamErrorResponse {
+    pub service: String,
+    /// the http status the service answered with, missing if it didn't answer
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub status: Option<u16>,
+    /// the error code of the service, e.g. the `error` of twitch or the error code of twitter
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub code: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct ErrorResponse {
+    pub status: u16,
+    /// reason phrase of the status
+    pub error: String,
+    pub code: ErrorCode,
+    pub message: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub upstream: Option<UpstreamErrorResponse>,
+}
@@ -0,0 +1,117 @@
+//! The requests and responses of the social_auth api, shared by the server and the client.
+
+mod audit;
+mod error;
+
+pub use audit::{Actor, AuditEntry, Outcome};
+pub use error::{ErrorCode, ErrorResponse, UpstreamErrorResponse};
+
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// Successful responses that aren't a plain object wrap their data in this.
+#[derive(Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[cfg_attr(feature = "openapi", aliases(
+    StringResponse = GenericApiResponse<String>,
+    StatusResponse = GenericApiResponse<HashMap<String, ServiceStatus>>,
+    AuditResponse = GenericApiResponse<Vec<AuditEntry>>,
+    RefreshStatusResponse = GenericApiResponse<Option<RefreshStatus>>,
+))]
+pub struct GenericApiResponse<T: Serialize> {
+    pub data: T,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct CheckAvailResponse {
+    /// whether an account of the service is connected, e.g. `"twitch": true`
+    #[serde(flatten)]
+    pub services: HashMap<String, bool>,
+    /// connected accounts per service
+    #[cfg_attr(feature = "openapi", schema(value_type = Object))]
+    pub accounts: HashMap<String, Vec<String>>,
+}
+
+/// What the service said about the token of an account when it was asked just now.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct TokenStatus {
+    pub account: String,
+    pub valid: bool,
+    /// unix timestamp, None if the token doesn't expire or is invalid
+    pub expires_at: Option<u64>,
+    /// the scopes the service reported for the token
+    pub scopes: Vec<String>,
+    /// why the token isn't valid
+    pub error: Option<String>,
+}
+
+impl TokenStatus {
+    pub fn valid(account: &str, expires_at: Option<u64>, scopes: Vec<String>) -> Self {
+        TokenStatus {
+            account: account.to_string(),
+            valid: true,
+            expires_at,
+            scopes,
+            error: None,
+        }
+    }
+
+    pub fn invalid(account: &str, error: &str) -> Self {
+        TokenStatus {
+            account: account.to_string(),
+            valid: false,
+            expires_at: None,
+            scopes: vec![],
+            error: Some(error.to_string()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct ServiceStatus {
+    pub accounts: Vec<TokenStatus>,
+    /// unix timestamp of the last request to the service that succeeded
+    pub last_success: Option<u64>,
+}
+
+/// outcome of the last attempt to refresh the twitch token
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct RefreshStatus {
+    pub at: u64,
+    pub success: bool,
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct PostTweetRequest {
+    pub body: String,
+    /// twitter account to tweet from, optional if only one is connected
+    pub account: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct TwitchUpdateRequest {
+    pub game: String,
+    pub title: String,
+    /// login name of the channel
+    pub login: String,
+    /// twitch account whose token is used, defaults to the channel itself if it is connected
+    pub account: Option<String>,
+}
+
+/// what twitch said about a commercial that was started
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct TwitchAdJson {
+    /// seconds the commercial runs, can be shorter than requested
+    pub length: u64,
+    pub message: String,
+    /// seconds until the next commercial can be run
+    pub retry_after: u64,
+}
@@ -0,0 +1,345 @@
+use crate::api_keys::{ApiKeyInfo, Scope};
+use crate::audit::{Actor, AuditLog};
+use crate::error::Error;
+use crate::provider::TokenStatus;
+use crate::templates::gen_random_string;
+use crate::store::StoreLock;
+use crate::{open_audit_log, store_lock_path, Config, InvalidConfig, Services};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::io::{self, BufRead, Write};
+use std::time::{SystemTime, UNIX_EPOCH};
+use url::Url;
+
+const USAGE: &str = "usage: social_auth-admin <command>
+
+reads the same config file and environment variables as the server.
+
+commands:
+  accounts                                       connected accounts with the expiry and scopes of their tokens
+  refresh <service> <account>                    gets a new token for an account
+  revoke <service> <account>                     revokes the token of an account and forgets it
+  authorize <service> [scope...]                 connects an account without a browser on the server
+  api-keys                                       lists the api keys
+  create-api-key <name> <scope>... [--rate-limit <n>]
+  revoke-api-key <id>
+  export <file>                                  writes every value of the token store to a file, unencrypted
+  import <file>                                  saves the values of an export in the token store
+
+api keys can be changed while the server is running. refresh, revoke, authorize and import
+change tokens the server has loaded, they refuse to run until the server is stopped.";
+
+/// Everything in the token store, what `export` writes and `import` reads.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct StoreExport {
+    /// the values are base64 since the store doesn't care what they contain
+    pub entries: BTreeMap<String, String>,
+}
+
+/// An authorization that waits for the url the service redirected to.
+pub struct PendingAuthorization {
+    pub service: String,
+    /// the user has to open this and authorize us
+    pub url: String,
+    state: String,
+}
+
+/// What `social_auth-admin` can do, it works on the token store directly so the server doesn't have to run.
+pub struct AdminCli {
+    services: Services,
+    audit: AuditLog,
+    /// `None` while the server runs, the tokens are only read then
+    lock: Option<StoreLock>,
+}
+
+impl AdminCli {
+    pub async fn new(config: &Config) -> Result<Self, InvalidConfig> {
+        // taken before loading so a running server's store isn't rewritten underneath it
+        let lock = StoreLock::try_acquire(&store_lock_path(config))
+            .map_err(|e| InvalidConfig::new("token_store.path", format!("unable to lock the token store: {}", e.message())))?;
+        Ok(AdminCli {
+            services: Services::load(config, lock.as_ref()).await?,
+            audit: open_audit_log(config).await?,
+            lock,
+        })
+    }
+
+    /// the server keeps using the tokens it loaded, so they are only changed here while it is stopped
+    fn lock_store(&self) -> Result<(), Error> {
+        match self.lock {
+            Some(_) => Ok(()),
+            None => Err(Error::new_conflict(
+                "the server is running, stop it first or use the dashboard".to_string(),
+            )),
+        }
+    }
+
+    /// every connected account and what its service says about the token right now
+    pub async fn accounts(&self) -> Vec<(&'static str, TokenStatus)> {
+        let mut accounts = vec![];
+        for provider in self.services.providers.iter() {
+            for account in provider.accounts().await {
+                accounts.push((provider.name(), provider.check(&account).await));
+            }
+        }
+
+        accounts
+    }
+
+    pub async fn refresh(&self, service: &str, account: &str) -> Result<(), Error> {
+        self.lock_store()?;
+        let provider = self.services.providers.get_or_err(service)?;
+        let account = provider.select_account(Some(account)).await?;
+        let result = provider.force_refresh(&account).await;
+        self.audit.record(Actor::Cli, "refresh_token", Some(service), Some(&account), &result).await;
+        result
+    }
+
+    pub async fn revoke(&self, service: &str, account: &str) -> Result<(), Error> {
+        self.lock_store()?;
+        let provider = self.services.providers.get_or_err(service)?;
+        let account = provider.select_account(Some(account)).await?;
+        let result = provider.revoke(&account).await;
+        self.audit.record(Actor::Cli, "disconnect_account", Some(service), Some(&account), &result).await;
+        result
+    }
+
+    /// `scopes` are names of the scopes of the service, its default ones are requested if it is empty
+    pub async fn start_authorization(&self, service: &str, scopes: &[String]) -> Result<PendingAuthorization, Error> {
+        // fail before the user authorizes in the browser
+        self.lock_store()?;
+        let provider = self.services.providers.get_or_err(service)?;
+        let state = gen_random_string(32);
+        Ok(PendingAuthorization {
+            service: service.to_string(),
+            url: provider.authorize_url(&state, scopes).await?,
+            state,
+        })
+    }
+
+    /// connects the account from the url the browser was redirected to after authorizing, returns the account
+    pub async fn finish_authorization(&self, pending: PendingAuthorization, callback_url: &str) -> Result<String, Error> {
+        self.lock_store()?;
+        let provider = self.services.providers.get_or_err(&pending.service)?;
+        let callback_url = Url::parse(callback_url.trim())
+            .map_err(|_| Error::new_bad_request("the callback url isn't a url".to_string()))?;
+        let params: HashMap<String, String> = callback_url.query_pairs().into_owned().collect();
+        if params.get("state") != Some(&pending.state) {
+            return Err(Error::new_bad_request(
+                "the callback url doesn't belong to this authorization".to_string(),
+            ));
+        }
+
+        let result = provider.authorize_callback(&params).await;
+        let account = result.as_ref().ok().map(String::as_str);
+        self.audit.record(Actor::Cli, "connect_account", Some(provider.name()), account, &result).await;
+        result
+    }
+
+    pub async fn api_keys(&self) -> Vec<ApiKeyInfo> {
+        self.services.api_keys.list().await
+    }
+
+    /// returns the key together with its info, this is the only time the key is known
+    pub async fn create_api_key(
+        &self,
+        name: &str,
+        scopes: Vec<Scope>,
+        rate_limit: Option<u32>,
+    ) -> Result<(ApiKeyInfo, String), Error> {
+        let result = self.services.api_keys.create(name, scopes, rate_limit).await;
+        let target = result.as_ref().ok().map(|(info, _)| info.id.as_str());
+        self.audit.record(Actor::Cli, "create_api_key", None, target, &result).await;
+        result
+    }
+
+    pub async fn revoke_api_key(&self, id: &str) -> Result<(), Error> {
+        let result = self.services.api_keys.revoke(id).await;
+        self.audit.record(Actor::Cli, "revoke_api_key", None, Some(id), &result).await;
+        result
+    }
+
+    /// every value of the token store, decrypted if the store is encrypted
+    pub async fn export(&self) -> Result<StoreExport, Error> {
+        let mut entries = BTreeMap::new();
+        for key in self.services.token_store.keys().await? {
+            if let Some(value) = self.services.token_store.load(&key).await? {
+                entries.insert(key, base64::encode(value));
+            }
+        }
+
+        Ok(StoreExport { entries })
+    }
+
+    /// saves every value of `export`, replacing values that already exist. returns how many were saved
+    pub async fn import(&self, export: &StoreExport) -> Result<usize, Error> {
+        // decode everything first so a broken export doesn't get imported halfway
+        let mut values = Vec::with_capacity(export.entries.len());
+        for (key, value) in &export.entries {
+            let value = base64::decode(value)
+                .map_err(|_| Error::new_bad_request(format!("the value of {} isn't base64", key)))?;
+            values.push((key, value));
+        }
+
+        self.lock_store()?;
+        for (key, value) in &values {
+            self.services.token_store.save(key, value).await?;
+        }
+
+        Ok(values.len())
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("system time is before the unix epoch")
+        .as_secs()
+}
+
+/// `3h 59m` for a number of seconds
+fn format_duration(secs: u64) -> String {
+    match secs {
+        0..=59 => format!("{}s", secs),
+        60..=3599 => format!("{}m", secs / 60),
+        _ => format!("{}h {}m", secs / 3600, secs % 3600 / 60),
+    }
+}
+
+fn parse_scopes(names: &[String]) -> Result<Vec<Scope>, Error> {
+    names
+        .iter()
+        .map(|name| {
+            Scope::ALL.iter().copied().find(|scope| scope.name() == name).ok_or_else(|| {
+                let available: Vec<&str> = Scope::ALL.iter().map(Scope::name).collect();
+                Error::new_bad_request(format!("unknown scope {}, available scopes: {}", name, available.join(", ")))
+            })
+        })
+        .collect()
+}
+
+/// the arguments of `create-api-key` after the name, scopes and an optional `--rate-limit <n>`
+fn parse_api_key_args(args: &[String]) -> Result<(Vec<Scope>, Option<u32>), Error> {
+    match args.iter().position(|arg| arg == "--rate-limit") {
+        Some(i) => {
+            let rate_limit = args
+                .get(i + 1)
+                .and_then(|n| n.parse().ok())
+                .ok_or_else(|| Error::new_bad_request("--rate-limit needs a number of requests per minute".to_string()))?;
+            let scopes: Vec<String> = args[..i].iter().chain(&args[i + 2..]).cloned().collect();
+            Ok((parse_scopes(&scopes)?, Some(rate_limit)))
+        }
+        None => Ok((parse_scopes(args)?, None)),
+    }
+}
+
+fn write_export(path: &str, export: &StoreExport) -> Result<(), Error> {
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    // the tokens are in plain text
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+
+    let mut file = options.open(path)?;
+    file.write_all(&serde_json::to_vec_pretty(export)?)?;
+    Ok(())
+}
+
+fn usage_error() -> Error {
+    Error::new_bad_request(USAGE.to_string())
+}
+
+async fn run_command(args: &[String]) -> Result<(), Error> {
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+    let command = match args.first() {
+        Some(&"help") | Some(&"--help") | Some(&"-h") | None => {
+            println!("{}", USAGE);
+            return Ok(());
+        }
+        Some(command) => *command,
+    };
+
+    let config = Config::load().map_err(|e| Error::new_bad_request(e.to_string()))?;
+    let cli = AdminCli::new(&config).await.map_err(|e| Error::new_bad_request(e.to_string()))?;
+    match (command, &args[1..]) {
+        ("accounts", []) => {
+            for (service, status) in cli.accounts().await {
+                if !status.valid {
+                    println!("{} {}: invalid, {}", service, status.account, status.error.unwrap_or_default());
+                    continue;
+                }
+
+                let expiry = match status.expires_at {
+                    Some(expires_at) => format!("expires in {}", format_duration(expires_at.saturating_sub(now()))),
+                    None => "doesn't expire".to_string(),
+                };
+                println!("{} {}: valid, {}, scopes: {}", service, status.account, expiry, status.scopes.join(" "));
+            }
+        }
+        ("refresh", [service, account]) => {
+            cli.refresh(service, account).await?;
+            println!("refreshed the token of {} account {}", service, account);
+        }
+        ("revoke", [service, account]) => {
+            cli.revoke(service, account).await?;
+            println!("revoked the token of {} account {}", service, account);
+        }
+        ("authorize", [service, scopes @ ..]) => {
+            let scopes: Vec<String> = scopes.iter().map(|scope| scope.to_string()).collect();
+            let pending = cli.start_authorization(service, &scopes).await?;
+            println!("open this url on any device and authorize the account:\n\n{}\n", pending.url);
+            println!("then paste the url your browser was redirected to, it doesn't matter if the page didn't load:");
+            let mut callback_url = String::new();
+            io::stdin().lock().read_line(&mut callback_url)?;
+            let account = cli.finish_authorization(pending, &callback_url).await?;
+            println!("connected {} account {}", service, account);
+        }
+        ("api-keys", []) => {
+            for key in cli.api_keys().await {
+                let scopes: Vec<&str> = key.scopes.iter().map(Scope::name).collect();
+                let last_used = match key.last_used {
+                    Some(last_used) => format!("{} ago", format_duration(now().saturating_sub(last_used))),
+                    None => "never".to_string(),
+                };
+                println!("{} {}: scopes: {}, last used: {}", key.id, key.name, scopes.join(" "), last_used);
+            }
+        }
+        ("create-api-key", [name, rest @ ..]) if !rest.is_empty() => {
+            let rest: Vec<String> = rest.iter().map(|arg| arg.to_string()).collect();
+            let (scopes, rate_limit) = parse_api_key_args(&rest)?;
+            let (info, key) = cli.create_api_key(name, scopes, rate_limit).await?;
+            println!("created api key {}, it isn't shown again:\n{}", info.id, key);
+        }
+        ("revoke-api-key", [id]) => {
+            cli.revoke_api_key(id).await?;
+            println!("revoked api key {}", id);
+        }
+        ("export", [path]) => {
+            let export = cli.export().await?;
+            write_export(path, &export)?;
+            println!("exported {} values to {}", export.entries.len(), path);
+        }
+        ("import", [path]) => {
+            let export: StoreExport = serde_json::from_slice(&std::fs::read(path)?)?;
+            let imported = cli.import(&export).await?;
+            println!("imported {} values", imported);
+        }
+        _ => return Err(usage_error()),
+    }
+
+    Ok(())
+}
+
+/// runs the command in `args` (without the program name) and returns the exit code
+pub async fn run(args: &[String]) -> i32 {
+    match run_command(args).await {
+        Ok(()) => 0,
+        Err(e) => {
+            eprintln!("{}", e.message());
+            1
+        }
+    }
+}
@@ -1,68 +1,213 @@
+use crate::api_keys::{ApiKeyInfo, ApiKeys, Scope};
+use crate::audit::{Actor, AuditFilter, AuditLog};
 use crate::error::Error;
-use crate::twitch_config::TwitchAdJson;
-use crate::{templates, twitch_config::Twitch};
+use crate::logging;
+use crate::metrics::Metrics;
+use crate::provider::{Providers, SocialProvider};
+use crate::rate_limit::RateLimiter;
+use crate::twitch_config::{RefreshStatus, Twitch, TwitchAdJson};
 
 use rocket::http::Status;
 use rocket::request::{FromRequest, Outcome, Request};
 use rocket::response::{content, status};
-use rocket::serde::{json::Json, Deserialize, Serialize};
+use rocket::serde::json::Json;
 use rocket::State;
-use tokio::fs;
+use std::collections::HashMap;
+use std::marker::PhantomData;
+use std::sync::Arc;
+use std::time::Duration;
 
 use crate::twitter_config::Twitter;
 
-#[get("/auth?<service>")]
+pub use social_auth_types::{
+    AuditEntry, CheckAvailResponse, GenericApiResponse, PostTweetRequest, ServiceStatus, TwitchUpdateRequest,
+};
+
+/// the token of a connected account as the service returned it
+#[utoipa::path(
+    get,
+    path = "/api/v1/auth",
+    tag = "accounts",
+    params(
+        ("service" = String, Query, description = "`twitch` or `twitter`"),
+        ("account" = Option<String>, Query, description = "the account, optional if only one is connected"),
+    ),
+    responses(
+        (status = 200, description = "the token, its fields depend on the service", body = Object),
+        (status = 400, description = "multiple accounts are connected and none was given", body = ErrorResponse),
+        (status = 404, description = "unknown service", body = ErrorResponse),
+        (status = 409, description = "the account isn't connected", body = ErrorResponse),
+        (status = 502, description = "refreshing the token failed", body = ErrorResponse),
+    ),
+    security(("api_key" = [])),
+)]
+#[get("/auth?<service>&<account>")]
 async fn get_twitch_info(
-    _api_key: ApiKey<'_>,
+    _api_key: ApiKey<ReadAuth>,
+    providers: &State<Providers>,
     service: &str,
+    account: Option<&str>,
 ) -> Result<content::Json<Vec<u8>>, Error> {
-    let bytes = fs::read(format!("{}_auth.json", service)).await;
-    let bytes = match bytes {
-        Ok(bytes) => bytes,
-        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
-            return Err(Error::new_bad_request(
-                format!("no {} auth info available", service),
-            ));
-        }
-        Err(e) => return Err(e.into()),
-    };
+    let provider = providers.get_or_err(service)?;
+    let account = provider.select_account(account).await?;
+    // make sure we don't hand out a token that expired in the meantime
+    provider.refresh(&account).await?;
 
-    Ok(content::Json(bytes))
+    match provider.token_json(&account).await? {
+        Some(bytes) => Ok(content::Json(bytes)),
+        None => Err(Error::new_auth_not_avail(service)),
+    }
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-pub struct GenericApiResponse<T: Serialize> {
-    data: T,
+/// revokes the token of an account and forgets it, needs the `manage_accounts` scope
+#[utoipa::path(
+    delete,
+    path = "/api/v1/auth",
+    tag = "accounts",
+    params(
+        ("service" = String, Query, description = "`twitch` or `twitter`"),
+        ("account" = Option<String>, Query, description = "the account, optional if only one is connected"),
+    ),
+    responses(
+        (status = 204, description = "the account was disconnected"),
+        (status = 404, description = "unknown service", body = ErrorResponse),
+        (status = 409, description = "the account isn't connected", body = ErrorResponse),
+        (status = 502, description = "the service didn't revoke the token", body = ErrorResponse),
+    ),
+    security(("api_key" = [])),
+)]
+#[delete("/auth?<service>&<account>")]
+async fn disconnect_account(
+    api_key: ApiKey<ManageAccounts>,
+    providers: &State<Providers>,
+    audit: &State<AuditLog>,
+    service: &str,
+    account: Option<&str>,
+) -> Result<status::Custom<()>, Error> {
+    let provider = providers.get_or_err(service)?;
+    let account = provider.select_account(account).await?;
+    let result = provider.revoke(&account).await;
+    audit.record(api_key.actor(), "disconnect_account", Some(service), Some(&account), &result).await;
+    result?;
+    tracing::info!(service, %account, api_key = %api_key.info.id, "disconnected account");
+
+    Ok(status::Custom(Status::NoContent, ()))
 }
 
-#[get("/twitch/login_to_id?<login>")]
+/// the id of a twitch channel
+#[utoipa::path(
+    get,
+    path = "/api/v1/twitch/login_to_id",
+    tag = "twitch",
+    params(
+        ("login" = String, Query, description = "login name of the channel"),
+        ("account" = Option<String>, Query, description = "twitch account whose token is used, defaults to the channel itself if it is connected"),
+    ),
+    responses(
+        (status = 200, description = "the id of the channel", body = StringResponse),
+        (status = 400, description = "the channel doesn't exist", body = ErrorResponse),
+        (status = 409, description = "no twitch account is connected", body = ErrorResponse),
+        (status = 502, description = "twitch failed", body = ErrorResponse),
+    ),
+    security(("api_key" = [])),
+)]
+#[get("/twitch/login_to_id?<login>&<account>")]
 async fn twitch_game_to_id(
-    _api_key: ApiKey<'_>,
-    twitch: &State<Twitch>,
+    _api_key: ApiKey<ReadAuth>,
+    twitch: &State<Arc<Twitch>>,
     login: &str,
+    account: Option<&str>,
 ) -> Result<Json<GenericApiResponse<String>>, Error> {
-    let res = twitch.get_channel_id_from_string(login).await?;
+    let account = twitch.select_account_for(account, login).await?;
+    let res = twitch.get_channel_id_from_string(&account, login).await?;
 
     Ok(Json(GenericApiResponse { data: res }))
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-struct CheckAvailResponse {
-    pub twitter: bool,
-    pub twitch: bool,
+/// which services have connected accounts
+#[utoipa::path(
+    get,
+    path = "/api/v1/avail",
+    tag = "accounts",
+    responses((status = 200, description = "the connected accounts", body = CheckAvailResponse)),
+    security(("api_key" = [])),
+)]
+#[get("/avail")]
+async fn check_avail(_api_key: ApiKey<ReadAuth>, providers: &State<Providers>) -> Json<CheckAvailResponse> {
+    let mut services = HashMap::new();
+    let mut accounts = HashMap::new();
+    for provider in providers.iter() {
+        let provider_accounts = provider.accounts().await;
+        services.insert(provider.name().to_string(), !provider_accounts.is_empty());
+        accounts.insert(provider.name().to_string(), provider_accounts);
+    }
+
+    Json(CheckAvailResponse { services, accounts })
 }
 
-#[get("/avail")]
-async fn check_avail(_api_key: ApiKey<'_>) -> Json<CheckAvailResponse> {
-    Json(CheckAvailResponse {
-        twitter: templates::is_twitter_avail(),
-        twitch: templates::is_twitch_avail(),
-    })
+/// checks every connected token with its service
+#[utoipa::path(
+    get,
+    path = "/api/v1/status",
+    tag = "accounts",
+    responses((status = 200, description = "the tokens of every service", body = StatusResponse)),
+    security(("api_key" = [])),
+)]
+#[get("/status")]
+async fn get_status(
+    _api_key: ApiKey<ReadAuth>,
+    providers: &State<Providers>,
+    metrics: &State<Arc<Metrics>>,
+) -> Json<GenericApiResponse<HashMap<String, ServiceStatus>>> {
+    let mut services = HashMap::new();
+    for provider in providers.iter() {
+        let mut accounts = vec![];
+        for account in provider.accounts().await {
+            accounts.push(provider.check(&account).await);
+        }
+        services.insert(
+            provider.name().to_string(),
+            ServiceStatus {
+                accounts,
+                last_success: metrics.upstream_last_success(provider.name()),
+            },
+        );
+    }
+
+    Json(GenericApiResponse { data: services })
 }
 
 #[catch(400)]
 fn bad_request(_req: &Request) -> Error {
-    Error::new_bad_request("malformed or unauthorized request".to_string())
+    Error::new_bad_request("malformed request".to_string())
+}
+
+/// rocket answers bodies it can't parse with 422, for clients that's just another invalid request
+#[catch(422)]
+fn unprocessable_entity(_req: &Request) -> Error {
+    Error::new_bad_request("the request body is invalid".to_string())
+}
+
+#[catch(401)]
+fn unauthorized(req: &Request) -> Error {
+    match req.headers().get_one("Authorization") {
+        Some(_) => Error::new_unauthorized("invalid api key".to_string()),
+        None => Error::new_unauthorized("missing api key, send it in the Authorization header".to_string()),
+    }
+}
+
+#[catch(403)]
+fn forbidden(req: &Request) -> Error {
+    match req.local_cache(|| MissingScope(None)).0 {
+        Some(scope) => Error::new_forbidden(format!("api key is missing the {} scope", scope.name())),
+        None => Error::new_forbidden("forbidden".to_string()),
+    }
+}
+
+#[catch(429)]
+fn too_many_requests(req: &Request) -> Error {
+    let retry_after = req.local_cache(|| RetryAfter(Duration::from_secs(1))).0;
+    Error::new_too_many_requests("api key exceeded its rate limit".to_string(), retry_after)
 }
 
 #[catch(404)]
@@ -70,83 +215,269 @@ fn not_found(_req: &Request) -> Error {
     Error::new_not_found("the requested resource does not exist".to_string())
 }
 
-#[derive(Deserialize)]
-struct PostTweetRequest<'r> {
-    body: &'r str,
-}
-
+/// tweets `body`, needs the `tweet` scope
+#[utoipa::path(
+    post,
+    path = "/api/v1/tweet",
+    tag = "twitter",
+    request_body = PostTweetRequest,
+    responses(
+        (status = 204, description = "the tweet was posted"),
+        (status = 400, description = "multiple accounts are connected and none was given", body = ErrorResponse),
+        (status = 409, description = "the account isn't connected", body = ErrorResponse),
+        (status = 502, description = "twitter rejected the tweet, e.g. because it is a duplicate", body = ErrorResponse),
+    ),
+    security(("api_key" = [])),
+)]
 #[post("/tweet", data = "<tweet_body>")]
 async fn post_tweet(
-    _api_key: ApiKey<'_>,
-    tweet_body: Json<PostTweetRequest<'_>>,
-    twitter: &State<Twitter>,
+    api_key: ApiKey<Tweet>,
+    tweet_body: Json<PostTweetRequest>,
+    twitter: &State<Arc<Twitter>>,
+    audit: &State<AuditLog>,
 ) -> Result<status::Custom<()>, Error> {
-    if let Some(ref token) = *twitter.auth_token.lock().await {
-        egg_mode::tweet::DraftTweet::new(tweet_body.body.to_string())
-            .send(token)
-            .await?;
-        return Ok(status::Custom(Status::NoContent, ()));
+    let account = twitter.select_account(tweet_body.account.as_deref()).await?;
+    let result = twitter.tweet(&account, &tweet_body.body).await;
+    audit.record(api_key.actor(), "tweet", Some("twitter"), Some(&account), &result).await;
+    result?;
+
+    Ok(status::Custom(Status::NoContent, ()))
+}
+
+/// sets the title and game of a channel, needs the `twitch_update` scope
+#[utoipa::path(
+    post,
+    path = "/api/v1/twitch/update",
+    tag = "twitch",
+    request_body = TwitchUpdateRequest,
+    responses(
+        (status = 204, description = "the channel was updated"),
+        (status = 400, description = "the channel or game doesn't exist", body = ErrorResponse),
+        (status = 403, description = "the api key or the connected account is missing the channel:manage:broadcast scope", body = ErrorResponse),
+        (status = 409, description = "the account isn't connected", body = ErrorResponse),
+        (status = 502, description = "twitch failed", body = ErrorResponse),
+    ),
+    security(("api_key" = [])),
+)]
+#[post("/twitch/update", data = "<twitch_data>")]
+async fn twitch_update(api_key: ApiKey<TwitchUpdate>, twitch_data: Json<TwitchUpdateRequest>, twitch: &State<Arc<Twitch>>, audit: &State<AuditLog>) -> Result<status::Custom<()>, Error> {
+    let account = twitch.select_account_for(twitch_data.account.as_deref(), &twitch_data.login).await?;
+    let result = async {
+        let channel_id = twitch.get_channel_id_from_string(&account, &twitch_data.login).await?;
+        let game_id = twitch.get_game_id_from_string(&account, &twitch_data.game).await?;
+        twitch.update_channel(&account, &channel_id, &game_id, &twitch_data.title).await
+    }
+    .await;
+    audit.record(api_key.actor(), "update_channel", Some("twitch"), Some(&twitch_data.login), &result).await;
+    result?;
+
+    Ok(status::Custom(Status::NoContent, ()))
+}
+
+/// runs a commercial on a channel, needs the `twitch_commercial` scope
+#[utoipa::path(
+    post,
+    path = "/api/v1/twitch/commercial",
+    tag = "twitch",
+    params(
+        ("login" = String, Query, description = "login name of the channel"),
+        ("length" = u16, Query, description = "seconds, twitch allows 30 to 180"),
+        ("account" = Option<String>, Query, description = "twitch account whose token is used, defaults to the channel itself if it is connected"),
+    ),
+    responses(
+        (status = 200, description = "the commercial started", body = TwitchAdJson),
+        (status = 400, description = "the channel doesn't exist or the length is invalid", body = ErrorResponse),
+        (status = 403, description = "the api key or the connected account is missing the channel:edit:commercial scope", body = ErrorResponse),
+        (status = 409, description = "the account isn't connected", body = ErrorResponse),
+        (status = 502, description = "twitch failed, e.g. because the channel isn't live", body = ErrorResponse),
+    ),
+    security(("api_key" = [])),
+)]
+#[post("/twitch/commercial?<login>&<length>&<account>")]
+async fn twitch_commercial(api_key: ApiKey<TwitchCommercial>, twitch: &State<Arc<Twitch>>, audit: &State<AuditLog>, login: &str, length: u16, account: Option<&str>) -> Result<Json<TwitchAdJson>, Error> {
+    let account = twitch.select_account_for(account, login).await?;
+    let result = async {
+        let channel_id = twitch.get_channel_id_from_string(&account, login).await?;
+        twitch.run_commercial(&account, channel_id, length).await
     }
+    .await;
+    audit.record(api_key.actor(), "run_commercial", Some("twitch"), Some(login), &result).await;
 
-    Err(Error::new_auth_not_avail("twitter"))
+    Ok(Json(result?))
 }
 
-#[derive(Deserialize)]
-struct TwitchUpdateRequest<'r> {
-    game: &'r str,
-    title: &'r str,
-    login: &'r str,
+/// entries of the audit log, newest first, needs the `read_audit` scope
+#[utoipa::path(
+    get,
+    path = "/api/v1/audit",
+    tag = "audit",
+    params(
+        ("actor" = Option<String>, Query, description = "username or api key id"),
+        ("action" = Option<String>, Query, description = "e.g. `tweet` or `connect_account`"),
+        ("service" = Option<String>, Query, description = "`twitch` or `twitter`"),
+        ("target" = Option<String>, Query, description = "the account, api key or user the action was done to"),
+        ("since" = Option<u64>, Query, description = "unix timestamp, inclusive"),
+        ("until" = Option<u64>, Query, description = "unix timestamp, inclusive"),
+        ("limit" = Option<usize>, Query, description = "at most 1000, 100 if not set"),
+    ),
+    responses((status = 200, description = "the matching entries", body = AuditResponse)),
+    security(("api_key" = [])),
+)]
+#[get("/audit?<filter..>")]
+async fn get_audit(
+    _api_key: ApiKey<ReadAudit>,
+    audit: &State<AuditLog>,
+    filter: AuditFilter,
+) -> Result<Json<GenericApiResponse<Vec<AuditEntry>>>, Error> {
+    Ok(Json(GenericApiResponse {
+        data: audit.query(&filter).await?,
+    }))
 }
 
-#[post("/twitch/update", data = "<twitch_data>")]
-async fn twitch_update(_api_key: ApiKey<'_>, twitch_data: Json<TwitchUpdateRequest<'_>>, twitch: &State<Twitch>) -> Result<status::Custom<()>, Error> {
-    let channel_id = twitch.get_channel_id_from_string(twitch_data.login).await?;
-    let game_id = twitch.get_game_id_from_string(twitch_data.game).await?;
-    twitch.update_channel(&channel_id, &game_id, twitch_data.title).await?;
+/// the outcome of the last refresh of a twitch token
+#[utoipa::path(
+    get,
+    path = "/api/v1/twitch/refresh_status",
+    tag = "twitch",
+    params(("account" = Option<String>, Query, description = "the account, optional if only one is connected")),
+    responses(
+        (status = 200, description = "the last refresh, null if the token wasn't refreshed yet", body = RefreshStatusResponse),
+        (status = 409, description = "the account isn't connected", body = ErrorResponse),
+    ),
+    security(("api_key" = [])),
+)]
+#[get("/twitch/refresh_status?<account>")]
+async fn twitch_refresh_status(
+    _api_key: ApiKey<ReadAuth>,
+    twitch: &State<Arc<Twitch>>,
+    account: Option<&str>,
+) -> Result<Json<GenericApiResponse<Option<RefreshStatus>>>, Error> {
+    let account = twitch.select_account(account).await?;
+    Ok(Json(GenericApiResponse {
+        data: twitch.refresh_status(&account).await,
+    }))
+}
 
-    Ok(status::Custom(Status::NoContent, ()))
+/// the scope a route needs, checked by the `ApiKey` guard
+trait RequiredScope: Send + Sync {
+    const SCOPE: Scope;
 }
 
-#[post("/twitch/commercial?<login>&<length>")]
-async fn twitch_commercial(_api_key: ApiKey<'_>, twitch: &State<Twitch>, login: &str, length: u16) -> Result<Json<TwitchAdJson>, Error> {
-    let channel_id = twitch.get_channel_id_from_string(login).await?;
-    let res = twitch.run_commercial(channel_id, length).await?;
+struct ReadAuth;
+struct Tweet;
+struct TwitchUpdate;
+struct TwitchCommercial;
+struct ManageAccounts;
+struct ReadAudit;
 
-    Ok(Json(res))
+impl RequiredScope for ReadAuth {
+    const SCOPE: Scope = Scope::ReadAuth;
 }
 
-struct ApiKey<'r>(&'r str);
+impl RequiredScope for Tweet {
+    const SCOPE: Scope = Scope::Tweet;
+}
+
+impl RequiredScope for TwitchUpdate {
+    const SCOPE: Scope = Scope::TwitchUpdate;
+}
+
+impl RequiredScope for TwitchCommercial {
+    const SCOPE: Scope = Scope::TwitchCommercial;
+}
+
+impl RequiredScope for ManageAccounts {
+    const SCOPE: Scope = Scope::ManageAccounts;
+}
+
+impl RequiredScope for ReadAudit {
+    const SCOPE: Scope = Scope::ReadAudit;
+}
+
+/// a valid api key that has the scope `S`
+struct ApiKey<S: RequiredScope> {
+    info: ApiKeyInfo,
+    scope: PhantomData<S>,
+}
+
+impl<S: RequiredScope> ApiKey<S> {
+    /// the key as the actor of the audit log
+    fn actor(&self) -> Actor {
+        Actor::api_key(&self.info.id, &self.info.name)
+    }
+}
 
 #[derive(Debug)]
 enum ApiKeyError {
     Missing,
     Invalid,
+    MissingScope,
+    RateLimited,
 }
 
+/// the scope the request was missing, for the 403 catcher
+struct MissingScope(Option<Scope>);
+
+/// how long a rate limited key has to wait, for the 429 catcher
+struct RetryAfter(Duration);
+
 #[rocket::async_trait]
-impl<'r> FromRequest<'r> for ApiKey<'r> {
+impl<'r, S: RequiredScope> FromRequest<'r> for ApiKey<S> {
     type Error = ApiKeyError;
     async fn from_request(req: &'r Request<'_>) -> Outcome<Self, Self::Error> {
-        let api_key = req
+        let api_keys = req
+            .rocket()
+            .state::<ApiKeys>()
+            .expect("ApiKeys not managed");
+        let key = match req.headers().get_one("Authorization") {
+            Some(key) => key,
+            None => return Outcome::Failure((Status::Unauthorized, ApiKeyError::Missing)),
+        };
+
+        let info = match api_keys.authenticate(key).await {
+            Some(info) => info,
+            None => return Outcome::Failure((Status::Unauthorized, ApiKeyError::Invalid)),
+        };
+
+        let rate_limiter = req
             .rocket()
-            .state::<crate::templates::Sessions>()
-            .expect("Sessions not managed")
-            .api_key();
-        match req.headers().get_one("Authorization") {
-            None => Outcome::Failure((Status::BadRequest, ApiKeyError::Missing)),
-            Some(key) if key == api_key => Outcome::Success(ApiKey(key)),
-            Some(_) => Outcome::Failure((Status::BadRequest, ApiKeyError::Invalid)),
+            .state::<RateLimiter>()
+            .expect("RateLimiter not managed");
+        if let Err(retry_after) = rate_limiter.check(&info.id, info.rate_limit).await {
+            req.local_cache(|| RetryAfter(retry_after));
+            return Outcome::Failure((Status::TooManyRequests, ApiKeyError::RateLimited));
+        }
+
+        if !info.scopes.contains(&S::SCOPE) {
+            req.local_cache(|| MissingScope(Some(S::SCOPE)));
+            return Outcome::Failure((Status::Forbidden, ApiKeyError::MissingScope));
         }
+
+        Outcome::Success(ApiKey {
+            info,
+            scope: PhantomData,
+        })
     }
 }
 
 pub fn stage() -> rocket::fairing::AdHoc {
-    rocket::fairing::AdHoc::on_ignite("twitch", |rocket| async {
+    rocket::fairing::AdHoc::on_ignite("twitch", |mut rocket| async move {
+        // a service that isn't enabled isn't managed, its routes would keep rocket from launching
+        if rocket.state::<Arc<Twitch>>().is_some() {
+            rocket = rocket.mount(
+                "/api/v1",
+                logging::traced(routes![twitch_game_to_id, twitch_update, twitch_commercial, twitch_refresh_status]),
+            );
+        }
+        if rocket.state::<Arc<Twitter>>().is_some() {
+            rocket = rocket.mount("/api/v1", logging::traced(routes![post_tweet]));
+        }
+
         rocket
             .mount(
                 "/api/v1",
-                routes![get_twitch_info, check_avail, post_tweet, twitch_game_to_id, twitch_update, twitch_commercial],
+                logging::traced(routes![get_twitch_info, disconnect_account, check_avail, get_audit, get_status]),
             )
-            .register("/api/v1", catchers![bad_request, not_found])
+            .register("/api/v1", catchers![bad_request, unauthorized, forbidden, not_found, unprocessable_entity, too_many_requests])
     })
 }
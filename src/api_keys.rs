@@ -0,0 +1,271 @@
+use crate::error::Error;
+use crate::store::TokenStore;
+use crate::templates::gen_random_string;
+use rocket::serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::sync::Mutex;
+
+const STORE_PREFIX: &str = "api_key.";
+const ID_LEN: usize = 8;
+const SECRET_LEN: usize = 32;
+/// last used timestamps are only written to the store this often so not every request causes a write
+const LAST_USED_SAVE_INTERVAL: u64 = 60;
+
+/// What an api key is allowed to do.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, FromFormField)]
+#[serde(rename_all = "snake_case")]
+pub enum Scope {
+    #[field(value = "read_auth")]
+    ReadAuth,
+    #[field(value = "tweet")]
+    Tweet,
+    #[field(value = "twitch_update")]
+    TwitchUpdate,
+    #[field(value = "twitch_commercial")]
+    TwitchCommercial,
+    #[field(value = "manage_accounts")]
+    ManageAccounts,
+    #[field(value = "read_audit")]
+    ReadAudit,
+}
+
+impl Scope {
+    pub const ALL: [Scope; 6] = [
+        Scope::ReadAuth,
+        Scope::Tweet,
+        Scope::TwitchUpdate,
+        Scope::TwitchCommercial,
+        Scope::ManageAccounts,
+        Scope::ReadAudit,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Scope::ReadAuth => "read_auth",
+            Scope::Tweet => "tweet",
+            Scope::TwitchUpdate => "twitch_update",
+            Scope::TwitchCommercial => "twitch_commercial",
+            Scope::ManageAccounts => "manage_accounts",
+            Scope::ReadAudit => "read_audit",
+        }
+    }
+
+    pub fn description(&self) -> &'static str {
+        match self {
+            Scope::ReadAuth => "Read tokens and connected accounts",
+            Scope::Tweet => "Post tweets",
+            Scope::TwitchUpdate => "Update twitch title and game",
+            Scope::TwitchCommercial => "Run twitch commercials",
+            Scope::ManageAccounts => "Disconnect accounts",
+            Scope::ReadAudit => "Read the audit log",
+        }
+    }
+}
+
+/// An api key as it is shown on the dashboard, the key itself is only known when it is created.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ApiKeyInfo {
+    pub id: String,
+    pub name: String,
+    pub scopes: Vec<Scope>,
+    pub created_at: u64,
+    pub last_used: Option<u64>,
+    /// requests per minute, the configured default is used if this isn't set
+    #[serde(default)]
+    pub rate_limit: Option<u32>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct StoredApiKey {
+    #[serde(flatten)]
+    info: ApiKeyInfo,
+    /// sha256 of the secret part of the key
+    hash: String,
+}
+
+pub(crate) fn hash_secret(secret: &str) -> String {
+    Sha256::digest(secret.as_bytes())
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("system time is before the unix epoch")
+        .as_secs()
+}
+
+/// the last use is only saved once a minute, a newer one that wasn't saved yet is kept
+fn keep_last_use(stored: &mut StoredApiKey, cached: Option<&StoredApiKey>) {
+    if let Some(cached) = cached {
+        stored.info.last_used = stored.info.last_used.max(cached.info.last_used);
+    }
+}
+
+/// The api keys clients use for `/api/v1`, kept in the token store so they survive restarts.
+///
+/// A key looks like `{id}.{secret}`, only a hash of the secret is stored. Every lookup
+/// checks the store since `social_auth-admin` can create and revoke keys while the server runs.
+pub struct ApiKeys {
+    store: Arc<dyn TokenStore>,
+    keys: Mutex<HashMap<String, StoredApiKey>>,
+}
+
+impl ApiKeys {
+    pub fn new(store: Arc<dyn TokenStore>) -> Self {
+        ApiKeys {
+            store,
+            keys: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// reads every key from the store
+    pub async fn load(&self) -> Result<(), Error> {
+        let mut stored_keys = HashMap::new();
+        for key in self.store.keys().await? {
+            if let Some(id) = key.strip_prefix(STORE_PREFIX) {
+                if let Some(bytes) = self.store.load(&key).await? {
+                    stored_keys.insert(id.to_string(), serde_json::from_slice(&bytes)?);
+                }
+            }
+        }
+
+        let mut keys = self.keys.lock().await;
+        for (id, stored) in stored_keys.iter_mut() {
+            keep_last_use(stored, keys.get(id));
+        }
+        *keys = stored_keys;
+        Ok(())
+    }
+
+    /// reads the key `id` from the store, it is forgotten if it was revoked
+    async fn reload(&self, id: &str) -> Result<(), Error> {
+        let stored: Option<StoredApiKey> = match self.store.load(&format!("{}{}", STORE_PREFIX, id)).await? {
+            Some(bytes) => Some(serde_json::from_slice(&bytes)?),
+            None => None,
+        };
+
+        let mut keys = self.keys.lock().await;
+        match stored {
+            Some(mut stored) => {
+                keep_last_use(&mut stored, keys.get(id));
+                keys.insert(id.to_string(), stored);
+            }
+            None => {
+                keys.remove(id);
+            }
+        }
+        Ok(())
+    }
+
+    async fn save(&self, stored: &StoredApiKey) -> Result<(), Error> {
+        self.store
+            .save(
+                &format!("{}{}", STORE_PREFIX, stored.info.id),
+                &serde_json::to_vec(stored)?,
+            )
+            .await
+    }
+
+    /// creates a new key and returns it together with its info, this is the only time the key is known
+    pub async fn create(
+        &self,
+        name: &str,
+        scopes: Vec<Scope>,
+        rate_limit: Option<u32>,
+    ) -> Result<(ApiKeyInfo, String), Error> {
+        let name = name.trim();
+        if name.is_empty() || scopes.is_empty() {
+            return Err(Error::new_bad_request(
+                "an api key needs a name and at least one scope".to_string(),
+            ));
+        }
+
+        let mut keys = self.keys.lock().await;
+        let id = loop {
+            let id = gen_random_string(ID_LEN).to_lowercase();
+            if !keys.contains_key(&id) {
+                break id;
+            }
+        };
+        let secret = gen_random_string(SECRET_LEN);
+        let stored = StoredApiKey {
+            info: ApiKeyInfo {
+                id: id.clone(),
+                name: name.to_string(),
+                scopes,
+                created_at: now(),
+                last_used: None,
+                rate_limit,
+            },
+            hash: hash_secret(&secret),
+        };
+
+        self.save(&stored).await?;
+        let info = stored.info.clone();
+        keys.insert(id.clone(), stored);
+        Ok((info, format!("{}.{}", id, secret)))
+    }
+
+    pub async fn revoke(&self, id: &str) -> Result<(), Error> {
+        self.reload(id).await?;
+        if self.keys.lock().await.remove(id).is_none() {
+            return Err(Error::new_not_found(format!("api key {} does not exist", id)));
+        }
+
+        self.store.delete(&format!("{}{}", STORE_PREFIX, id)).await
+    }
+
+    /// all keys, oldest first
+    pub async fn list(&self) -> Vec<ApiKeyInfo> {
+        if let Err(e) = self.load().await {
+            tracing::warn!(error = ?e, "unable to read the api keys, showing the ones read before");
+        }
+
+        let mut keys: Vec<ApiKeyInfo> = self
+            .keys
+            .lock()
+            .await
+            .values()
+            .map(|stored| stored.info.clone())
+            .collect();
+        keys.sort_by_key(|info| info.created_at);
+        keys
+    }
+
+    /// checks `key` and records that it was used, `None` if the key doesn't exist
+    pub async fn authenticate(&self, key: &str) -> Option<ApiKeyInfo> {
+        let (id, secret) = key.split_once('.')?;
+        // the id becomes part of a store key
+        if id.len() != ID_LEN || !id.chars().all(|c| c.is_ascii_alphanumeric()) {
+            return None;
+        }
+        if let Err(e) = self.reload(id).await {
+            tracing::warn!(api_key = %id, error = ?e, "unable to read api key, using the one read before");
+        }
+        let mut keys = self.keys.lock().await;
+        let stored = keys.get_mut(id)?;
+        if stored.hash != hash_secret(secret) {
+            return None;
+        }
+
+        let now = now();
+        let save = match stored.info.last_used {
+            Some(last_used) => last_used + LAST_USED_SAVE_INTERVAL <= now,
+            None => true,
+        };
+        stored.info.last_used = Some(now);
+        if save {
+            if let Err(e) = self.save(stored).await {
+                tracing::warn!(api_key = %id, error = ?e, "unable to save last use of api key");
+            }
+        }
+
+        Some(stored.info.clone())
+    }
+}
@@ -0,0 +1,115 @@
+use crate::error::Error;
+use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::fs::{File, OpenOptions};
+use tokio::io::AsyncWriteExt;
+use tokio::sync::Mutex;
+
+pub use social_auth_types::{Actor, AuditEntry, Outcome};
+
+const DEFAULT_LIMIT: usize = 100;
+const MAX_LIMIT: usize = 1000;
+
+/// What to look for in the audit log, every field that is set has to match.
+#[derive(Debug, Default, FromForm)]
+pub struct AuditFilter {
+    /// username or api key id
+    pub actor: Option<String>,
+    pub action: Option<String>,
+    pub service: Option<String>,
+    pub target: Option<String>,
+    /// unix timestamps, both inclusive
+    pub since: Option<u64>,
+    pub until: Option<u64>,
+    /// at most 1000, 100 if not set
+    pub limit: Option<usize>,
+}
+
+impl AuditFilter {
+    fn matches(&self, entry: &AuditEntry) -> bool {
+        fn matches_field(filter: &Option<String>, value: Option<&str>) -> bool {
+            match filter {
+                Some(filter) => Some(filter.as_str()) == value,
+                None => true,
+            }
+        }
+
+        matches_field(&self.actor, Some(entry.actor.id()))
+            && matches_field(&self.action, Some(&entry.action))
+            && matches_field(&self.service, entry.service.as_deref())
+            && matches_field(&self.target, entry.target.as_deref())
+            && entry.timestamp >= self.since.unwrap_or(0)
+            && entry.timestamp <= self.until.unwrap_or(u64::MAX)
+    }
+}
+
+/// Append-only log of privileged actions, one json object per line.
+pub struct AuditLog {
+    path: PathBuf,
+    file: Mutex<File>,
+}
+
+impl AuditLog {
+    pub async fn open<P: Into<PathBuf>>(path: P) -> Result<Self, Error> {
+        let path = path.into();
+        let file = OpenOptions::new().create(true).append(true).open(&path).await?;
+        Ok(AuditLog {
+            path,
+            file: Mutex::new(file),
+        })
+    }
+
+    /// appends an entry, failing to do so doesn't fail the action that is recorded
+    pub async fn record<T>(
+        &self,
+        actor: Actor,
+        action: &str,
+        service: Option<&str>,
+        target: Option<&str>,
+        result: &Result<T, Error>,
+    ) {
+        let entry = AuditEntry {
+            timestamp: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .expect("system time is before the unix epoch")
+                .as_secs(),
+            actor,
+            action: action.to_string(),
+            service: service.map(String::from),
+            target: target.map(String::from),
+            outcome: match result {
+                Ok(_) => Outcome::Success,
+                Err(e) => Outcome::Failure { message: e.message() },
+            },
+        };
+
+        if let Err(e) = self.append(&entry).await {
+            tracing::error!(?entry, error = ?e, "unable to write audit log entry");
+        }
+    }
+
+    async fn append(&self, entry: &AuditEntry) -> Result<(), Error> {
+        let mut line = serde_json::to_vec(entry)?;
+        line.push(b'\n');
+
+        let mut file = self.file.lock().await;
+        file.write_all(&line).await?;
+        file.flush().await?;
+        Ok(())
+    }
+
+    /// the entries matching `filter`, newest first
+    pub async fn query(&self, filter: &AuditFilter) -> Result<Vec<AuditEntry>, Error> {
+        let limit = filter.limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT);
+        let content = tokio::fs::read_to_string(&self.path).await?;
+
+        Ok(content
+            .lines()
+            .rev()
+            // a line that is still being written doesn't parse and is skipped
+            .filter_map(|line| serde_json::from_str::<AuditEntry>(line).ok())
+            .filter(|entry| filter.matches(entry))
+            .take(limit)
+            .collect())
+    }
+}
@@ -0,0 +1,9 @@
+use std::env;
+use std::process;
+
+#[rocket::main]
+async fn main() {
+    social_auth::logging::init();
+    let args: Vec<String> = env::args().skip(1).collect();
+    process::exit(social_auth::admin::run(&args).await);
+}
@@ -0,0 +1,617 @@
+use crate::encryption::MasterKey;
+use crate::http::HttpConfig;
+use crate::{twitch_config, twitter_config, users};
+use rocket::figment::providers::{Format, Toml};
+use rocket::figment::value::{Dict, Map, Value};
+use rocket::config::SecretKey;
+use rocket::figment::{self, Figment, Metadata, Profile, Provider};
+use serde::Deserialize;
+use std::env;
+use std::fmt;
+use std::time::Duration;
+use url::Url;
+
+/// read from the working directory unless `SOCIAL_AUTH_CONFIG` points somewhere else
+const DEFAULT_PATH: &str = "social_auth.toml";
+
+const ENV_SOURCE: &str = "environment variable";
+
+/// how the value of an environment variable is read
+#[derive(Clone, Copy)]
+enum EnvKind {
+    /// as it is, so a secret of only digits stays a string
+    Text,
+    /// like a toml value, for numbers and booleans
+    Value,
+    /// comma separated strings
+    List,
+}
+
+/// environment variables that override a setting of the config file, named like before there was one
+const ENV_VARS: &[(&str, &str, EnvKind)] = &[
+    ("TWITCH_ENABLED", "twitch.enabled", EnvKind::Value),
+    ("TWITCH_CLIENT_ID", "twitch.client_id", EnvKind::Text),
+    ("TWITCH_CLIENT_SECRET", "twitch.client_secret", EnvKind::Text),
+    ("TWITCH_REDIRECT_URI", "twitch.redirect_uri", EnvKind::Text),
+    ("TWITCH_AUTH_BASE_URL", "twitch.auth_base_url", EnvKind::Text),
+    ("TWITCH_API_BASE_URL", "twitch.api_base_url", EnvKind::Text),
+    ("TWITTER_ENABLED", "twitter.enabled", EnvKind::Value),
+    ("TWITTER_API_KEY", "twitter.api_key", EnvKind::Text),
+    ("TWITTER_API_SECRET", "twitter.api_secret", EnvKind::Text),
+    ("TWITTER_CALLBACK_URL", "twitter.callback_url", EnvKind::Text),
+    ("TWITTER_API_BASE_URL", "twitter.api_base_url", EnvKind::Text),
+    ("AUTH_PASSWORD_HASH", "password_hash", EnvKind::Text),
+    ("AUTH_PASSWORD", "password", EnvKind::Text),
+    ("SESSION_IDLE_TIMEOUT", "session.idle_timeout", EnvKind::Value),
+    ("SESSION_ABSOLUTE_TIMEOUT", "session.absolute_timeout", EnvKind::Value),
+    ("SESSION_COOKIE_SECURE", "session.cookie_secure", EnvKind::Value),
+    ("LOGIN_ATTEMPTS", "login_attempts", EnvKind::Value),
+    ("LOGIN_GLOBAL_ATTEMPTS", "login_global_attempts", EnvKind::Value),
+    ("API_RATE_LIMIT", "api_rate_limit", EnvKind::Value),
+    ("AUDIT_LOG_PATH", "audit_log_path", EnvKind::Text),
+    ("HTTP_TIMEOUT", "http.timeout", EnvKind::Value),
+    ("HTTP_CONNECT_TIMEOUT", "http.connect_timeout", EnvKind::Value),
+    ("HTTP_POOL_IDLE_TIMEOUT", "http.pool_idle_timeout", EnvKind::Value),
+    ("HTTP_POOL_MAX_IDLE", "http.pool_max_idle", EnvKind::Value),
+    ("HTTP_USER_AGENT", "http.user_agent", EnvKind::Text),
+    ("HTTP_UPSTREAM_PROXY", "http.upstream_proxy", EnvKind::Text),
+    ("HTTP_MAX_RETRIES", "http.max_retries", EnvKind::Value),
+    ("HTTP_RETRY_BASE_DELAY_MS", "http.retry_base_delay_ms", EnvKind::Value),
+    ("TOKEN_STORE", "token_store.kind", EnvKind::Text),
+    ("TOKEN_STORE_PATH", "token_store.path", EnvKind::Text),
+    ("TOKEN_ENCRYPTION_KEY", "token_store.encryption_key", EnvKind::Text),
+    ("TOKEN_ENCRYPTION_KEY_PREVIOUS", "token_store.encryption_key_previous", EnvKind::List),
+];
+
+/// the environment variable that overrides `key`, if there is one
+fn env_var(key: &str) -> Option<&'static str> {
+    ENV_VARS.iter().find(|(_, k, _)| *k == key).map(|(name, _, _)| *name)
+}
+
+/// The settings of `ENV_VARS` that are set, errors name the variable instead of the key.
+pub struct EnvOverrides;
+
+impl Provider for EnvOverrides {
+    fn metadata(&self) -> Metadata {
+        Metadata::named(ENV_SOURCE).interpolater(|_, keys| {
+            let key = keys.join(".");
+            env_var(&key).map(String::from).unwrap_or(key)
+        })
+    }
+
+    fn data(&self) -> Result<Map<Profile, Dict>, figment::Error> {
+        let mut dict = Dict::new();
+        for (name, key, kind) in ENV_VARS {
+            let value = match env::var(name) {
+                Ok(value) => value,
+                Err(_) => continue,
+            };
+            let value = match kind {
+                EnvKind::Text => Value::from(value),
+                EnvKind::Value => value.parse().expect("parsing a value is infallible"),
+                EnvKind::List => Value::from(value.split(',').map(String::from).collect::<Vec<_>>()),
+            };
+
+            match key.split_once('.') {
+                Some((section, field)) => {
+                    let section = dict.entry(section.to_string()).or_insert_with(|| Value::from(Dict::new()));
+                    if let Value::Dict(_, section) = section {
+                        section.insert(field.to_string(), value);
+                    }
+                }
+                None => {
+                    dict.insert(key.to_string(), value);
+                }
+            }
+        }
+
+        Ok(Profile::Global.collect(dict))
+    }
+}
+
+/// Rocket's own configuration, then the config file and the environment variables on top.
+///
+/// The server is configured with this as well, so the config file can hold Rocket's settings too.
+pub fn figment() -> Figment {
+    let path = env::var("SOCIAL_AUTH_CONFIG").unwrap_or_else(|_| String::from(DEFAULT_PATH));
+    rocket::Config::figment()
+        .merge(Toml::file(path))
+        .merge(EnvOverrides)
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+struct TwitchSettings {
+    /// enabled if a client id or secret is set unless this says otherwise
+    enabled: Option<bool>,
+    client_id: Option<String>,
+    client_secret: Option<String>,
+    redirect_uri: String,
+    auth_base_url: String,
+    api_base_url: String,
+}
+
+impl Default for TwitchSettings {
+    fn default() -> Self {
+        TwitchSettings {
+            enabled: None,
+            client_id: None,
+            client_secret: None,
+            redirect_uri: String::from("http://localhost:8000/twitch/authorize/callback"),
+            auth_base_url: String::from(twitch_config::DEFAULT_AUTH_BASE_URL),
+            api_base_url: String::from(twitch_config::DEFAULT_API_BASE_URL),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+struct TwitterSettings {
+    /// enabled if an api key or secret is set unless this says otherwise
+    enabled: Option<bool>,
+    api_key: Option<String>,
+    api_secret: Option<String>,
+    callback_url: String,
+    api_base_url: String,
+}
+
+impl Default for TwitterSettings {
+    fn default() -> Self {
+        TwitterSettings {
+            enabled: None,
+            api_key: None,
+            api_secret: None,
+            callback_url: String::from("http://localhost:8000/twitter/authorize/callback"),
+            api_base_url: String::from(twitter_config::DEFAULT_API_BASE_URL),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+struct SessionSettings {
+    /// seconds
+    idle_timeout: u64,
+    absolute_timeout: u64,
+    cookie_secure: bool,
+}
+
+impl Default for SessionSettings {
+    fn default() -> Self {
+        SessionSettings {
+            idle_timeout: 60 * 60,
+            absolute_timeout: 12 * 60 * 60,
+            cookie_secure: true,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+struct HttpSettings {
+    /// seconds
+    timeout: u64,
+    connect_timeout: u64,
+    pool_idle_timeout: u64,
+    pool_max_idle: usize,
+    user_agent: String,
+    upstream_proxy: Option<String>,
+    max_retries: u32,
+    retry_base_delay_ms: u64,
+}
+
+impl Default for HttpSettings {
+    fn default() -> Self {
+        let default = HttpConfig::default();
+        HttpSettings {
+            timeout: default.timeout.as_secs(),
+            connect_timeout: default.connect_timeout.as_secs(),
+            pool_idle_timeout: default.pool_idle_timeout.as_secs(),
+            pool_max_idle: default.pool_max_idle_per_host,
+            user_agent: default.user_agent,
+            upstream_proxy: default.proxy,
+            max_retries: default.max_retries,
+            retry_base_delay_ms: default.retry_base_delay.as_millis() as u64,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+struct TokenStoreSettings {
+    /// `fs` or `sqlite`
+    kind: String,
+    path: Option<String>,
+    encryption_key: Option<String>,
+    encryption_key_previous: Vec<String>,
+}
+
+impl Default for TokenStoreSettings {
+    fn default() -> Self {
+        TokenStoreSettings {
+            kind: String::from("fs"),
+            path: None,
+            encryption_key: None,
+            encryption_key_previous: vec![],
+        }
+    }
+}
+
+/// What the config file and the environment variables contain, before it is checked.
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+struct Settings {
+    twitch: TwitchSettings,
+    twitter: TwitterSettings,
+    password_hash: Option<String>,
+    /// the plaintext password from before there were hashes
+    password: Option<String>,
+    session: SessionSettings,
+    login_attempts: u32,
+    login_global_attempts: u32,
+    api_rate_limit: u32,
+    audit_log_path: String,
+    http: HttpSettings,
+    token_store: TokenStoreSettings,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            twitch: TwitchSettings::default(),
+            twitter: TwitterSettings::default(),
+            password_hash: None,
+            password: None,
+            session: SessionSettings::default(),
+            login_attempts: 5,
+            login_global_attempts: 100,
+            api_rate_limit: 60,
+            audit_log_path: String::from("audit.log"),
+            http: HttpSettings::default(),
+            token_store: TokenStoreSettings::default(),
+        }
+    }
+}
+
+/// A setting that is missing or has a value that can't work.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ConfigProblem {
+    /// e.g. `twitch.client_secret`
+    pub key: String,
+    pub message: String,
+}
+
+impl fmt::Display for ConfigProblem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.key, self.message)
+    }
+}
+
+/// Everything that is wrong with the configuration, instead of only the first problem.
+#[derive(Debug)]
+pub struct InvalidConfig {
+    pub problems: Vec<ConfigProblem>,
+}
+
+impl InvalidConfig {
+    /// a problem found after the settings were checked, e.g. while opening the token store
+    pub fn new(key: &str, message: String) -> Self {
+        InvalidConfig {
+            problems: vec![ConfigProblem {
+                key: key.to_string(),
+                message,
+            }],
+        }
+    }
+
+    /// `key` isn't set but is needed, `reason` says what for
+    pub fn missing(key: &str, reason: &str) -> Self {
+        let message = match env_var(key) {
+            Some(name) => format!("is missing, {}, set it in the config file or with {}", reason, name),
+            None => format!("is missing, {}", reason),
+        };
+        Self::new(key, message)
+    }
+
+    fn from_figment(err: figment::Error) -> Self {
+        let problems = err
+            .into_iter()
+            .map(|e| {
+                // other sources would put the profile in front of the key
+                let key = match (&e.profile, &e.metadata) {
+                    (Some(profile), Some(metadata)) if metadata.name == ENV_SOURCE && !e.path.is_empty() => {
+                        metadata.interpolate(profile, &e.path)
+                    }
+                    _ => e.path.join("."),
+                };
+                let message = match e.metadata.as_ref().and_then(|metadata| metadata.source.as_ref()) {
+                    Some(source) => format!("{} in {}", e.kind, source),
+                    None => e.kind.to_string(),
+                };
+                ConfigProblem { key, message }
+            })
+            .collect();
+
+        InvalidConfig { problems }
+    }
+}
+
+impl fmt::Display for InvalidConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "the configuration is invalid:")?;
+        for problem in &self.problems {
+            write!(f, "\n  - {}", problem)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl std::error::Error for InvalidConfig {}
+
+/// collects the problems of the settings while they are turned into a `Config`
+#[derive(Default)]
+struct Checker {
+    problems: Vec<ConfigProblem>,
+}
+
+impl Checker {
+    fn problem(&mut self, key: &str, message: String) {
+        self.problems.push(ConfigProblem {
+            key: key.to_string(),
+            message,
+        });
+    }
+
+    fn required(&mut self, key: &str, value: Option<String>) -> Option<String> {
+        match value.filter(|value| !value.trim().is_empty()) {
+            Some(value) => Some(value),
+            None => {
+                let message = match env_var(key) {
+                    Some(name) => format!("is missing, set it in the config file or with {}", name),
+                    None => "is missing".to_string(),
+                };
+                self.problem(key, message);
+                None
+            }
+        }
+    }
+
+    fn url(&mut self, key: &str, url: &str) -> Option<Url> {
+        match Url::parse(url) {
+            Ok(url) if url.scheme() == "http" || url.scheme() == "https" => Some(url),
+            _ => {
+                self.problem(key, format!("{:?} isn't an http or https url", url));
+                None
+            }
+        }
+    }
+
+    /// the service redirects the user to `url` after authorizing, it has to reach the callback route
+    fn callback_url(&mut self, key: &str, url: &str, service: &str) {
+        let path = format!("/{}/authorize/callback", service);
+        if let Some(url) = self.url(key, url) {
+            if !url.path().ends_with(&path) {
+                self.problem(key, format!("has to point to {} of this server", path));
+            }
+        }
+    }
+
+    fn twitch(&mut self, settings: TwitchSettings) -> Option<TwitchConfig> {
+        let configured = settings.client_id.is_some() || settings.client_secret.is_some();
+        if !settings.enabled.unwrap_or(configured) {
+            return None;
+        }
+
+        let client_id = self.required("twitch.client_id", settings.client_id);
+        let client_secret = self.required("twitch.client_secret", settings.client_secret);
+        self.callback_url("twitch.redirect_uri", &settings.redirect_uri, "twitch");
+        self.url("twitch.auth_base_url", &settings.auth_base_url);
+        self.url("twitch.api_base_url", &settings.api_base_url);
+        Some(TwitchConfig {
+            client_id: client_id?,
+            client_secret: client_secret?,
+            redirect_uri: settings.redirect_uri,
+            auth_base_url: settings.auth_base_url,
+            api_base_url: settings.api_base_url,
+        })
+    }
+
+    fn twitter(&mut self, settings: TwitterSettings) -> Option<TwitterConfig> {
+        let configured = settings.api_key.is_some() || settings.api_secret.is_some();
+        if !settings.enabled.unwrap_or(configured) {
+            return None;
+        }
+
+        let api_key = self.required("twitter.api_key", settings.api_key);
+        let api_secret = self.required("twitter.api_secret", settings.api_secret);
+        self.callback_url("twitter.callback_url", &settings.callback_url, "twitter");
+        self.url("twitter.api_base_url", &settings.api_base_url);
+        Some(TwitterConfig {
+            api_key: api_key?,
+            api_secret: api_secret?,
+            callback_url: settings.callback_url,
+            api_base_url: settings.api_base_url,
+        })
+    }
+
+    fn password_hash(&mut self, hash: Option<String>, password: Option<String>) -> Option<String> {
+        if let Some(hash) = hash {
+            if let Err(e) = users::validate_hash(&hash) {
+                self.problem("password_hash", e.message());
+            }
+            return Some(hash);
+        }
+
+        // still accept the plaintext password so existing deployments keep working
+        let password = password?;
+        tracing::warn!("AUTH_PASSWORD is deprecated, run `social_auth hash-password` and set AUTH_PASSWORD_HASH instead");
+        match users::hash_password(&password) {
+            Ok(hash) => Some(hash),
+            Err(e) => {
+                self.problem("password", e.message());
+                None
+            }
+        }
+    }
+
+    fn http(&mut self, settings: HttpSettings) -> HttpConfig {
+        let proxy = settings.upstream_proxy.filter(|proxy| !proxy.is_empty());
+        if let Some(proxy) = &proxy {
+            if let Err(e) = reqwest::Proxy::all(proxy.as_str()) {
+                self.problem("http.upstream_proxy", e.to_string());
+            }
+        }
+        if reqwest::header::HeaderValue::from_str(&settings.user_agent).is_err() {
+            self.problem("http.user_agent", "can't be sent in a header".to_string());
+        }
+
+        HttpConfig {
+            timeout: Duration::from_secs(settings.timeout),
+            connect_timeout: Duration::from_secs(settings.connect_timeout),
+            pool_idle_timeout: Duration::from_secs(settings.pool_idle_timeout),
+            pool_max_idle_per_host: settings.pool_max_idle,
+            user_agent: settings.user_agent,
+            proxy,
+            max_retries: settings.max_retries,
+            retry_base_delay: Duration::from_millis(settings.retry_base_delay_ms),
+        }
+    }
+
+    /// rocket signs the session and private cookies with it, it refuses to start without one outside of debug
+    fn secret_key(&mut self, figment: &Figment) {
+        if figment.profile() == rocket::Config::DEBUG_PROFILE {
+            return;
+        }
+
+        let provided = match figment.extract_inner::<SecretKey>("secret_key") {
+            Ok(key) => key.is_provided(),
+            Err(e) if matches!(e.kind, figment::error::Kind::MissingField(_)) => false,
+            Err(e) => {
+                self.problem("secret_key", e.kind.to_string());
+                return;
+            }
+        };
+        if !provided {
+            self.problem(
+                "secret_key",
+                format!(
+                    "is missing or all zeros, it is required in the {} profile, generate one with `openssl rand -base64 32` and set it in the config file or with ROCKET_SECRET_KEY",
+                    figment.profile()
+                ),
+            );
+        }
+    }
+
+    fn token_store(&mut self, settings: &TokenStoreSettings) {
+        if settings.kind != "fs" && settings.kind != "sqlite" {
+            self.problem("token_store.kind", format!("has to be fs or sqlite, not {:?}", settings.kind));
+        }
+        if let Some(key) = &settings.encryption_key {
+            if let Err(e) = MasterKey::from_base64(key) {
+                self.problem("token_store.encryption_key", e.message());
+            }
+        }
+        for key in &settings.encryption_key_previous {
+            if let Err(e) = MasterKey::from_base64(key) {
+                self.problem("token_store.encryption_key_previous", e.message());
+            }
+        }
+    }
+}
+
+pub struct TwitchConfig {
+    pub client_id: String,
+    pub client_secret: String,
+    pub redirect_uri: String,
+    pub auth_base_url: String,
+    pub api_base_url: String,
+}
+
+pub struct TwitterConfig {
+    pub api_key: String,
+    pub api_secret: String,
+    pub callback_url: String,
+    pub api_base_url: String,
+}
+
+pub struct Config {
+    /// None if twitch isn't enabled
+    pub twitch: Option<TwitchConfig>,
+    /// None if twitter isn't enabled
+    pub twitter: Option<TwitterConfig>,
+    /// argon2 hash of the password of the first admin, only used while there are no users
+    pub password_hash: Option<String>,
+    pub session_idle_timeout: Duration,
+    pub session_absolute_timeout: Duration,
+    /// only send the session cookie over https
+    pub session_cookie_secure: bool,
+    /// failed logins per ip before it gets locked out
+    pub login_attempts: u32,
+    /// failed logins of all ips per minute before the login is locked
+    pub login_global_attempts: u32,
+    /// default requests per minute of an api key, 0 for no limit
+    pub api_rate_limit: u32,
+    /// file the audit log is appended to
+    pub audit_log_path: String,
+    /// clients for twitch and twitter
+    pub http: HttpConfig,
+    pub token_store: String,
+    pub token_store_path: Option<String>,
+    pub token_encryption_key: Option<String>,
+    pub token_encryption_key_previous: Vec<String>,
+}
+
+impl Config {
+    /// reads the config file and the environment, see `figment`
+    pub fn load() -> Result<Self, InvalidConfig> {
+        Self::from_figment(&figment())
+    }
+
+    /// checks every setting and reports all problems at once
+    pub fn from_figment(figment: &Figment) -> Result<Self, InvalidConfig> {
+        let settings: Settings = figment.extract().map_err(InvalidConfig::from_figment)?;
+        let mut checker = Checker::default();
+        let twitch = checker.twitch(settings.twitch);
+        let twitter = checker.twitter(settings.twitter);
+        let password_hash = checker.password_hash(settings.password_hash, settings.password);
+        let http = checker.http(settings.http);
+        checker.token_store(&settings.token_store);
+        checker.secret_key(figment);
+        if !checker.problems.is_empty() {
+            return Err(InvalidConfig {
+                problems: checker.problems,
+            });
+        }
+
+        Ok(Config {
+            twitch,
+            twitter,
+            password_hash,
+            session_idle_timeout: Duration::from_secs(settings.session.idle_timeout),
+            session_absolute_timeout: Duration::from_secs(settings.session.absolute_timeout),
+            session_cookie_secure: settings.session.cookie_secure,
+            login_attempts: settings.login_attempts,
+            login_global_attempts: settings.login_global_attempts,
+            api_rate_limit: settings.api_rate_limit,
+            audit_log_path: settings.audit_log_path,
+            http,
+            token_store: settings.token_store.kind,
+            token_store_path: settings.token_store.path,
+            token_encryption_key: settings.token_store.encryption_key,
+            token_encryption_key_previous: settings.token_store.encryption_key_previous,
+        })
+    }
+
+    /// the services that are enabled, for the startup log
+    pub fn enabled_services(&self) -> Vec<&'static str> {
+        let mut services = vec![];
+        if self.twitch.is_some() {
+            services.push("twitch");
+        }
+        if self.twitter.is_some() {
+            services.push("twitter");
+        }
+        services
+    }
+}
@@ -0,0 +1,202 @@
+use crate::error::Error;
+use crate::store::TokenStore;
+use aes_gcm::aead::{Aead, NewAead, Payload};
+use aes_gcm::{Aes256Gcm, Key, Nonce};
+use rand::{thread_rng, Rng};
+use rocket::serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+const NONCE_LEN: usize = 12;
+/// binds `data` to the key it is saved under, version 1 envelopes were sealed without it
+const ENCRYPTION_VERSION: u8 = 2;
+
+/// What actually ends up in the underlying store.
+///
+/// Every value is encrypted with its own random data key, which in turn is encrypted
+/// with the master key from `TOKEN_ENCRYPTION_KEY`. Rotating the master key therefore
+/// only needs to re-encrypt the data keys.
+///
+/// The store key is the associated data of `data`, so a value copied to another key
+/// fails to decrypt instead of handing out the token of a different account.
+#[derive(Debug, Serialize, Deserialize)]
+struct Envelope {
+    encryption_version: u8,
+    /// base64 of nonce + encrypted data key
+    data_key: String,
+    /// base64 of nonce + encrypted value
+    data: String,
+}
+
+/// A master key given to us as 32 base64 encoded bytes.
+pub struct MasterKey(Aes256Gcm);
+
+impl MasterKey {
+    pub fn from_base64(key: &str) -> Result<Self, Error> {
+        let bytes = base64::decode(key.trim())
+            .map_err(|e| Error::new_internal_server_error(format!("invalid encryption key: {}", e)))?;
+        if bytes.len() != 32 {
+            return Err(Error::new_internal_server_error(
+                "encryption key has to be 32 bytes".to_string(),
+            ));
+        }
+
+        Ok(MasterKey(Aes256Gcm::new(Key::from_slice(&bytes))))
+    }
+}
+
+fn seal(cipher: &Aes256Gcm, msg: &[u8], aad: &[u8]) -> Result<String, Error> {
+    let nonce: [u8; NONCE_LEN] = thread_rng().gen();
+    let mut sealed = nonce.to_vec();
+    sealed.extend(
+        cipher
+            .encrypt(Nonce::from_slice(&nonce), Payload { msg, aad })
+            .map_err(|_| Error::new_internal_server_error("unable to encrypt token".to_string()))?,
+    );
+
+    Ok(base64::encode(sealed))
+}
+
+fn open(cipher: &Aes256Gcm, sealed: &str, aad: &[u8]) -> Option<Vec<u8>> {
+    let sealed = base64::decode(sealed).ok()?;
+    if sealed.len() < NONCE_LEN {
+        return None;
+    }
+
+    let (nonce, ciphertext) = sealed.split_at(NONCE_LEN);
+    cipher.decrypt(Nonce::from_slice(nonce), Payload { msg: ciphertext, aad }).ok()
+}
+
+/// Wraps another store and encrypts everything written to it.
+///
+/// Values that are still plaintext or were encrypted with one of the `previous` keys
+/// are re-encrypted with the current key as soon as they are loaded.
+pub struct EncryptedTokenStore {
+    inner: Arc<dyn TokenStore>,
+    current: MasterKey,
+    previous: Vec<MasterKey>,
+    rewrite_on_load: bool,
+}
+
+impl EncryptedTokenStore {
+    pub fn new(inner: Arc<dyn TokenStore>, current: MasterKey, previous: Vec<MasterKey>) -> Self {
+        EncryptedTokenStore {
+            inner,
+            current,
+            previous,
+            rewrite_on_load: true,
+        }
+    }
+
+    /// leaves loaded values as they are, for a process that doesn't hold the `StoreLock`
+    pub fn without_rewrites(self) -> Self {
+        EncryptedTokenStore {
+            rewrite_on_load: false,
+            ..self
+        }
+    }
+
+    /// the data key of `envelope` and whether it was encrypted with the current master key
+    fn open_data_key(&self, envelope: &Envelope) -> Result<(Vec<u8>, bool), Error> {
+        if let Some(data_key) = open(&self.current.0, &envelope.data_key, &[]) {
+            return Ok((data_key, true));
+        }
+
+        self.previous
+            .iter()
+            .find_map(|key| open(&key.0, &envelope.data_key, &[]))
+            .map(|data_key| (data_key, false))
+            .ok_or_else(|| {
+                Error::new_internal_server_error(
+                    "unable to decrypt token, is TOKEN_ENCRYPTION_KEY correct?".to_string(),
+                )
+            })
+    }
+
+    /// re-encrypts every value that was encrypted with a previous key and returns how many there were.
+    /// plaintext values are left alone since we can't tell tokens apart from unrelated files
+    pub async fn rotate(&self) -> Result<usize, Error> {
+        let mut rotated = 0;
+        for key in self.inner.keys().await? {
+            let bytes = match self.inner.load(&key).await? {
+                Some(bytes) => bytes,
+                None => continue,
+            };
+
+            if let Ok(envelope) = serde_json::from_slice::<Envelope>(&bytes) {
+                let (data_key, is_current) = self.open_data_key(&envelope)?;
+                if is_current {
+                    continue;
+                }
+
+                // only the data key has to be encrypted again, the value itself stays the same
+                let envelope = Envelope {
+                    data_key: seal(&self.current.0, &data_key, &[])?,
+                    ..envelope
+                };
+                self.inner.save(&key, &serde_json::to_vec(&envelope)?).await?;
+                rotated += 1;
+            }
+        }
+
+        Ok(rotated)
+    }
+}
+
+#[rocket::async_trait]
+impl TokenStore for EncryptedTokenStore {
+    async fn load(&self, key: &str) -> Result<Option<Vec<u8>>, Error> {
+        let bytes = match self.inner.load(key).await? {
+            Some(bytes) => bytes,
+            None => return Ok(None),
+        };
+
+        let envelope = match serde_json::from_slice::<Envelope>(&bytes) {
+            Ok(envelope) => envelope,
+            Err(_) => {
+                // saved before encryption was enabled
+                if self.rewrite_on_load {
+                    tracing::info!(%key, "encrypting plaintext token");
+                    self.save(key, &bytes).await?;
+                }
+                return Ok(Some(bytes));
+            }
+        };
+
+        let (data_key, is_current) = self.open_data_key(&envelope)?;
+        let cipher = Aes256Gcm::new(Key::from_slice(&data_key));
+        let aad = match envelope.encryption_version {
+            1 => &[][..],
+            _ => key.as_bytes(),
+        };
+        let value = open(&cipher, &envelope.data, aad).ok_or_else(|| {
+            Error::new_internal_server_error(format!("token {} is corrupted or was saved under another key", key))
+        })?;
+
+        if (!is_current || envelope.encryption_version < ENCRYPTION_VERSION) && self.rewrite_on_load {
+            tracing::info!(%key, "re-encrypting token with the current key");
+            self.save(key, &value).await?;
+        }
+
+        Ok(Some(value))
+    }
+
+    async fn save(&self, key: &str, value: &[u8]) -> Result<(), Error> {
+        let data_key: [u8; 32] = thread_rng().gen();
+        let cipher = Aes256Gcm::new(Key::from_slice(&data_key));
+        let envelope = Envelope {
+            encryption_version: ENCRYPTION_VERSION,
+            data_key: seal(&self.current.0, &data_key, &[])?,
+            data: seal(&cipher, value, key.as_bytes())?,
+        };
+
+        self.inner.save(key, &serde_json::to_vec(&envelope)?).await
+    }
+
+    async fn delete(&self, key: &str) -> Result<(), Error> {
+        self.inner.delete(key).await
+    }
+
+    async fn keys(&self) -> Result<Vec<String>, Error> {
+        self.inner.keys().await
+    }
+}
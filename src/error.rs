@@ -1,57 +1,174 @@
-use rocket::serde::{Serialize, Deserialize, json::Json};
+use rocket::http::{Header, Status};
+use rocket::request::Request;
+use rocket::response::{self, Responder, Response};
+use rocket::serde::json::Json;
 
 use url::ParseError;
 use std::sync::PoisonError;
+use std::time::Duration;
+
+pub use social_auth_types::{ErrorCode, ErrorResponse, UpstreamErrorResponse};
+
+/// What twitch or twitter answered with, or why they didn't.
+#[derive(Debug)]
+pub struct UpstreamError {
+    pub service: &'static str,
+    /// None if there was no response
+    pub status: Option<u16>,
+    /// the error code of the service
+    pub code: Option<String>,
+    pub message: String,
+    /// the service didn't answer in time
+    pub timeout: bool,
+    /// how long the service wants us to wait before trying again
+    pub retry_after: Option<Duration>,
+}
 
-#[derive(Debug, Serialize, Deserialize)]
-pub struct ErrorResponse {
-    status: u16,
-    error: String,
-    message: String
+impl UpstreamError {
+    pub fn error_code(&self) -> ErrorCode {
+        match self.status {
+            _ if self.timeout => ErrorCode::UpstreamTimeout,
+            None => ErrorCode::UpstreamUnavailable,
+            Some(429) => ErrorCode::UpstreamRateLimited,
+            Some(401) => ErrorCode::UpstreamUnauthorized,
+            Some(_) => ErrorCode::UpstreamError,
+        }
+    }
 }
 
-#[derive(Debug, Responder)]
-#[allow(clippy::enum_variant_names)] 
+#[derive(Debug)]
 pub enum Error {
-    #[response(status=400)]
-    BadRequest(Json<ErrorResponse>),
-    #[response(status=500)]
-    InternalServerError(Json<ErrorResponse>),
-    #[response(status=404)]
-    NotFound(Json<ErrorResponse>)
+    BadRequest(String),
+    Unauthorized(String),
+    /// not allowed, the code says whether the api key or the connected account lacks a scope
+    Forbidden(ErrorCode, String),
+    NotFound(String),
+    /// the request can't be done in the current state, the code says why
+    Conflict(ErrorCode, String),
+    TooManyRequests(String, Duration),
+    /// twitch or twitter failed
+    Upstream(UpstreamError),
+    InternalServerError(String),
 }
 
 impl Error {
     pub fn new_bad_request(message: String) -> Self {
-        Self::BadRequest(Json(Self::new_error_response(400, message)))
+        Self::BadRequest(message)
+    }
+
+    pub fn new_unauthorized(message: String) -> Self {
+        Self::Unauthorized(message)
     }
 
     pub fn new_internal_server_error(message: String) -> Self {
-        Self::InternalServerError(Json(Self::new_error_response(500, message)))
+        Self::InternalServerError(message)
     }
 
     pub fn new_not_found(message: String) -> Self {
-        Self::NotFound(Json(Self::new_error_response(404, message)))
+        Self::NotFound(message)
+    }
+
+    pub fn new_forbidden(message: String) -> Self {
+        Self::Forbidden(ErrorCode::Forbidden, message)
+    }
+
+    pub fn new_conflict(message: String) -> Self {
+        Self::Conflict(ErrorCode::Conflict, message)
+    }
+
+    /// `retry_after` is sent as the Retry-After header, rounded up to whole seconds
+    pub fn new_too_many_requests(message: String, retry_after: Duration) -> Self {
+        Self::TooManyRequests(message, retry_after)
     }
 
     pub fn new_auth_not_avail(service: &str) -> Self {
-        Self::BadRequest(Json(Self::new_error_response(403, format!("no {} auth info available", service))))
+        Self::Conflict(
+            ErrorCode::AccountNotConnected,
+            format!("no {} auth info available", service),
+        )
+    }
+
+    pub fn new_account_not_connected(message: String) -> Self {
+        Self::Conflict(ErrorCode::AccountNotConnected, message)
+    }
+
+    pub fn new_missing_upstream_scope(message: String) -> Self {
+        Self::Forbidden(ErrorCode::MissingUpstreamScope, message)
+    }
+
+    pub fn code(&self) -> ErrorCode {
+        match self {
+            Self::BadRequest(_) => ErrorCode::InvalidRequest,
+            Self::Unauthorized(_) => ErrorCode::Unauthorized,
+            Self::NotFound(_) => ErrorCode::NotFound,
+            Self::Forbidden(code, _) | Self::Conflict(code, _) => *code,
+            Self::TooManyRequests(..) => ErrorCode::RateLimited,
+            Self::Upstream(upstream) => upstream.error_code(),
+            Self::InternalServerError(_) => ErrorCode::Internal,
+        }
+    }
+
+    /// the message that is sent to the client
+    pub fn message(&self) -> String {
+        match self {
+            Self::BadRequest(message)
+            | Self::Unauthorized(message)
+            | Self::Forbidden(_, message)
+            | Self::NotFound(message)
+            | Self::Conflict(_, message)
+            | Self::TooManyRequests(message, _)
+            | Self::InternalServerError(message) => message.clone(),
+            Self::Upstream(upstream) => match upstream.status {
+                Some(status) => format!("{} responded with {}: {}", upstream.service, status, upstream.message),
+                None => format!("{} request failed: {}", upstream.service, upstream.message),
+            },
+        }
     }
 
-    fn new_error_response(status: u16, message: String) -> ErrorResponse {
-        let error = match status {
-            400 => "bad Request",
-            500 => "internal server error",
-            403 => "unauthorized",
-            404 => "not found",
-            _ => "unknown"
-        }.to_string();
+    fn retry_after(&self) -> Option<Duration> {
+        match self {
+            Self::TooManyRequests(_, retry_after) => Some(*retry_after),
+            Self::Upstream(upstream) if upstream.status == Some(429) => upstream.retry_after,
+            _ => None,
+        }
+    }
 
+    pub fn response(&self) -> ErrorResponse {
+        let code = self.code();
+        let status = Status::new(code.status());
         ErrorResponse {
-            status,
-            error,
-            message
+            status: status.code,
+            error: status.reason().unwrap_or("error").to_lowercase(),
+            code,
+            message: self.message(),
+            upstream: match self {
+                Self::Upstream(upstream) => Some(UpstreamErrorResponse {
+                    service: upstream.service.to_string(),
+                    status: upstream.status,
+                    code: upstream.code.clone(),
+                }),
+                _ => None,
+            },
+        }
+    }
+}
+
+impl<'r> Responder<'r, 'static> for Error {
+    fn respond_to(self, req: &'r Request<'_>) -> response::Result<'static> {
+        let response = self.response();
+        if response.status >= 500 {
+            tracing::error!(status = response.status, code = response.code.name(), message = %response.message, "request failed");
+        } else {
+            tracing::debug!(status = response.status, code = response.code.name(), message = %response.message, "request rejected");
+        }
+
+        let mut builder = Response::build_from(Json(response).respond_to(req)?);
+        builder.status(Status::new(self.code().status()));
+        if let Some(retry_after) = self.retry_after() {
+            let seconds = retry_after.as_secs() + u64::from(retry_after.subsec_nanos() > 0);
+            builder.header(Header::new("Retry-After", seconds.max(1).to_string()));
         }
+        Ok(builder.finalize())
     }
 }
 
@@ -67,9 +184,12 @@ impl<T> From<PoisonError<T>> for Error {
     }
 }
 
+/// errors of requests to twitch and twitter go through `HttpClient` which keeps the service,
+/// this is only for everything else
 impl From<reqwest::Error> for Error {
     fn from(err: reqwest::Error) -> Self {
-        Self::new_internal_server_error(err.to_string())
+        // the url can contain tokens and client secrets
+        Self::new_internal_server_error(err.without_url().to_string())
     }
 }
 
@@ -79,15 +199,6 @@ impl From<reqwest::header::InvalidHeaderValue> for Error {
     }
 }
 
-impl From<crate::twitch_config::TwitchErrorJson> for Error {
-    fn from(err: crate::twitch_config::TwitchErrorJson) -> Self {
-        Self::new_internal_server_error(format!(
-            "twitch responded with {} ({}) message: {}",
-            err.status, err.error, err.message
-        ))
-    }
-}
-
 impl From<std::io::Error> for Error {
     fn from(err: std::io::Error) -> Self {
         Self::new_internal_server_error(err.to_string())
@@ -100,8 +211,14 @@ impl From<serde_json::Error> for Error {
     }
 }
 
-impl From<egg_mode::error::Error> for Error {
-    fn from(err: egg_mode::error::Error) -> Self {
+impl From<rusqlite::Error> for Error {
+    fn from(err: rusqlite::Error) -> Self {
         Self::new_internal_server_error(err.to_string())
     }
-}
\ No newline at end of file
+}
+
+impl From<tokio::task::JoinError> for Error {
+    fn from(err: tokio::task::JoinError) -> Self {
+        Self::new_internal_server_error(err.to_string())
+    }
+}
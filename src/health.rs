@@ -0,0 +1,52 @@
+use crate::logging;
+use crate::store::TokenStore;
+use rocket::http::Status;
+use rocket::response::status;
+use rocket::serde::{json::Json, Serialize};
+use rocket::State;
+use std::sync::Arc;
+
+#[derive(Debug, Serialize)]
+pub struct HealthResponse {
+    status: &'static str,
+    /// why the server isn't ready
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+/// the process is up, doesn't look at anything else
+#[get("/healthz")]
+fn healthz() -> Json<HealthResponse> {
+    Json(HealthResponse {
+        status: "ok",
+        error: None,
+    })
+}
+
+/// the server can handle requests. twitch and twitter being down doesn't make it unready,
+/// restarting wouldn't help with that, `/api/v1/status` reports on them
+#[get("/readyz")]
+async fn readyz(store: &State<Arc<dyn TokenStore>>) -> status::Custom<Json<HealthResponse>> {
+    match store.keys().await {
+        Ok(_) => status::Custom(
+            Status::Ok,
+            Json(HealthResponse {
+                status: "ready",
+                error: None,
+            }),
+        ),
+        Err(e) => status::Custom(
+            Status::ServiceUnavailable,
+            Json(HealthResponse {
+                status: "unavailable",
+                error: Some(format!("token store: {}", e.message())),
+            }),
+        ),
+    }
+}
+
+pub fn stage() -> rocket::fairing::AdHoc {
+    rocket::fairing::AdHoc::on_ignite("health", |rocket| async {
+        rocket.mount("/", logging::traced(routes![healthz, readyz]))
+    })
+}
@@ -0,0 +1,249 @@
+use crate::error::{Error, UpstreamError};
+use crate::logging;
+use crate::metrics::Metrics;
+use rand::{thread_rng, Rng};
+use reqwest::header::HeaderMap;
+use reqwest::{IntoUrl, Method, Proxy, RequestBuilder, Response, StatusCode};
+use rocket::serde::DeserializeOwned;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// a rate limit that resets later than this isn't waited for, the caller gets the 429 instead
+const MAX_RETRY_WAIT: Duration = Duration::from_secs(10);
+const MAX_BACKOFF: Duration = Duration::from_secs(5);
+
+/// Settings of the clients that talk to twitch and twitter.
+#[derive(Debug, Clone)]
+pub struct HttpConfig {
+    /// time a whole request may take, including reading the response
+    pub timeout: Duration,
+    pub connect_timeout: Duration,
+    /// idle connections are closed after this long
+    pub pool_idle_timeout: Duration,
+    pub pool_max_idle_per_host: usize,
+    pub user_agent: String,
+    /// proxy for all upstream requests, the usual HTTP_PROXY/HTTPS_PROXY variables work as well
+    pub proxy: Option<String>,
+    /// retries after the first attempt, 0 turns retrying off
+    pub max_retries: u32,
+    /// the wait before the first retry, it doubles with every further one
+    pub retry_base_delay: Duration,
+}
+
+impl Default for HttpConfig {
+    fn default() -> Self {
+        HttpConfig {
+            timeout: Duration::from_secs(10),
+            connect_timeout: Duration::from_secs(5),
+            pool_idle_timeout: Duration::from_secs(90),
+            pool_max_idle_per_host: 8,
+            user_agent: format!("social_auth/{}", env!("CARGO_PKG_VERSION")),
+            proxy: None,
+            max_retries: 2,
+            retry_base_delay: Duration::from_millis(200),
+        }
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("system time is before the unix epoch")
+        .as_secs()
+}
+
+/// how long the service asked us to wait, twitch sends when its rate limit bucket refills,
+/// twitter the same with another header and others how many seconds to wait
+fn rate_limit_wait(headers: &HeaderMap) -> Option<Duration> {
+    let header = |name: &str| -> Option<u64> { headers.get(name)?.to_str().ok()?.trim().parse().ok() };
+
+    if let Some(reset) = header("Ratelimit-Reset").or_else(|| header("X-Rate-Limit-Reset")) {
+        return Some(Duration::from_secs(reset.saturating_sub(now())));
+    }
+    header("Retry-After").map(Duration::from_secs)
+}
+
+/// requests that can be sent twice without doing something twice
+fn is_idempotent(method: &Method) -> bool {
+    matches!(
+        *method,
+        Method::GET | Method::HEAD | Method::PUT | Method::DELETE | Method::OPTIONS
+    )
+}
+
+/// The pooled client one service sends all its requests with.
+pub struct HttpClient {
+    service: &'static str,
+    client: reqwest::Client,
+    metrics: Arc<Metrics>,
+    max_retries: u32,
+    retry_base_delay: Duration,
+}
+
+impl HttpClient {
+    pub fn new(service: &'static str, config: &HttpConfig, metrics: Arc<Metrics>) -> Result<Self, Error> {
+        let mut builder = reqwest::Client::builder()
+            .timeout(config.timeout)
+            .connect_timeout(config.connect_timeout)
+            .pool_idle_timeout(config.pool_idle_timeout)
+            .pool_max_idle_per_host(config.pool_max_idle_per_host)
+            .user_agent(config.user_agent.as_str());
+        if let Some(proxy) = &config.proxy {
+            builder = builder.proxy(Proxy::all(proxy.as_str())?);
+        }
+
+        Ok(HttpClient {
+            service,
+            client: builder.build()?,
+            metrics,
+            max_retries: config.max_retries,
+            retry_base_delay: config.retry_base_delay,
+        })
+    }
+
+    pub fn request<U: IntoUrl>(&self, method: Method, url: U) -> RequestBuilder {
+        self.client.request(method, url)
+    }
+
+    pub fn get<U: IntoUrl>(&self, url: U) -> RequestBuilder {
+        self.client.get(url)
+    }
+
+    pub fn post<U: IntoUrl>(&self, url: U) -> RequestBuilder {
+        self.client.post(url)
+    }
+
+    pub fn patch<U: IntoUrl>(&self, url: U) -> RequestBuilder {
+        self.client.patch(url)
+    }
+
+    /// sends `request` and retries it with backoff when the service is rate limiting us, or for
+    /// idempotent requests when it had an error or couldn't be reached.
+    /// the response of the last attempt is returned whatever its status is
+    pub async fn send(&self, request: RequestBuilder) -> Result<Response, Error> {
+        let mut request = request.build()?;
+        let idempotent = is_idempotent(request.method());
+        let mut attempt = 0;
+        loop {
+            // our bodies are always in memory so they can be cloned
+            let retry = if attempt < self.max_retries {
+                request.try_clone()
+            } else {
+                None
+            };
+            let result = logging::send(self.service, &self.client, &self.metrics, request).await;
+
+            let (retry, delay) = match (retry, self.retry_delay(&result, idempotent, attempt)) {
+                (Some(retry), Some(delay)) => (retry, delay),
+                _ => return result.map_err(|e| self.request_error(e)),
+            };
+            tracing::info!(
+                service = self.service,
+                attempt = attempt + 1,
+                delay_ms = delay.as_millis() as u64,
+                "retrying upstream call"
+            );
+            tokio::time::sleep(delay).await;
+            request = retry;
+            attempt += 1;
+        }
+    }
+
+    /// reads the json body of a successful response
+    pub async fn json<T: DeserializeOwned>(&self, res: Response) -> Result<T, Error> {
+        let status = res.status().as_u16();
+        res.json().await.map_err(|e| self.invalid_response(status, e))
+    }
+
+    pub async fn text(&self, res: Response) -> Result<String, Error> {
+        let status = res.status().as_u16();
+        res.text().await.map_err(|e| self.invalid_response(status, e))
+    }
+
+    /// turns an error response into an `Error` that keeps the service, status and error code.
+    /// `parse` gets the body and returns the error code and message of the service if it understands it
+    pub async fn error(&self, res: Response, parse: fn(&str) -> Option<(String, String)>) -> Error {
+        let status = res.status();
+        let retry_after = rate_limit_wait(res.headers());
+        let body = match res.text().await {
+            Ok(body) => body,
+            Err(e) => return self.invalid_response(status.as_u16(), e),
+        };
+
+        let (code, message) = match parse(&body) {
+            Some((code, message)) => (Some(code), message),
+            None if body.trim().is_empty() => (None, status.canonical_reason().unwrap_or("no message").to_string()),
+            None => (None, body),
+        };
+        Error::Upstream(UpstreamError {
+            service: self.service,
+            status: Some(status.as_u16()),
+            code,
+            message,
+            timeout: false,
+            retry_after,
+        })
+    }
+
+    /// a successful response that doesn't contain what it should
+    pub fn unexpected_response(&self, message: &str) -> Error {
+        Error::Upstream(UpstreamError {
+            service: self.service,
+            status: Some(200),
+            code: None,
+            message: message.to_string(),
+            timeout: false,
+            retry_after: None,
+        })
+    }
+
+    /// the request didn't get a response
+    fn request_error(&self, err: reqwest::Error) -> Error {
+        Error::Upstream(UpstreamError {
+            service: self.service,
+            status: None,
+            code: None,
+            timeout: err.is_timeout(),
+            // the url can contain tokens and client secrets
+            message: err.without_url().to_string(),
+            retry_after: None,
+        })
+    }
+
+    fn invalid_response(&self, status: u16, err: reqwest::Error) -> Error {
+        Error::Upstream(UpstreamError {
+            service: self.service,
+            status: Some(status),
+            code: None,
+            timeout: err.is_timeout(),
+            message: format!("invalid response: {}", err.without_url()),
+            retry_after: None,
+        })
+    }
+
+    /// how long to wait before trying `result` again, None if it shouldn't be retried
+    fn retry_delay(
+        &self,
+        result: &Result<Response, reqwest::Error>,
+        idempotent: bool,
+        attempt: u32,
+    ) -> Option<Duration> {
+        let backoff = (self.retry_base_delay * 2u32.pow(attempt.min(16))).min(MAX_BACKOFF);
+        // spreads out the retries of requests that failed at the same time
+        let backoff = backoff + backoff.mul_f64(thread_rng().gen_range(0.0..0.5));
+
+        match result {
+            // a rate limited request wasn't handled so it can be sent again whatever it does
+            Ok(res) if res.status() == StatusCode::TOO_MANY_REQUESTS => {
+                match rate_limit_wait(res.headers()) {
+                    Some(wait) if wait > MAX_RETRY_WAIT => None,
+                    Some(wait) => Some(wait.max(backoff)),
+                    None => Some(backoff),
+                }
+            }
+            Ok(res) if idempotent && res.status().is_server_error() => Some(backoff),
+            Err(e) if idempotent && (e.is_timeout() || e.is_connect()) => Some(backoff),
+            _ => None,
+        }
+    }
+}
@@ -0,0 +1,254 @@
+pub mod twitch_config;
+pub mod api;
+pub mod twitter_config;
+pub mod templates;
+pub mod error;
+pub mod provider;
+pub mod store;
+pub mod encryption;
+pub mod api_keys;
+pub mod sessions;
+pub mod rate_limit;
+pub mod users;
+pub mod totp;
+pub mod audit;
+pub mod logging;
+pub mod metrics;
+pub mod health;
+pub mod http;
+pub mod openapi;
+pub mod admin;
+pub mod config;
+pub mod oauth1;
+
+#[macro_use]
+extern crate rocket;
+use rocket::fs::FileServer;
+use std::io::{self, BufRead};
+use std::sync::Arc;
+
+pub use config::{Config, InvalidConfig};
+
+/// re-encrypts all stored tokens that were encrypted with one of the TOKEN_ENCRYPTION_KEY_PREVIOUS keys, returns how many
+pub async fn rotate_key(config: &Config) -> Result<usize, InvalidConfig> {
+    let current = config
+        .token_encryption_key
+        .as_deref()
+        .ok_or_else(|| InvalidConfig::missing("token_store.encryption_key", "rotate-key re-encrypts the tokens with it"))?;
+    // a running server would keep the old key as the current one
+    let _lock = acquire_store_lock(config)?;
+    let token_store = encryption::EncryptedTokenStore::new(
+        open_store(config)?,
+        master_key("token_store.encryption_key", current)?,
+        previous_keys(&config.token_encryption_key_previous)?,
+    );
+    token_store
+        .rotate()
+        .await
+        .map_err(|e| store_problem("unable to re-encrypt the tokens", e))
+}
+
+/// reads a password from stdin and prints its hash for AUTH_PASSWORD_HASH
+pub fn hash_password() {
+    println!("enter the password to hash:");
+    let mut password = String::new();
+    io::stdin().lock().read_line(&mut password).expect("unable to read password");
+    let hash = users::hash_password(password.trim_end_matches(&['\r', '\n'][..])).expect("unable to hash password");
+    println!("{}", hash);
+}
+
+fn open_store(config: &Config) -> Result<Arc<dyn store::TokenStore>, InvalidConfig> {
+    store::from_config(&config.token_store, config.token_store_path.clone()).map_err(|e| {
+        InvalidConfig::new("token_store.path", format!("unable to open the token store: {}", e.message()))
+    })
+}
+
+/// the lock the server holds while it runs, see `store::StoreLock`
+pub(crate) fn store_lock_path(config: &Config) -> std::path::PathBuf {
+    store::lock_path(&config.token_store, config.token_store_path.as_deref())
+}
+
+/// fails if a server or `social_auth-admin` holds the lock already
+fn acquire_store_lock(config: &Config) -> Result<store::StoreLock, InvalidConfig> {
+    store::StoreLock::try_acquire(&store_lock_path(config))
+        .map_err(|e| InvalidConfig::new("token_store.path", format!("unable to lock the token store: {}", e.message())))?
+        .ok_or_else(|| {
+            InvalidConfig::new(
+                "token_store.path",
+                "another server or social_auth-admin is using the token store".to_string(),
+            )
+        })
+}
+
+/// the token store couldn't be read or written while starting
+fn store_problem(what: &str, e: error::Error) -> InvalidConfig {
+    InvalidConfig::new("token_store", format!("{}: {}", what, e.message()))
+}
+
+pub(crate) async fn open_audit_log(config: &Config) -> Result<audit::AuditLog, InvalidConfig> {
+    audit::AuditLog::open(&config.audit_log_path).await.map_err(|e| {
+        InvalidConfig::new(
+            "audit_log_path",
+            format!("unable to open {}: {}", config.audit_log_path, e.message()),
+        )
+    })
+}
+
+fn master_key(key: &str, value: &str) -> Result<encryption::MasterKey, InvalidConfig> {
+    encryption::MasterKey::from_base64(value).map_err(|e| InvalidConfig::new(key, e.message()))
+}
+
+fn previous_keys(keys: &[String]) -> Result<Vec<encryption::MasterKey>, InvalidConfig> {
+    keys.iter()
+        .map(|key| master_key("token_store.encryption_key_previous", key))
+        .collect()
+}
+
+/// the server configured from the config file and the environment
+pub async fn rocket() -> Result<rocket::Rocket<rocket::Build>, InvalidConfig> {
+    let figment = config::figment();
+    let config = Config::from_figment(&figment)?;
+    Ok(build(config).await?.configure(figment))
+}
+
+/// The token store and everything kept in it, shared by the server and `social_auth-admin`.
+pub struct Services {
+    pub token_store: Arc<dyn store::TokenStore>,
+    pub metrics: Arc<metrics::Metrics>,
+    /// None if the service isn't enabled
+    pub twitch: Option<Arc<twitch_config::Twitch>>,
+    pub twitter: Option<Arc<twitter_config::Twitter>>,
+    pub providers: provider::Providers,
+    pub api_keys: api_keys::ApiKeys,
+}
+
+impl Services {
+    /// opens the configured token store and loads the saved tokens and api keys.
+    ///
+    /// legacy tokens are only migrated and values only re-encrypted while `lock` is held,
+    /// otherwise loading doesn't write anything
+    pub async fn load(config: &Config, lock: Option<&store::StoreLock>) -> Result<Self, InvalidConfig> {
+        let token_store = open_store(config)?;
+        let token_store: Arc<dyn store::TokenStore> = match &config.token_encryption_key {
+            Some(key) => {
+                let encrypted = encryption::EncryptedTokenStore::new(
+                    token_store,
+                    master_key("token_store.encryption_key", key)?,
+                    previous_keys(&config.token_encryption_key_previous)?,
+                );
+                match lock {
+                    Some(_) => Arc::new(encrypted),
+                    None => Arc::new(encrypted.without_rewrites()),
+                }
+            }
+            None => {
+                tracing::warn!("TOKEN_ENCRYPTION_KEY is not set, tokens will be stored unencrypted");
+                token_store
+            }
+        };
+        let metrics = Arc::new(metrics::Metrics::new());
+        let http_client = |service| {
+            http::HttpClient::new(service, &config.http, metrics.clone()).map_err(|e| InvalidConfig::new("http", e.message()))
+        };
+        let mut providers = provider::Providers::new();
+        let twitter = match &config.twitter {
+            Some(twitter) => Some(Arc::new(twitter_config::Twitter::new(twitter, token_store.clone(), http_client("twitter")?))),
+            None => None,
+        };
+        if let Some(twitter) = &twitter {
+            providers = providers.register(twitter.clone());
+        }
+        let twitch = match &config.twitch {
+            Some(twitch) => Some(Arc::new(twitch_config::Twitch::new(
+                twitch,
+                token_store.clone(),
+                http_client("twitch")?,
+                metrics.clone(),
+            ))),
+            None => None,
+        };
+        if let Some(twitch) = &twitch {
+            providers = providers.register(twitch.clone());
+        }
+        providers
+            .load_tokens()
+            .await
+            .map_err(|e| store_problem("unable to load the saved tokens", e))?;
+        if lock.is_some() {
+            providers.migrate_legacy_tokens().await;
+        }
+        let api_keys = api_keys::ApiKeys::new(token_store.clone());
+        api_keys.load().await.map_err(|e| store_problem("unable to load the api keys", e))?;
+        Ok(Services {
+            token_store,
+            metrics,
+            twitch,
+            twitter,
+            providers,
+            api_keys,
+        })
+    }
+}
+
+/// the server for `config`, fails if the token store or the audit log can't be opened
+pub async fn build(config: Config) -> Result<rocket::Rocket<rocket::Build>, InvalidConfig> {
+    // before loading, which can write to the store
+    let store_lock = acquire_store_lock(&config)?;
+    let Services { token_store, metrics, twitch, twitter, providers, api_keys } =
+        Services::load(&config, Some(&store_lock)).await?;
+    let users = users::Users::new(token_store.clone());
+    users.load().await.map_err(|e| store_problem("unable to load the users", e))?;
+    if users.is_empty().await && config.password_hash.is_none() {
+        return Err(InvalidConfig::missing(
+            "password_hash",
+            "the first admin is created with it while there are no users",
+        ));
+    }
+    if users
+        .bootstrap(config.password_hash.as_deref())
+        .await
+        .map_err(|e| store_problem("unable to create the first user", e))?
+    {
+        tracing::info!(username = users::BOOTSTRAP_USERNAME, "created the first user from AUTH_PASSWORD_HASH");
+    }
+    let audit = open_audit_log(&config).await?;
+    let sessions = Arc::new(sessions::Sessions::new(
+        config.session_idle_timeout,
+        config.session_absolute_timeout,
+        config.session_cookie_secure,
+    ));
+    match config.enabled_services().as_slice() {
+        [] => tracing::warn!("neither twitch nor twitter is enabled, there is nothing to connect"),
+        services => tracing::info!(services = %services.join(", "), "enabled services"),
+    }
+    // the routes that need a service are only mounted if it is managed
+    let mut rocket = rocket::build();
+    if let Some(twitch) = twitch {
+        rocket = rocket.manage(twitch);
+    }
+    if let Some(twitter) = twitter {
+        rocket = rocket.manage(twitter);
+    }
+    Ok(rocket
+        .manage(providers)
+        .manage(api_keys)
+        .manage(provider::OAuthStates::new())
+        .manage(users)
+        .manage(audit)
+        .manage(sessions)
+        .manage(metrics)
+        .manage(token_store)
+        .manage(store_lock)
+        .manage(rate_limit::LoginAttempts::new(config.login_attempts, config.login_global_attempts))
+        .manage(rate_limit::RateLimiter::new(config.api_rate_limit))
+        .mount("/", logging::traced(FileServer::from("public/").into()))
+        .attach(logging::RequestLogger)
+        .attach(templates::stage())
+        .attach(provider::stage())
+        .attach(twitch_config::stage())
+        .attach(sessions::stage())
+        .attach(api::stage())
+        .attach(metrics::stage())
+        .attach(health::stage())
+        .attach(openapi::stage()))
+}
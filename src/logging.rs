@@ -0,0 +1,181 @@
+use crate::metrics::Metrics;
+use crate::templates::gen_random_string;
+use rocket::fairing::{Fairing, Info, Kind};
+use rocket::http::Header;
+use rocket::request::Request;
+use rocket::route::{Handler, Outcome, Route};
+use rocket::{Data, Response};
+use std::env;
+use std::time::Instant;
+use tracing::Instrument;
+use tracing_subscriber::EnvFilter;
+
+pub const REQUEST_ID_HEADER: &str = "X-Request-Id";
+const REQUEST_ID_LEN: usize = 16;
+/// longer ids sent by clients are replaced, they end up in every log line
+const MAX_REQUEST_ID_LEN: usize = 64;
+
+tokio::task_local! {
+    /// the id of the request that is being handled, for outbound calls
+    static CURRENT_REQUEST_ID: String;
+}
+
+/// sets up the global subscriber, `LOG_FORMAT=json` switches to one json object per line and
+/// `RUST_LOG` sets the levels (default `info`)
+pub fn init() {
+    let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info"));
+    let builder = tracing_subscriber::fmt().with_env_filter(filter);
+    let result = match env::var("LOG_FORMAT").as_deref() {
+        Ok("json") => builder.json().flatten_event(true).with_current_span(true).try_init(),
+        _ => builder.try_init(),
+    };
+
+    if let Err(e) = result {
+        eprintln!("unable to set up logging: {}", e);
+    }
+}
+
+/// the id of the request that is currently being handled, if any
+pub fn current_request_id() -> Option<String> {
+    CURRENT_REQUEST_ID.try_with(|id| id.clone()).ok()
+}
+
+/// The id of a request, taken from the `X-Request-Id` header or generated.
+#[derive(Debug, Clone)]
+struct RequestId(String);
+
+impl RequestId {
+    fn from_request<'r>(req: &'r Request<'_>) -> &'r RequestId {
+        req.local_cache(|| {
+            let id = req
+                .headers()
+                .get_one(REQUEST_ID_HEADER)
+                .filter(|id| {
+                    !id.is_empty()
+                        && id.len() <= MAX_REQUEST_ID_LEN
+                        && id.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+                })
+                .map(String::from)
+                .unwrap_or_else(|| gen_random_string(REQUEST_ID_LEN));
+            RequestId(id)
+        })
+    }
+}
+
+/// when the request came in, for the latency in the response log line
+struct RequestStart(Instant);
+
+/// Assigns every request an id that is sent back in the response and logs each request.
+pub struct RequestLogger;
+
+#[rocket::async_trait]
+impl Fairing for RequestLogger {
+    fn info(&self) -> Info {
+        Info {
+            name: "request logger",
+            kind: Kind::Request | Kind::Response,
+        }
+    }
+
+    async fn on_request(&self, req: &mut Request<'_>, _data: &mut Data<'_>) {
+        req.local_cache(|| RequestStart(Instant::now()));
+        RequestId::from_request(req);
+    }
+
+    async fn on_response<'r>(&self, req: &'r Request<'_>, res: &mut Response<'r>) {
+        let request_id = RequestId::from_request(req);
+        let latency_ms = req
+            .local_cache(|| RequestStart(Instant::now()))
+            .0
+            .elapsed()
+            .as_millis() as u64;
+
+        // only the path, query strings carry oauth codes and states
+        tracing::info!(
+            request_id = %request_id.0,
+            method = %req.method(),
+            path = %req.uri().path(),
+            status = res.status().code,
+            latency_ms,
+            "request handled"
+        );
+        res.set_header(Header::new(REQUEST_ID_HEADER, request_id.0.clone()));
+    }
+}
+
+/// Runs a route inside a span with the request id so every log line and outbound call of the
+/// handler carries it.
+#[derive(Clone)]
+struct Traced(Box<dyn Handler>);
+
+#[rocket::async_trait]
+impl Handler for Traced {
+    async fn handle<'r>(&self, req: &'r Request<'_>, data: Data<'r>) -> Outcome<'r> {
+        let request_id = RequestId::from_request(req).0.clone();
+        let span = tracing::info_span!("request", request_id = %request_id);
+        CURRENT_REQUEST_ID
+            .scope(request_id, self.0.handle(req, data))
+            .instrument(span)
+            .await
+    }
+}
+
+/// wraps the handlers of `routes`, use it for everything that is mounted
+pub fn traced(routes: Vec<Route>) -> Vec<Route> {
+    routes
+        .into_iter()
+        .map(|mut route| {
+            route.handler = Box::new(Traced(route.handler));
+            route
+        })
+        .collect()
+}
+
+/// sends `request` once with the id of the current request, logs the call and records it in
+/// `metrics`, `service` is the api that is called
+pub async fn send(
+    service: &'static str,
+    client: &reqwest::Client,
+    metrics: &Metrics,
+    mut request: reqwest::Request,
+) -> Result<reqwest::Response, reqwest::Error> {
+    if let Some(request_id) = current_request_id() {
+        if let Ok(value) = reqwest::header::HeaderValue::from_str(&request_id) {
+            request.headers_mut().insert(REQUEST_ID_HEADER, value);
+        }
+    }
+
+    let method = request.method().clone();
+    // the query isn't logged and the url is removed from errors, it contains tokens and client secrets
+    let host = request.url().host_str().unwrap_or_default().to_string();
+    let path = request.url().path().to_string();
+    let start = Instant::now();
+    let result = client.execute(request).await.map_err(|e| e.without_url());
+    let latency = start.elapsed();
+    let latency_ms = latency.as_millis() as u64;
+    let status = result.as_ref().ok().map(|res| res.status().as_u16());
+    metrics.upstream_request(service, method.as_str(), &path, status, latency);
+
+    match &result {
+        Ok(res) => tracing::info!(
+            service,
+            %method,
+            %host,
+            %path,
+            status = res.status().as_u16(),
+            latency_ms,
+            "upstream call"
+        ),
+        Err(e) => tracing::warn!(
+            service,
+            %method,
+            %host,
+            %path,
+            latency_ms,
+            error = %e,
+            "upstream call failed"
+        ),
+    }
+
+    result
+}
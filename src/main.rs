@@ -1,52 +1,48 @@
-mod twitch_config;
-mod api;
-mod twitter_config;
-mod templates;
-mod error;
-
-#[macro_use]
-extern crate rocket;
-use rocket::fs::FileServer;
 use std::env;
+use std::error::Error;
+use std::process;
 
+/// re-encrypts the tokens with the current TOKEN_ENCRYPTION_KEY
+async fn rotate_key() -> Result<(), Box<dyn Error>> {
+    let config = social_auth::Config::load()?;
+    let rotated = social_auth::rotate_key(&config).await?;
+    println!("re-encrypted {} tokens with the new key", rotated);
+    Ok(())
+}
 
-#[launch]
-async fn rocket() -> _ {
-    let config = Config::from_env();
-    let twitch = twitch_config::Twitch::new(config.twitch_client_id, config.twitch_client_secret, config.twitch_redirect_uri).await;
-    let twitter = twitter_config::Twitter::new(config.twitter_api_key, config.twitter_api_secret, config.twitter_callback_url).await;
-    let sessions = templates::Sessions::new(config.password);
-    rocket::build()
-        .manage(twitch)
-        .manage(twitter)
-        .manage(sessions)
-        .mount("/", FileServer::from("public/"))
-        .attach(templates::stage())
-        .attach(twitch_config::stage())
-        .attach(api::stage())
-        .attach(twitter_config::stage())
+fn check_config() -> Result<(), Box<dyn Error>> {
+    let config = social_auth::Config::load()?;
+    println!("the configuration is valid, enabled services: {}", config.enabled_services().join(", "));
+    Ok(())
 }
 
-struct Config {
-    twitch_client_id: String,
-    twitch_client_secret: String,
-    twitch_redirect_uri: String,
-    twitter_api_key: String,
-    twitter_api_secret: String,
-    twitter_callback_url: String,
-    password: String
+async fn serve() -> Result<(), Box<dyn Error>> {
+    // printing rocket's error marks it as handled, otherwise it panics when it is dropped
+    social_auth::rocket().await?.launch().await?;
+    Ok(())
 }
 
-impl Config {
-    fn from_env() -> Self {
-        Config {
-            twitch_client_id: env::var("TWITCH_CLIENT_ID").expect("did not find a TWITCH_CLIENT_ID"),
-            twitch_client_secret: env::var("TWITCH_CLIENT_SECRET").expect("did not find a TWITCH_CLIENT_SECRET"),
-            twitch_redirect_uri: env::var("TWITCH_REDIRECT_URI").unwrap_or_else(|_| String::from("http://localhost:8000/twitch/authorize/callback")),
-            twitter_api_key: env::var("TWITTER_API_KEY").expect("did not find a TWITTER_API_KEY"),
-            twitter_api_secret: env::var("TWITTER_API_SECRET").expect("did not find a TWITTER_API_SECRET"),
-            twitter_callback_url: env::var("TWITTER_CALLBACK_URL").unwrap_or_else(|_| String::from("http://127.0.0.1:8000/twitter/authorize/callback")),
-            password: env::var("AUTH_PASSWORD").expect("did not find a AUTH_PASSWORD")
+#[rocket::main]
+async fn main() {
+    social_auth::logging::init();
+    let command = env::args().nth(1);
+    let result = match command.as_deref() {
+        Some("rotate-key") => rotate_key().await,
+        Some("hash-password") => {
+            social_auth::hash_password();
+            Ok(())
         }
+        Some("check-config") => check_config(),
+        Some(command) => Err(format!(
+            "unknown command {}, available commands: rotate-key, hash-password, check-config",
+            command
+        )
+        .into()),
+        None => serve().await,
+    };
+
+    if let Err(e) = result {
+        eprintln!("{}", e);
+        process::exit(1);
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,226 @@
+use crate::logging;
+use crate::provider::Providers;
+use crate::sessions::Sessions;
+use prometheus::{
+    Encoder, HistogramOpts, HistogramVec, IntCounterVec, IntGauge, IntGaugeVec, Opts, Registry, TextEncoder,
+};
+use rocket::fairing::{Fairing, Info, Kind};
+use rocket::http::ContentType;
+use rocket::{Data, Request, Response, State};
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// only requests to the api are counted, the dashboard and static files aren't interesting
+const API_PREFIX: &str = "/api/v1";
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("system time is before the unix epoch")
+        .as_secs()
+}
+
+/// Counters of the server in the prometheus text format, served at `/metrics`.
+pub struct Metrics {
+    registry: Registry,
+    api_requests: IntCounterVec,
+    api_request_duration: HistogramVec,
+    upstream_requests: IntCounterVec,
+    upstream_request_duration: HistogramVec,
+    upstream_last_success: IntGaugeVec,
+    token_refreshes: IntCounterVec,
+    active_sessions: IntGauge,
+    connected_accounts: IntGaugeVec,
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        let registry = Registry::new_custom(Some("social_auth".to_string()), None)
+            .expect("invalid metrics prefix");
+        let metrics = Metrics {
+            api_requests: IntCounterVec::new(
+                Opts::new("api_requests_total", "requests to /api/v1 by route and status"),
+                &["method", "route", "status"],
+            )
+            .expect("invalid metric"),
+            api_request_duration: HistogramVec::new(
+                HistogramOpts::new("api_request_duration_seconds", "time to answer requests to /api/v1"),
+                &["method", "route"],
+            )
+            .expect("invalid metric"),
+            upstream_requests: IntCounterVec::new(
+                Opts::new(
+                    "upstream_requests_total",
+                    "requests to twitch and twitter by endpoint and status, status is error if there was no response",
+                ),
+                &["service", "method", "endpoint", "status"],
+            )
+            .expect("invalid metric"),
+            upstream_request_duration: HistogramVec::new(
+                HistogramOpts::new("upstream_request_duration_seconds", "time until twitch and twitter answered"),
+                &["service", "method", "endpoint"],
+            )
+            .expect("invalid metric"),
+            upstream_last_success: IntGaugeVec::new(
+                Opts::new(
+                    "upstream_last_success_timestamp_seconds",
+                    "unix timestamp of the last successful request to twitch and twitter",
+                ),
+                &["service"],
+            )
+            .expect("invalid metric"),
+            token_refreshes: IntCounterVec::new(
+                Opts::new("token_refreshes_total", "token refreshes by outcome"),
+                &["service", "outcome"],
+            )
+            .expect("invalid metric"),
+            active_sessions: IntGauge::new("active_sessions", "dashboard sessions that didn't expire")
+                .expect("invalid metric"),
+            connected_accounts: IntGaugeVec::new(
+                Opts::new("connected_accounts", "connected accounts by service"),
+                &["service"],
+            )
+            .expect("invalid metric"),
+            registry,
+        };
+
+        metrics.register(Box::new(metrics.api_requests.clone()));
+        metrics.register(Box::new(metrics.api_request_duration.clone()));
+        metrics.register(Box::new(metrics.upstream_requests.clone()));
+        metrics.register(Box::new(metrics.upstream_request_duration.clone()));
+        metrics.register(Box::new(metrics.upstream_last_success.clone()));
+        metrics.register(Box::new(metrics.token_refreshes.clone()));
+        metrics.register(Box::new(metrics.active_sessions.clone()));
+        metrics.register(Box::new(metrics.connected_accounts.clone()));
+        metrics
+    }
+
+    fn register(&self, collector: Box<dyn prometheus::core::Collector>) {
+        self.registry.register(collector).expect("metric registered twice");
+    }
+
+    /// records a call to twitch or twitter, `status` is None if there was no response
+    pub fn upstream_request(
+        &self,
+        service: &str,
+        method: &str,
+        endpoint: &str,
+        status: Option<u16>,
+        latency: Duration,
+    ) {
+        if let Some(200..=299) = status {
+            self.upstream_last_success
+                .with_label_values(&[service])
+                .set(now() as i64);
+        }
+
+        let status = match status {
+            Some(status) => status.to_string(),
+            None => "error".to_string(),
+        };
+        self.upstream_requests
+            .with_label_values(&[service, method, endpoint, &status])
+            .inc();
+        self.upstream_request_duration
+            .with_label_values(&[service, method, endpoint])
+            .observe(latency.as_secs_f64());
+    }
+
+    /// unix timestamp of the last successful request to `service`
+    pub fn upstream_last_success(&self, service: &str) -> Option<u64> {
+        match self.upstream_last_success.with_label_values(&[service]).get() {
+            0 => None,
+            timestamp => Some(timestamp as u64),
+        }
+    }
+
+    pub fn token_refresh(&self, service: &str, success: bool) {
+        let outcome = if success { "success" } else { "failure" };
+        self.token_refreshes.with_label_values(&[service, outcome]).inc();
+    }
+
+    /// all metrics in the prometheus text format, the gauges are read right before
+    pub async fn render(&self, sessions: &Sessions, providers: &Providers) -> String {
+        self.active_sessions.set(sessions.active().await as i64);
+        for provider in providers.iter() {
+            self.connected_accounts
+                .with_label_values(&[provider.name()])
+                .set(provider.accounts().await.len() as i64);
+        }
+
+        let mut buffer = Vec::new();
+        TextEncoder::new()
+            .encode(&self.registry.gather(), &mut buffer)
+            .expect("unable to encode metrics");
+        String::from_utf8(buffer).expect("metrics aren't valid utf-8")
+    }
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Metrics::new()
+    }
+}
+
+/// when an api request came in
+struct ApiRequestStart(Instant);
+
+/// Counts the requests to the api by route and status.
+pub struct ApiMetrics;
+
+#[rocket::async_trait]
+impl Fairing for ApiMetrics {
+    fn info(&self) -> Info {
+        Info {
+            name: "api metrics",
+            kind: Kind::Request | Kind::Response,
+        }
+    }
+
+    async fn on_request(&self, req: &mut Request<'_>, _data: &mut Data<'_>) {
+        req.local_cache(|| ApiRequestStart(Instant::now()));
+    }
+
+    async fn on_response<'r>(&self, req: &'r Request<'_>, res: &mut Response<'r>) {
+        if !req.uri().path().starts_with(API_PREFIX) {
+            return;
+        }
+        let metrics = match req.rocket().state::<Arc<Metrics>>() {
+            Some(metrics) => metrics,
+            None => return,
+        };
+
+        // the route and not the path so ids in the path don't create new series
+        let route = match req.route() {
+            Some(route) => route.uri.path(),
+            None => "unmatched",
+        };
+        let method = req.method().as_str();
+        let latency = req.local_cache(|| ApiRequestStart(Instant::now())).0.elapsed();
+        metrics
+            .api_requests
+            .with_label_values(&[method, route, &res.status().code.to_string()])
+            .inc();
+        metrics
+            .api_request_duration
+            .with_label_values(&[method, route])
+            .observe(latency.as_secs_f64());
+    }
+}
+
+#[get("/metrics")]
+async fn get_metrics(
+    metrics: &State<Arc<Metrics>>,
+    sessions: &State<Arc<Sessions>>,
+    providers: &State<Providers>,
+) -> (ContentType, String) {
+    (ContentType::Plain, metrics.render(sessions, providers).await)
+}
+
+pub fn stage() -> rocket::fairing::AdHoc {
+    rocket::fairing::AdHoc::on_ignite("metrics", |rocket| async {
+        rocket
+            .mount("/", logging::traced(routes![get_metrics]))
+            .attach(ApiMetrics)
+    })
+}
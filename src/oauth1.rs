@@ -0,0 +1,118 @@
+// just enough OAuth 1.0a to sign the requests we make to twitter.
+
+use egg_mode::KeyPair;
+use hmac::{Hmac, Mac, NewMac};
+use percent_encoding::{utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
+use rand::distributions::Alphanumeric;
+use rand::{thread_rng, Rng};
+use reqwest::Url;
+use sha1::Sha1;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// everything except the unreserved characters of RFC 3986 has to be encoded
+const ENCODE_SET: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'-')
+    .remove(b'.')
+    .remove(b'_')
+    .remove(b'~');
+
+fn encode(s: &str) -> String {
+    utf8_percent_encode(s, ENCODE_SET).to_string()
+}
+
+/// builds the `Authorization` header for a request to `url`.
+///
+/// `params` are additional `oauth_` parameters (like `oauth_callback`) and the form
+/// encoded body of the request, both are part of the signature.
+pub fn authorization_header(
+    method: &str,
+    url: &Url,
+    consumer: &KeyPair,
+    token: Option<&KeyPair>,
+    params: &[(&str, &str)],
+) -> String {
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("system time is before the unix epoch")
+        .as_secs();
+    let nonce: String = thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(32)
+        .map(char::from)
+        .collect();
+
+    signed_authorization_header(method, url, consumer, token, params, &nonce, timestamp)
+}
+
+/// `authorization_header` with a fixed nonce and timestamp, so a signature can be compared to a known one
+pub fn signed_authorization_header(
+    method: &str,
+    url: &Url,
+    consumer: &KeyPair,
+    token: Option<&KeyPair>,
+    params: &[(&str, &str)],
+    nonce: &str,
+    timestamp: u64,
+) -> String {
+    let mut oauth_params = vec![
+        ("oauth_consumer_key", consumer.key.to_string()),
+        ("oauth_nonce", nonce.to_string()),
+        ("oauth_signature_method", "HMAC-SHA1".to_string()),
+        ("oauth_timestamp", timestamp.to_string()),
+        ("oauth_version", "1.0".to_string()),
+    ];
+    if let Some(token) = token {
+        oauth_params.push(("oauth_token", token.key.to_string()));
+    }
+    for (key, value) in params {
+        if key.starts_with("oauth_") {
+            oauth_params.push((key, value.to_string()));
+        }
+    }
+
+    let mut signature_params: Vec<(String, String)> = oauth_params
+        .iter()
+        .map(|(k, v)| (encode(k), encode(v)))
+        .chain(
+            params
+                .iter()
+                .filter(|(k, _)| !k.starts_with("oauth_"))
+                .map(|(k, v)| (encode(k), encode(v))),
+        )
+        .chain(url.query_pairs().map(|(k, v)| (encode(&k), encode(&v))))
+        .collect();
+    signature_params.sort();
+    let signature_params = signature_params
+        .iter()
+        .map(|(k, v)| format!("{}={}", k, v))
+        .collect::<Vec<_>>()
+        .join("&");
+
+    let mut base_url = url.clone();
+    base_url.set_query(None);
+    base_url.set_fragment(None);
+    let base_string = format!(
+        "{}&{}&{}",
+        method.to_uppercase(),
+        encode(base_url.as_str()),
+        encode(&signature_params)
+    );
+    let signing_key = format!(
+        "{}&{}",
+        encode(&consumer.secret),
+        token.map(|t| encode(&t.secret)).unwrap_or_default()
+    );
+
+    let mut mac = Hmac::<Sha1>::new_from_slice(signing_key.as_bytes())
+        .expect("hmac accepts keys of any length");
+    mac.update(base_string.as_bytes());
+    let signature = base64::encode(mac.finalize().into_bytes());
+    oauth_params.push(("oauth_signature", signature));
+
+    let header = oauth_params
+        .iter()
+        .map(|(k, v)| format!("{}=\"{}\"", encode(k), encode(v)))
+        .collect::<Vec<_>>()
+        .join(", ");
+    format!("OAuth {}", header)
+}
@@ -0,0 +1,141 @@
+use crate::api;
+use crate::logging;
+use social_auth_types::{
+    Actor, AuditEntry, AuditResponse, CheckAvailResponse, ErrorCode, ErrorResponse, Outcome, PostTweetRequest,
+    RefreshStatus, RefreshStatusResponse, ServiceStatus, StatusResponse, StringResponse, TokenStatus,
+    TwitchAdJson, TwitchUpdateRequest, UpstreamErrorResponse,
+};
+use rocket::response::content;
+use rocket::serde::json::Json;
+use utoipa::openapi::security::{ApiKey, ApiKeyValue, SecurityScheme};
+use utoipa::openapi::{Content, Ref, RefOr, ResponseBuilder, Schema};
+use utoipa::{Modify, OpenApi};
+
+/// RapiDoc pointed at the document below, the script is vendored in public/
+const DOCS_PAGE: &str = include_str!("api_docs.html");
+
+/// errors every route can answer with, the routes only list the ones specific to them
+const COMMON_ERRORS: &[(&str, &str)] = &[
+    ("401", "no api key was sent or it doesn't exist"),
+    ("403", "the api key is missing the scope of the route"),
+    ("429", "the api key exceeded its rate limit"),
+    ("500", "something went wrong on our side"),
+];
+
+#[derive(OpenApi)]
+#[openapi(
+    paths(
+        api::check_avail,
+        api::get_status,
+        api::get_twitch_info,
+        api::disconnect_account,
+        api::post_tweet,
+        api::twitch_game_to_id,
+        api::twitch_update,
+        api::twitch_commercial,
+        api::twitch_refresh_status,
+        api::get_audit,
+    ),
+    components(schemas(
+        StringResponse,
+        StatusResponse,
+        AuditResponse,
+        RefreshStatusResponse,
+        CheckAvailResponse,
+        ServiceStatus,
+        TokenStatus,
+        RefreshStatus,
+        PostTweetRequest,
+        TwitchUpdateRequest,
+        TwitchAdJson,
+        AuditEntry,
+        Actor,
+        Outcome,
+        ErrorResponse,
+        ErrorCode,
+        UpstreamErrorResponse,
+    )),
+    modifiers(&ApiDocAddon),
+    tags(
+        (name = "accounts", description = "connected accounts and their tokens"),
+        (name = "twitch"),
+        (name = "twitter"),
+        (name = "audit", description = "the log of privileged actions"),
+    ),
+)]
+struct ApiDoc;
+
+/// the parts utoipa can't derive
+struct ApiDocAddon;
+
+impl Modify for ApiDocAddon {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        openapi.info.description = Some(
+            "Tokens of the connected Twitch and Twitter accounts and actions done with them.".to_string(),
+        );
+        // the manifest has no license so utoipa would put an empty one here
+        openapi.info.license = None;
+
+        let components = openapi.components.get_or_insert_with(Default::default);
+        components.add_security_scheme(
+            "api_key",
+            SecurityScheme::ApiKey(ApiKey::Header(ApiKeyValue::with_description(
+                "Authorization",
+                "an api key created on the dashboard, `<id>.<secret>`",
+            ))),
+        );
+
+        // the codes are what clients match on so every one of them is listed
+        if let Some(RefOr::T(Schema::Object(schema))) = components.schemas.get_mut("ErrorCode") {
+            let codes: Vec<String> = ErrorCode::ALL
+                .iter()
+                .map(|code| format!("- `{}` ({}): {}", code.name(), code.status(), code.description()))
+                .collect();
+            schema.description = Some(format!("Machine readable error code, they don't change.\n\n{}", codes.join("\n")));
+            // `unknown` only exists for clients
+            schema.enum_values = Some(ErrorCode::ALL.iter().map(|code| code.name().into()).collect());
+        }
+
+        for path in openapi.paths.paths.values_mut() {
+            for operation in path.operations.values_mut() {
+                for (status, description) in COMMON_ERRORS {
+                    operation
+                        .responses
+                        .responses
+                        .entry(status.to_string())
+                        .or_insert_with(|| error_response(description));
+                }
+            }
+        }
+    }
+}
+
+fn error_response(description: &str) -> RefOr<utoipa::openapi::Response> {
+    RefOr::T(
+        ResponseBuilder::new()
+            .description(description)
+            .content("application/json", Content::new(Ref::from_schema_name("ErrorResponse")))
+            .build(),
+    )
+}
+
+/// the OpenAPI 3 document of `/api/v1`
+pub fn document() -> utoipa::openapi::OpenApi {
+    ApiDoc::openapi()
+}
+
+#[get("/openapi.json")]
+fn openapi_json() -> Json<utoipa::openapi::OpenApi> {
+    Json(document())
+}
+
+#[get("/docs")]
+fn docs() -> content::Html<&'static str> {
+    content::Html(DOCS_PAGE)
+}
+
+pub fn stage() -> rocket::fairing::AdHoc {
+    rocket::fairing::AdHoc::on_ignite("openapi", |rocket| async {
+        rocket.mount("/api/v1", logging::traced(routes![openapi_json, docs]))
+    })
+}
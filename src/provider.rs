@@ -0,0 +1,293 @@
+use crate::error::Error;
+use crate::logging;
+use crate::audit::{Actor, AuditLog};
+use crate::sessions::Admin;
+use crate::templates::gen_random_string;
+use rocket::{response::Redirect, serde::Serialize, State};
+use std::time::{Duration, Instant};
+use std::{collections::HashMap, sync::Arc};
+use tokio::sync::Mutex;
+
+pub use social_auth_types::TokenStatus;
+
+/// how long the user has to finish authorizing us after starting the flow
+const STATE_LIFETIME: Duration = Duration::from_secs(10 * 60);
+
+/// A permission that can be requested when connecting an account.
+#[derive(Debug, Clone, Serialize)]
+pub struct OAuthScope {
+    pub name: &'static str,
+    pub description: &'static str,
+    /// what the scope is needed for, scopes are grouped by this on the dashboard
+    pub feature: &'static str,
+    /// requested if the user didn't pick any scopes
+    pub default: bool,
+}
+
+/// A social service we can obtain and hand out tokens for.
+///
+/// Every service can have several connected accounts, each identified by the lowercase
+/// login of the user that authorized us. Implementors keep their tokens in memory and
+/// take care of persisting them, the generic routes and the api only ever talk to
+/// services through this trait.
+#[rocket::async_trait]
+pub trait SocialProvider: Send + Sync {
+    /// short lowercase identifier, used in routes and as the key in api responses
+    fn name(&self) -> &'static str;
+
+    /// name shown on the dashboard
+    fn display_name(&self) -> &'static str;
+
+    /// scopes the user can choose from when connecting an account, empty if the service has none
+    fn scopes(&self) -> &'static [OAuthScope] {
+        &[]
+    }
+
+    /// url the user has to visit to grant us access, `state` has to come back as a query
+    /// parameter of the callback. `scopes` are names from `scopes`, the defaults are used if it is empty
+    async fn authorize_url(&self, state: &str, scopes: &[String]) -> Result<String, Error>;
+
+    /// exchanges the query parameters of the authorize callback for a token and saves it,
+    /// returns the account that was connected. `state` was already checked at this point
+    async fn authorize_callback(&self, params: &HashMap<String, String>) -> Result<String, Error>;
+
+    /// loads all previously saved tokens into memory
+    async fn load_tokens(&self) -> Result<(), Error>;
+
+    /// moves the token saved before multiple accounts were supported to the account it belongs to
+    async fn migrate_legacy_token(&self) -> Result<(), Error>;
+
+    /// persists the token of `account` currently held in memory
+    async fn save_token(&self, account: &str) -> Result<(), Error>;
+
+    /// the token of `account` serialized as json, None if the account isn't connected
+    async fn token_json(&self, account: &str) -> Result<Option<Vec<u8>>, Error>;
+
+    /// makes sure the token of `account` is still usable, refreshing it if the service supports that
+    async fn refresh(&self, account: &str) -> Result<(), Error>;
+
+    /// gets a new token for `account` even if the current one is still fine,
+    /// services whose tokens don't expire just check that the account is connected
+    async fn force_refresh(&self, account: &str) -> Result<(), Error> {
+        self.select_account(Some(account)).await?;
+        self.refresh(account).await
+    }
+
+    /// all connected accounts, sorted
+    async fn accounts(&self) -> Vec<String>;
+
+    /// the scopes `account` granted us
+    async fn granted_scopes(&self, _account: &str) -> Vec<String> {
+        vec![]
+    }
+
+    /// invalidates the token of `account` with the service and forgets it
+    async fn revoke(&self, account: &str) -> Result<(), Error>;
+
+    /// asks the service whether the token of `account` still works, doesn't refresh it
+    async fn check(&self, account: &str) -> TokenStatus;
+
+    /// picks the account a request should use.
+    ///
+    /// if no account was requested this only succeeds if exactly one account is connected
+    async fn select_account(&self, account: Option<&str>) -> Result<String, Error> {
+        let accounts = self.accounts().await;
+        match account {
+            Some(account) => {
+                let account = account.to_lowercase();
+                if accounts.contains(&account) {
+                    Ok(account)
+                } else {
+                    Err(Error::new_account_not_connected(format!(
+                        "{} account {} is not connected",
+                        self.name(),
+                        account
+                    )))
+                }
+            }
+            None if accounts.len() > 1 => Err(Error::new_bad_request(format!(
+                "multiple {} accounts are connected, specify one with account",
+                self.name()
+            ))),
+            None => accounts
+                .into_iter()
+                .next()
+                .ok_or_else(|| Error::new_auth_not_avail(self.name())),
+        }
+    }
+}
+
+/// key a token of `account` is saved under in the token store
+pub fn store_key(service: &str, account: &str) -> String {
+    format!("{}_auth.{}", service, account)
+}
+
+/// the account of a token store key created by `store_key`
+pub fn account_from_store_key<'a>(service: &str, key: &'a str) -> Option<&'a str> {
+    key.strip_prefix(service)?.strip_prefix("_auth.")
+}
+
+/// key the single token was saved under before multiple accounts were supported
+pub fn legacy_store_key(service: &str) -> String {
+    format!("{}_auth", service)
+}
+
+/// All providers the server knows about, in the order they are shown on the dashboard.
+#[derive(Default)]
+pub struct Providers {
+    providers: Vec<Arc<dyn SocialProvider>>,
+}
+
+impl Providers {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn register(mut self, provider: Arc<dyn SocialProvider>) -> Self {
+        self.providers.push(provider);
+        self
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Arc<dyn SocialProvider>> {
+        self.providers.iter().find(|p| p.name() == name)
+    }
+
+    /// same as `get` but returns an error that can be handed straight to the client
+    pub fn get_or_err(&self, name: &str) -> Result<&Arc<dyn SocialProvider>, Error> {
+        self.get(name)
+            .ok_or_else(|| Error::new_not_found(format!("unknown service {}", name)))
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Arc<dyn SocialProvider>> {
+        self.providers.iter()
+    }
+
+    pub async fn load_tokens(&self) -> Result<(), Error> {
+        for provider in self.iter() {
+            provider.load_tokens().await?;
+        }
+
+        Ok(())
+    }
+
+    /// a service being down or a revoked token must not keep the server from starting,
+    /// the legacy token is kept and migrating it is tried again on the next start
+    pub async fn migrate_legacy_tokens(&self) {
+        for provider in self.iter() {
+            if let Err(e) = provider.migrate_legacy_token().await {
+                tracing::warn!(service = provider.name(), error = %e.message(), "unable to migrate the saved token");
+            }
+        }
+    }
+}
+
+struct PendingAuthorization {
+    service: String,
+    session: String,
+    started: Instant,
+}
+
+/// The `state` values of authorizations that were started but haven't come back yet.
+///
+/// Each one is bound to the login session that started it so a callback url with someone
+/// else's code can't be used to connect their account.
+#[derive(Default)]
+pub struct OAuthStates {
+    pending: Mutex<HashMap<String, PendingAuthorization>>,
+}
+
+impl OAuthStates {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub async fn create(&self, service: &str, session: &str) -> String {
+        let mut pending = self.pending.lock().await;
+        pending.retain(|_, p| p.started.elapsed() < STATE_LIFETIME);
+
+        let state = gen_random_string(32);
+        pending.insert(
+            state.clone(),
+            PendingAuthorization {
+                service: service.to_string(),
+                session: session.to_string(),
+                started: Instant::now(),
+            },
+        );
+        state
+    }
+
+    /// checks `state` came from an authorization of `service` started by `session`, every state can only be used once
+    pub async fn verify(&self, state: Option<&str>, service: &str, session: &str) -> Result<(), Error> {
+        let pending = match state {
+            Some(state) => self.pending.lock().await.remove(state),
+            None => None,
+        };
+
+        match pending {
+            Some(p) if p.service == service && p.session == session && p.started.elapsed() < STATE_LIFETIME => Ok(()),
+            _ => Err(Error::new_bad_request(
+                "invalid or expired oauth state, start connecting the account again".to_string(),
+            )),
+        }
+    }
+}
+
+#[get("/<service>/authorize?<scopes>")]
+async fn authorize(
+    providers: &State<Providers>,
+    states: &State<OAuthStates>,
+    admin: Admin,
+    service: &str,
+    scopes: Vec<String>,
+) -> Result<Redirect, Error> {
+    let provider = providers.get_or_err(service)?;
+    let state = states.create(service, admin.0.session()).await;
+    let redirect_url = provider.authorize_url(&state, &scopes).await?;
+    Ok(Redirect::to(redirect_url))
+}
+
+#[get("/<service>/authorize/callback?<params..>")]
+async fn authorize_callback(
+    providers: &State<Providers>,
+    states: &State<OAuthStates>,
+    audit: &State<AuditLog>,
+    admin: Admin,
+    service: &str,
+    params: HashMap<String, String>,
+) -> Result<Redirect, Error> {
+    let provider = providers.get_or_err(service)?;
+    states
+        .verify(params.get("state").map(String::as_str), service, admin.0.session())
+        .await?;
+    let result = provider.authorize_callback(&params).await;
+    let account = result.as_ref().ok().map(String::as_str);
+    audit.record(Actor::user(admin.0.username()), "connect_account", Some(service), account, &result).await;
+    let account = result?;
+    tracing::info!(user = admin.0.username(), service, %account, "connected account");
+    Ok(Redirect::to("/"))
+}
+
+// html forms can't send DELETE requests
+#[post("/<service>/accounts/<account>/disconnect")]
+async fn disconnect(
+    providers: &State<Providers>,
+    audit: &State<AuditLog>,
+    admin: Admin,
+    service: &str,
+    account: &str,
+) -> Result<Redirect, Error> {
+    let provider = providers.get_or_err(service)?;
+    let account = provider.select_account(Some(account)).await?;
+    let result = provider.revoke(&account).await;
+    audit.record(Actor::user(admin.0.username()), "disconnect_account", Some(service), Some(&account), &result).await;
+    result?;
+    tracing::info!(user = admin.0.username(), service, %account, "disconnected account");
+    Ok(Redirect::to("/"))
+}
+
+pub fn stage() -> rocket::fairing::AdHoc {
+    rocket::fairing::AdHoc::on_ignite("providers", |rocket| async {
+        rocket.mount("/", logging::traced(routes![authorize, authorize_callback, disconnect]))
+    })
+}
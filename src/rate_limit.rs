@@ -0,0 +1,143 @@
+use std::collections::{HashMap, VecDeque};
+use std::net::IpAddr;
+use std::time::{Duration, Instant};
+use tokio::sync::Mutex;
+
+/// the first lockout after the free attempts are used up, it doubles with every further failure
+const LOGIN_BASE_LOCKOUT: Duration = Duration::from_secs(1);
+const LOGIN_MAX_LOCKOUT: Duration = Duration::from_secs(15 * 60);
+/// failed attempts of an ip are forgotten after this long without another failure
+const LOGIN_FAILURE_RESET: Duration = Duration::from_secs(15 * 60);
+const LOGIN_GLOBAL_WINDOW: Duration = Duration::from_secs(60);
+
+struct LoginFailures {
+    count: u32,
+    last_failure: Instant,
+    locked_until: Option<Instant>,
+}
+
+struct LoginAttemptsInner {
+    ips: HashMap<Option<IpAddr>, LoginFailures>,
+    /// failures of all ips within the last `LOGIN_GLOBAL_WINDOW`
+    recent: VecDeque<Instant>,
+    global_locked_until: Option<Instant>,
+}
+
+/// Failed logins of the dashboard, per ip and across all ips.
+///
+/// An ip gets `per_ip` attempts, after that every failure locks it out for twice as long as
+/// the one before. When `global` logins fail within a minute nobody can log in
+/// for the rest of that minute. A limit of 0 turns that check off.
+pub struct LoginAttempts {
+    inner: Mutex<LoginAttemptsInner>,
+    per_ip: u32,
+    global: u32,
+}
+
+fn remaining(until: Option<Instant>, now: Instant) -> Option<Duration> {
+    until.filter(|until| *until > now).map(|until| until - now)
+}
+
+impl LoginAttempts {
+    pub fn new(per_ip: u32, global: u32) -> Self {
+        LoginAttempts {
+            inner: Mutex::new(LoginAttemptsInner {
+                ips: HashMap::new(),
+                recent: VecDeque::new(),
+                global_locked_until: None,
+            }),
+            per_ip,
+            global,
+        }
+    }
+
+    /// how long `ip` has to wait before it may try again, `None` if it may try now
+    pub async fn locked(&self, ip: Option<IpAddr>) -> Option<Duration> {
+        let inner = self.inner.lock().await;
+        let now = Instant::now();
+        let ip_lock = inner.ips.get(&ip).and_then(|failures| remaining(failures.locked_until, now));
+        let global_lock = remaining(inner.global_locked_until, now);
+        ip_lock.max(global_lock)
+    }
+
+    pub async fn failure(&self, ip: Option<IpAddr>) {
+        let mut inner = self.inner.lock().await;
+        let now = Instant::now();
+        inner
+            .ips
+            .retain(|_, failures| now - failures.last_failure < LOGIN_FAILURE_RESET);
+
+        let failures = inner.ips.entry(ip).or_insert(LoginFailures {
+            count: 0,
+            last_failure: now,
+            locked_until: None,
+        });
+        failures.count += 1;
+        failures.last_failure = now;
+        if self.per_ip > 0 && failures.count >= self.per_ip {
+            let doublings = (failures.count - self.per_ip).min(16);
+            let lockout = (LOGIN_BASE_LOCKOUT * 2u32.pow(doublings)).min(LOGIN_MAX_LOCKOUT);
+            failures.locked_until = Some(now + lockout);
+        }
+
+        while matches!(inner.recent.front(), Some(failure) if now - *failure >= LOGIN_GLOBAL_WINDOW) {
+            inner.recent.pop_front();
+        }
+        inner.recent.push_back(now);
+        if self.global > 0 && inner.recent.len() as u32 >= self.global {
+            tracing::warn!(failures = inner.recent.len(), "too many failed logins within a minute, locking the dashboard");
+            inner.global_locked_until = Some(now + LOGIN_GLOBAL_WINDOW);
+        }
+    }
+
+    pub async fn success(&self, ip: Option<IpAddr>) {
+        self.inner.lock().await.ips.remove(&ip);
+    }
+}
+
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+/// Per api key request limits, a token bucket that holds up to a minute worth of requests.
+pub struct RateLimiter {
+    buckets: Mutex<HashMap<String, Bucket>>,
+    /// requests per minute for keys without their own limit, 0 means unlimited
+    default_limit: u32,
+}
+
+impl RateLimiter {
+    pub fn new(default_limit: u32) -> Self {
+        RateLimiter {
+            buckets: Mutex::new(HashMap::new()),
+            default_limit,
+        }
+    }
+
+    /// takes a request from the bucket of `key_id`, if it is empty returns how long until the next request is allowed
+    pub async fn check(&self, key_id: &str, limit: Option<u32>) -> Result<(), Duration> {
+        let limit = limit.unwrap_or(self.default_limit);
+        if limit == 0 {
+            return Ok(());
+        }
+
+        let capacity = f64::from(limit);
+        let per_second = capacity / 60.0;
+        let now = Instant::now();
+        let mut buckets = self.buckets.lock().await;
+        let bucket = buckets.entry(key_id.to_string()).or_insert(Bucket {
+            tokens: capacity,
+            updated: now,
+        });
+        bucket.tokens = (bucket.tokens + (now - bucket.updated).as_secs_f64() * per_second).min(capacity);
+        bucket.updated = now;
+
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(Duration::from_secs_f64((1.0 - bucket.tokens) / per_second))
+        }
+    }
+}
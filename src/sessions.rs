@@ -0,0 +1,257 @@
+use crate::templates::gen_random_string;
+use crate::users::{Role, Users};
+use rocket::http::{Cookie, SameSite, Status};
+use rocket::request::{self, FromRequest, Request};
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::Mutex;
+
+pub const COOKIE_NAME: &str = "session";
+/// the login that still needs the second factor
+pub const PENDING_COOKIE_NAME: &str = "pending_login";
+const PURGE_INTERVAL: Duration = Duration::from_secs(5 * 60);
+/// how long the second factor can be entered after the password
+const PENDING_LIFETIME: Duration = Duration::from_secs(5 * 60);
+
+struct Session {
+    username: String,
+    created: Instant,
+    last_seen: Instant,
+}
+
+struct PendingLogin {
+    username: String,
+    created: Instant,
+}
+
+/// Login sessions of the dashboard.
+///
+/// A session ends when it wasn't used for `idle_timeout` or when it is older than
+/// `absolute_timeout`, whichever comes first.
+pub struct Sessions {
+    sessions: Mutex<HashMap<String, Session>>,
+    /// logins that passed the password check but not the second factor yet
+    pending: Mutex<HashMap<String, PendingLogin>>,
+    idle_timeout: Duration,
+    absolute_timeout: Duration,
+    secure_cookie: bool,
+}
+
+impl Sessions {
+    pub fn new(idle_timeout: Duration, absolute_timeout: Duration, secure_cookie: bool) -> Self {
+        Sessions {
+            sessions: Mutex::new(HashMap::new()),
+            pending: Mutex::new(HashMap::new()),
+            idle_timeout,
+            absolute_timeout,
+            secure_cookie,
+        }
+    }
+
+    /// starts a new session of `username` and returns the cookie for it
+    pub async fn create(&self, username: &str) -> Cookie<'static> {
+        let token = gen_random_string(30);
+        let now = Instant::now();
+        self.sessions.lock().await.insert(
+            token.clone(),
+            Session {
+                username: username.to_string(),
+                created: now,
+                last_seen: now,
+            },
+        );
+
+        self.cookie(COOKIE_NAME, token)
+    }
+
+    fn cookie(&self, name: &'static str, token: String) -> Cookie<'static> {
+        // lax so the cookie is still sent when twitch or twitter redirect back to us,
+        // no max age since the expiry is enforced here and not by the browser
+        Cookie::build(name, token)
+            .same_site(SameSite::Lax)
+            .http_only(true)
+            .secure(self.secure_cookie)
+            .finish()
+    }
+
+    /// remembers that `username` entered the right password and returns the cookie for the second step
+    pub async fn create_pending(&self, username: &str) -> Cookie<'static> {
+        let token = gen_random_string(30);
+        self.pending.lock().await.insert(
+            token.clone(),
+            PendingLogin {
+                username: username.to_string(),
+                created: Instant::now(),
+            },
+        );
+
+        self.cookie(PENDING_COOKIE_NAME, token)
+    }
+
+    /// the user of a pending login that hasn't expired
+    pub async fn pending_user(&self, token: &str) -> Option<String> {
+        self.pending
+            .lock()
+            .await
+            .get(token)
+            .filter(|pending| pending.created.elapsed() < PENDING_LIFETIME)
+            .map(|pending| pending.username.clone())
+    }
+
+    pub async fn remove_pending(&self, token: &str) {
+        self.pending.lock().await.remove(token);
+    }
+
+    fn is_expired(&self, session: &Session) -> bool {
+        session.last_seen.elapsed() >= self.idle_timeout
+            || session.created.elapsed() >= self.absolute_timeout
+    }
+
+    /// marks the session of `token` as used and returns its user, `None` if the session doesn't exist or expired
+    pub async fn touch(&self, token: &str) -> Option<String> {
+        let mut sessions = self.sessions.lock().await;
+        match sessions.get_mut(token) {
+            Some(session) if !self.is_expired(session) => {
+                session.last_seen = Instant::now();
+                Some(session.username.clone())
+            }
+            Some(_) => {
+                sessions.remove(token);
+                None
+            }
+            None => None,
+        }
+    }
+
+    pub async fn remove(&self, token: &str) {
+        self.sessions.lock().await.remove(token);
+    }
+
+    /// ends every session of `username`, e.g. when the user was deleted
+    pub async fn remove_user(&self, username: &str) {
+        self.sessions
+            .lock()
+            .await
+            .retain(|_, session| session.username != username);
+    }
+
+    /// how many sessions didn't expire yet
+    pub async fn active(&self) -> usize {
+        self.sessions
+            .lock()
+            .await
+            .values()
+            .filter(|session| !self.is_expired(session))
+            .count()
+    }
+
+    /// forgets all expired sessions and pending logins, returns how many sessions there were
+    pub async fn purge(&self) -> usize {
+        self.pending
+            .lock()
+            .await
+            .retain(|_, pending| pending.created.elapsed() < PENDING_LIFETIME);
+
+        let mut sessions = self.sessions.lock().await;
+        let before = sessions.len();
+        sessions.retain(|_, session| !self.is_expired(session));
+        before - sessions.len()
+    }
+
+    /// purges expired sessions every few minutes, runs forever
+    pub async fn purge_task(self: Arc<Self>) {
+        let mut interval = tokio::time::interval(PURGE_INTERVAL);
+        loop {
+            interval.tick().await;
+            let purged = self.purge().await;
+            if purged > 0 {
+                tracing::debug!(purged, "purged expired sessions");
+            }
+        }
+    }
+}
+
+pub fn stage() -> rocket::fairing::AdHoc {
+    rocket::fairing::AdHoc::on_liftoff("session purge", |rocket| {
+        Box::pin(async move {
+            let sessions = rocket
+                .state::<Arc<Sessions>>()
+                .expect("sessions state is not being managed")
+                .clone();
+            tokio::spawn(sessions.purge_task());
+        })
+    })
+}
+
+/// A logged in user of the dashboard.
+pub struct Authenticated {
+    session: String,
+    username: String,
+    role: Role,
+}
+
+impl Authenticated {
+    /// the token of the login session the request belongs to
+    pub fn session(&self) -> &str {
+        &self.session
+    }
+
+    pub fn username(&self) -> &str {
+        &self.username
+    }
+
+    pub fn role(&self) -> Role {
+        self.role
+    }
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for Authenticated {
+    type Error = std::convert::Infallible;
+
+    async fn from_request(
+        request: &'r Request<'_>,
+    ) -> request::Outcome<Authenticated, Self::Error> {
+        if let Some(cookie) = request.cookies().get_private(COOKIE_NAME) {
+            let sessions = request
+                .rocket()
+                .state::<Arc<Sessions>>()
+                .expect("sessions state is not being managed");
+            let users = request
+                .rocket()
+                .state::<Users>()
+                .expect("users state is not being managed");
+            // the user is looked up on every request so role changes and deletions apply right away
+            if let Some(username) = sessions.touch(cookie.value()).await {
+                if let Some(user) = users.get(&username).await {
+                    return request::Outcome::Success(Authenticated {
+                        session: cookie.value().to_string(),
+                        username: user.username,
+                        role: user.role,
+                    });
+                }
+            }
+        };
+
+        request::Outcome::Forward(())
+    }
+}
+
+/// A logged in user with the admin role, other users get a 403.
+pub struct Admin(pub Authenticated);
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for Admin {
+    type Error = ();
+
+    async fn from_request(request: &'r Request<'_>) -> request::Outcome<Admin, Self::Error> {
+        match request.guard::<Authenticated>().await {
+            request::Outcome::Success(authenticated) if authenticated.role() == Role::Admin => {
+                request::Outcome::Success(Admin(authenticated))
+            }
+            request::Outcome::Success(_) => request::Outcome::Failure((Status::Forbidden, ())),
+            _ => request::Outcome::Forward(()),
+        }
+    }
+}
@@ -0,0 +1,253 @@
+use crate::error::Error;
+use fs2::FileExt;
+use rusqlite::{params, Connection, OptionalExtension};
+use std::{
+    fs::{File, OpenOptions},
+    io::{ErrorKind, Write},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+use tokio::task;
+
+/// Persistent storage for the tokens we obtained from the social services.
+///
+/// Values are opaque bytes, it's up to the providers how they serialize their tokens.
+#[rocket::async_trait]
+pub trait TokenStore: Send + Sync {
+    async fn load(&self, key: &str) -> Result<Option<Vec<u8>>, Error>;
+
+    /// replaces the value for `key`, readers never see a partially written value
+    async fn save(&self, key: &str, value: &[u8]) -> Result<(), Error>;
+
+    /// removes the value for `key`, does nothing if there is none
+    async fn delete(&self, key: &str) -> Result<(), Error>;
+
+    /// every key that currently has a value
+    async fn keys(&self) -> Result<Vec<String>, Error>;
+}
+
+/// a directory of its own, every `*.json` file in it is taken for a token
+const DEFAULT_FS_DIR: &str = "tokens";
+const DEFAULT_SQLITE_PATH: &str = "social_auth.db";
+/// where tokens were saved before the store existed, relative to the working directory
+const LEGACY_FILES: [&str; 2] = ["twitch_auth.json", "twitter_auth.json"];
+
+/// Builds the store selected with `TOKEN_STORE` (`fs` or `sqlite`).
+///
+/// `path` is the directory for the `fs` store and the database file for the `sqlite` store.
+pub fn from_config(kind: &str, path: Option<String>) -> Result<Arc<dyn TokenStore>, Error> {
+    match kind {
+        "fs" => match path {
+            Some(path) => Ok(Arc::new(FsTokenStore::new(path)?)),
+            None => {
+                let store = FsTokenStore::new(DEFAULT_FS_DIR)?;
+                move_legacy_files(&store.dir)?;
+                Ok(Arc::new(store))
+            }
+        },
+        "sqlite" => Ok(Arc::new(SqliteTokenStore::open(
+            path.unwrap_or_else(|| String::from(DEFAULT_SQLITE_PATH)),
+        )?)),
+        _ => Err(Error::new_internal_server_error(format!(
+            "unknown token store {}",
+            kind
+        ))),
+    }
+}
+
+/// moves the tokens saved in the working directory into the default store, where they are migrated
+fn move_legacy_files(dir: &Path) -> Result<(), Error> {
+    for name in LEGACY_FILES {
+        let target = dir.join(name);
+        if Path::new(name).is_file() && !target.exists() {
+            tracing::info!(file = name, dir = %dir.display(), "moving the saved token into the token store");
+            std::fs::rename(name, target)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// the lock file next to the store `from_config` opens
+pub fn lock_path(kind: &str, path: Option<&str>) -> PathBuf {
+    match kind {
+        "sqlite" => PathBuf::from(format!("{}.lock", path.unwrap_or(DEFAULT_SQLITE_PATH))),
+        _ => Path::new(path.unwrap_or(DEFAULT_FS_DIR)).join("server.lock"),
+    }
+}
+
+/// Held by the server while it runs, the tokens it loaded would be outdated if
+/// `social_auth-admin` changed them.
+///
+/// The operating system releases it when the process exits, even if it crashed.
+pub struct StoreLock {
+    _file: File,
+}
+
+impl StoreLock {
+    /// `None` if the lock is held already
+    pub fn try_acquire(path: &Path) -> Result<Option<Self>, Error> {
+        // the lock is taken before the store is opened, which would create the directory
+        if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            std::fs::create_dir_all(dir)?;
+        }
+        let file = OpenOptions::new().create(true).write(true).truncate(false).open(path)?;
+        match file.try_lock_exclusive() {
+            Ok(()) => Ok(Some(StoreLock { _file: file })),
+            Err(e) if e.kind() == fs2::lock_contended_error().kind() => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+}
+
+/// Stores every value in `{dir}/{key}.json`.
+pub struct FsTokenStore {
+    dir: PathBuf,
+}
+
+impl FsTokenStore {
+    pub fn new<P: Into<PathBuf>>(dir: P) -> Result<Self, Error> {
+        let dir = dir.into();
+        std::fs::create_dir_all(&dir)?;
+        Ok(FsTokenStore { dir })
+    }
+
+    fn path(&self, key: &str) -> PathBuf {
+        self.dir.join(format!("{}.json", key))
+    }
+}
+
+#[rocket::async_trait]
+impl TokenStore for FsTokenStore {
+    async fn load(&self, key: &str) -> Result<Option<Vec<u8>>, Error> {
+        match tokio::fs::read(self.path(key)).await {
+            Ok(bytes) => Ok(Some(bytes)),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    async fn save(&self, key: &str, value: &[u8]) -> Result<(), Error> {
+        let path = self.path(key);
+        let tmp_path = self.dir.join(format!(".{}.json.tmp", key));
+        let value = value.to_vec();
+
+        // write to a temporary file first and rename it over the old one so a crash
+        // or a concurrent reader never ends up with half a token
+        task::spawn_blocking(move || -> std::io::Result<()> {
+            let mut options = std::fs::OpenOptions::new();
+            options.write(true).create(true).truncate(true);
+            #[cfg(unix)]
+            {
+                use std::os::unix::fs::OpenOptionsExt;
+                options.mode(0o600);
+            }
+
+            let mut file = options.open(&tmp_path)?;
+            file.write_all(&value)?;
+            file.sync_all()?;
+            std::fs::rename(&tmp_path, &path)
+        })
+        .await??;
+
+        Ok(())
+    }
+
+    async fn delete(&self, key: &str) -> Result<(), Error> {
+        match tokio::fs::remove_file(self.path(key)).await {
+            Err(e) if e.kind() != ErrorKind::NotFound => Err(e.into()),
+            _ => Ok(()),
+        }
+    }
+
+    async fn keys(&self) -> Result<Vec<String>, Error> {
+        let mut keys = Vec::new();
+        let mut entries = tokio::fs::read_dir(&self.dir).await?;
+        while let Some(entry) = entries.next_entry().await? {
+            let name = entry.file_name();
+            let name = name.to_string_lossy();
+            // temporary files of unfinished writes start with a dot
+            if let Some(key) = name.strip_suffix(".json") {
+                if !key.starts_with('.') && entry.file_type().await?.is_file() {
+                    keys.push(key.to_string());
+                }
+            }
+        }
+
+        Ok(keys)
+    }
+}
+
+/// Stores all values in a single table of an embedded sqlite database.
+pub struct SqliteTokenStore {
+    conn: Arc<Mutex<Connection>>,
+}
+
+impl SqliteTokenStore {
+    pub fn open<P: Into<PathBuf>>(path: P) -> Result<Self, Error> {
+        let conn = Connection::open(path.into())?;
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS tokens (key TEXT PRIMARY KEY, value BLOB NOT NULL)",
+            [],
+        )?;
+
+        Ok(SqliteTokenStore {
+            conn: Arc::new(Mutex::new(conn)),
+        })
+    }
+
+    /// runs `f` on the blocking thread pool since rusqlite is synchronous
+    async fn with_conn<F, T>(&self, f: F) -> Result<T, Error>
+    where
+        F: FnOnce(&Connection) -> rusqlite::Result<T> + Send + 'static,
+        T: Send + 'static,
+    {
+        let conn = self.conn.clone();
+        task::spawn_blocking(move || -> Result<T, Error> { Ok(f(&*conn.lock()?)?) }).await?
+    }
+}
+
+#[rocket::async_trait]
+impl TokenStore for SqliteTokenStore {
+    async fn load(&self, key: &str) -> Result<Option<Vec<u8>>, Error> {
+        let key = key.to_string();
+        self.with_conn(move |conn| {
+            conn.query_row("SELECT value FROM tokens WHERE key = ?1", params![key], |row| {
+                row.get(0)
+            })
+            .optional()
+        })
+        .await
+    }
+
+    async fn save(&self, key: &str, value: &[u8]) -> Result<(), Error> {
+        let key = key.to_string();
+        let value = value.to_vec();
+        self.with_conn(move |conn| {
+            conn.execute(
+                "INSERT INTO tokens (key, value) VALUES (?1, ?2) ON CONFLICT(key) DO UPDATE SET value = excluded.value",
+                params![key, value],
+            )
+        })
+        .await?;
+
+        Ok(())
+    }
+
+    async fn delete(&self, key: &str) -> Result<(), Error> {
+        let key = key.to_string();
+        self.with_conn(move |conn| conn.execute("DELETE FROM tokens WHERE key = ?1", params![key]))
+            .await?;
+
+        Ok(())
+    }
+
+    async fn keys(&self) -> Result<Vec<String>, Error> {
+        self.with_conn(|conn| {
+            let mut stmt = conn.prepare("SELECT key FROM tokens")?;
+            let keys = stmt.query_map([], |row| row.get(0))?;
+            keys.collect()
+        })
+        .await
+    }
+}
@@ -1,109 +1,178 @@
-use crate::twitch_config::Twitch;
-use crate::twitter_config::Twitter;
+use crate::api_keys::{ApiKeys, Scope};
+use crate::audit::{Actor, AuditEntry, AuditFilter, AuditLog};
+use crate::error::Error;
+use crate::logging;
+use crate::provider::{OAuthScope, Providers};
+use crate::rate_limit::LoginAttempts;
 use rand::distributions::Alphanumeric;
 use rand::{thread_rng, Rng};
+use crate::sessions::{self, Admin, Authenticated, Sessions};
+use crate::totp;
+use crate::users::{Role, UserInfo, Users};
 use rocket::http::Cookie;
 use rocket::{
     form::Form,
     http::CookieJar,
-    request::{self, FromRequest, Request},
-    response::Redirect,
+    request::FlashMessage,
+    response::{Flash, Redirect},
     serde::Serialize,
     State,
 };
 use rocket_dyn_templates::Template;
-use std::path::Path;
-use tokio::sync::Mutex;
+use std::net::IpAddr;
+use std::sync::Arc;
 
 pub fn stage() -> rocket::fairing::AdHoc {
     rocket::fairing::AdHoc::on_ignite("templates", |rocket| async {
         rocket
-            .mount("/", routes![index, index_no_login, login, login_post, login_forward])
+            .mount(
+                "/",
+                logging::traced(routes![index, index_no_login, login, login_post, login_forward, login_totp, login_totp_post, logout, create_api_key, revoke_api_key, create_user, delete_user, totp_settings, enable_totp, disable_totp, audit_log]),
+            )
             .attach(Template::fairing())
     })
 }
 
-pub fn is_twitch_avail() -> bool {
-    Path::new("twitch_auth.json").exists()
-}
-
-pub fn is_twitter_avail() -> bool {
-    Path::new("twitter_auth.json").exists()
+#[derive(Debug, Serialize)]
+pub struct AccountContext {
+    name: String,
+    scopes: Vec<String>,
 }
 
 #[derive(Debug, Serialize)]
-pub struct IndexContext<'a> {
-    creator: String,
-    twitch_url: String,
-    twitter_url: String,
-    twitter_avail: bool,
-    twitch_avail: bool,
-    api_key: &'a str
+pub struct ScopeGroupContext {
+    feature: &'static str,
+    scopes: Vec<&'static OAuthScope>,
 }
 
-pub struct Sessions {
-    sessions: Mutex<Vec<String>>,
-    password: String,
-    api_key: String,
+#[derive(Debug, Serialize)]
+pub struct ProviderContext {
+    name: &'static str,
+    display_name: &'static str,
+    accounts: Vec<AccountContext>,
+    scope_groups: Vec<ScopeGroupContext>,
 }
 
-impl Sessions {
-    pub fn new(password: String) -> Self {
-        Sessions {
-            sessions: Mutex::new(vec![]),
-            password,
-            api_key: gen_random_string(30)
+/// `scopes` grouped by feature, keeping the order they are defined in
+fn group_scopes(scopes: &'static [OAuthScope]) -> Vec<ScopeGroupContext> {
+    let mut groups: Vec<ScopeGroupContext> = Vec::new();
+    for scope in scopes {
+        match groups.iter_mut().find(|group| group.feature == scope.feature) {
+            Some(group) => group.scopes.push(scope),
+            None => groups.push(ScopeGroupContext {
+                feature: scope.feature,
+                scopes: vec![scope],
+            }),
         }
     }
 
-    pub fn api_key(&self) -> &str {
-        &self.api_key
-    }
+    groups
 }
 
-pub struct Authenticated;
-
-#[rocket::async_trait]
-impl<'r> FromRequest<'r> for Authenticated {
-    type Error = std::convert::Infallible;
-
-    async fn from_request(
-        request: &'r Request<'_>,
-    ) -> request::Outcome<Authenticated, Self::Error> {
-        if let Some(session_token) = request.cookies().get("session") {
-            let session_token = session_token.value();
-            let sessions = &request
-                .rocket()
-                .state::<Sessions>()
-                .expect("sessions state is not being managed")
-                .sessions
-                .lock()
-                .await;
-            if sessions.iter().any(|e| e == session_token) {
-                return request::Outcome::Success(Authenticated {});
-            }
-        };
-        request::Outcome::Forward(())
-    }
+#[derive(Debug, Serialize)]
+pub struct ScopeContext {
+    name: &'static str,
+    description: &'static str,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ApiKeyContext {
+    id: String,
+    name: String,
+    scopes: Vec<&'static str>,
+    created_at: u64,
+    last_used: Option<u64>,
+    rate_limit: Option<u32>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct UserContext {
+    username: String,
+    role: &'static str,
+}
+
+#[derive(Debug, Serialize)]
+pub struct IndexContext {
+    creator: String,
+    /// the logged in user
+    user: UserContext,
+    is_admin: bool,
+    providers: Vec<ProviderContext>,
+    scopes: Vec<ScopeContext>,
+    api_keys: Vec<ApiKeyContext>,
+    /// only filled in for admins
+    users: Vec<UserInfo>,
+    roles: Vec<&'static str>,
+    /// a key that was just created, it can't be shown again after this
+    new_api_key: Option<String>,
+    error: Option<String>,
 }
 
 #[get("/", rank = 1)]
 async fn index(
-    twitch: &State<Twitch>,
-    twitter: &State<Twitter>,
-    sessions: &State<Sessions>,
-    _authenticated: Authenticated,
+    providers: &State<Providers>,
+    api_keys: &State<ApiKeys>,
+    users: &State<Users>,
+    flash: Option<FlashMessage<'_>>,
+    cookies: &CookieJar<'_>,
+    authenticated: Authenticated,
 ) -> Template {
+    let is_admin = authenticated.role() == Role::Admin;
+    let mut provider_contexts = Vec::new();
+    for provider in providers.iter() {
+        let mut accounts = Vec::new();
+        for account in provider.accounts().await {
+            accounts.push(AccountContext {
+                scopes: provider.granted_scopes(&account).await,
+                name: account,
+            });
+        }
+
+        provider_contexts.push(ProviderContext {
+            name: provider.name(),
+            display_name: provider.display_name(),
+            accounts,
+            scope_groups: group_scopes(provider.scopes()),
+        });
+    }
+
+    let api_key_contexts = if is_admin { api_keys.list().await } else { Vec::new() }
+        .into_iter()
+        .map(|info| ApiKeyContext {
+            id: info.id,
+            name: info.name,
+            scopes: info.scopes.iter().map(|scope| scope.name()).collect(),
+            created_at: info.created_at,
+            last_used: info.last_used,
+            rate_limit: info.rate_limit,
+        })
+        .collect();
+    // only shown once
+    let new_api_key = cookies.get_private(NEW_API_KEY_COOKIE_NAME).map(|cookie| cookie.value().to_string());
+    if new_api_key.is_some() {
+        cookies.remove_private(Cookie::named(NEW_API_KEY_COOKIE_NAME));
+    }
+
     let context = IndexContext {
         creator: "onestay".to_string(),
-        twitch_url: twitch.get_authorize_url(),
-        twitter_url: twitter
-            .get_authorize_url()
-            .await
-            .expect("can't get twitter url"),
-        twitch_avail: is_twitch_avail(),
-        twitter_avail: is_twitter_avail(),
-        api_key: &sessions.api_key
+        user: UserContext {
+            username: authenticated.username().to_string(),
+            role: authenticated.role().name(),
+        },
+        is_admin,
+        providers: provider_contexts,
+        scopes: Scope::ALL
+            .iter()
+            .map(|scope| ScopeContext {
+                name: scope.name(),
+                description: scope.description(),
+            })
+            .collect(),
+        api_keys: api_key_contexts,
+        users: if is_admin { users.list().await } else { Vec::new() },
+        roles: Role::ALL.iter().map(|role| role.name()).collect(),
+        new_api_key,
+        error: flash.map(|flash| flash.message().to_string()),
     };
     Template::render("index", context)
 }
@@ -125,27 +194,332 @@ async fn login() -> Template {
 
 #[derive(FromForm)]
 struct LoginForm<'r> {
+    username: &'r str,
     password: &'r str,
 }
 
 #[post("/login", data = "<login_form>")]
 async fn login_post(
     login_form: Form<LoginForm<'_>>,
-    sessions: &State<Sessions>,
+    sessions: &State<Arc<Sessions>>,
+    users: &State<Users>,
+    login_attempts: &State<LoginAttempts>,
+    ip: Option<IpAddr>,
     cookies: &CookieJar<'_>,
-) -> Redirect {
-    if login_form.password == sessions.password {
-        let session_token: String = gen_random_string(30);
+) -> Result<Redirect, Error> {
+    // checked before the password so a locked out client learns nothing from trying
+    if let Some(retry_after) = login_attempts.locked(ip).await {
+        return Err(Error::new_too_many_requests(
+            "too many failed logins, try again later".to_string(),
+            retry_after,
+        ));
+    }
+
+    if let Some(user) = users.verify_password(login_form.username, login_form.password).await {
+        // failed attempts are only forgotten after the second factor, otherwise knowing the
+        // password would allow guessing codes forever
+        if user.totp_enabled {
+            cookies.add_private(sessions.create_pending(&user.username).await);
+            return Ok(Redirect::to("/login/totp"));
+        }
+
+        login_attempts.success(ip).await;
+        cookies.add_private(sessions.create(&user.username).await);
+        return Ok(Redirect::to("/"));
+    }
+
+    login_attempts.failure(ip).await;
+    Ok(Redirect::to("/login"))
+}
+
+#[get("/login/totp")]
+async fn login_totp(sessions: &State<Arc<Sessions>>, cookies: &CookieJar<'_>) -> Result<Template, Redirect> {
+    match cookies.get_private(sessions::PENDING_COOKIE_NAME) {
+        Some(cookie) if sessions.pending_user(cookie.value()).await.is_some() => {
+            Ok(Template::render("login_totp", ()))
+        }
+        _ => Err(Redirect::to("/login")),
+    }
+}
+
+#[derive(FromForm)]
+struct TotpForm<'r> {
+    code: &'r str,
+}
+
+#[post("/login/totp", data = "<totp_form>")]
+async fn login_totp_post(
+    totp_form: Form<TotpForm<'_>>,
+    sessions: &State<Arc<Sessions>>,
+    users: &State<Users>,
+    login_attempts: &State<LoginAttempts>,
+    ip: Option<IpAddr>,
+    cookies: &CookieJar<'_>,
+) -> Result<Redirect, Error> {
+    if let Some(retry_after) = login_attempts.locked(ip).await {
+        return Err(Error::new_too_many_requests(
+            "too many failed logins, try again later".to_string(),
+            retry_after,
+        ));
+    }
+
+    let token = match cookies.get_private(sessions::PENDING_COOKIE_NAME) {
+        Some(cookie) => cookie.value().to_string(),
+        None => return Ok(Redirect::to("/login")),
+    };
+    let username = match sessions.pending_user(&token).await {
+        Some(username) => username,
+        None => return Ok(Redirect::to("/login")),
+    };
 
-        cookies.add(Cookie::new("session", session_token.clone()));
-        sessions.sessions.lock().await.push(session_token);
-        return Redirect::to("/");
+    if users.verify_second_factor(&username, totp_form.code).await {
+        login_attempts.success(ip).await;
+        sessions.remove_pending(&token).await;
+        cookies.remove_private(Cookie::named(sessions::PENDING_COOKIE_NAME));
+        cookies.add_private(sessions.create(&username).await);
+        return Ok(Redirect::to("/"));
     }
 
+    login_attempts.failure(ip).await;
+    Ok(Redirect::to("/login/totp"))
+}
+
+#[post("/logout")]
+async fn logout(
+    sessions: &State<Arc<Sessions>>,
+    authenticated: Option<Authenticated>,
+    cookies: &CookieJar<'_>,
+) -> Redirect {
+    if let Some(authenticated) = authenticated {
+        sessions.remove(authenticated.session()).await;
+    }
+    cookies.remove_private(Cookie::named(sessions::COOKIE_NAME));
     Redirect::to("/login")
 }
 
-fn gen_random_string(n: usize) -> String {
+#[derive(FromForm)]
+struct CreateApiKeyForm<'r> {
+    name: &'r str,
+    scopes: Vec<Scope>,
+    /// requests per minute, empty for the default
+    rate_limit: Option<u32>,
+}
+
+/// a key that was just created, in a private cookie since flash messages aren't encrypted
+const NEW_API_KEY_COOKIE_NAME: &str = "new_api_key";
+
+#[post("/api_keys", data = "<form>")]
+async fn create_api_key(
+    form: Form<CreateApiKeyForm<'_>>,
+    api_keys: &State<ApiKeys>,
+    audit: &State<AuditLog>,
+    cookies: &CookieJar<'_>,
+    admin: Admin,
+) -> Result<Redirect, Flash<Redirect>> {
+    if form.name.trim().is_empty() || form.scopes.is_empty() {
+        return Err(Flash::error(
+            Redirect::to("/"),
+            "an api key needs a name and at least one scope",
+        ));
+    }
+
+    let result = api_keys.create(form.name, form.scopes.clone(), form.rate_limit).await;
+    let target = result.as_ref().ok().map(|(info, _)| info.id.as_str());
+    audit.record(Actor::user(admin.0.username()), "create_api_key", None, target, &result).await;
+    let (info, key) = result.map_err(|e| Flash::error(Redirect::to("/"), e.message()))?;
+    tracing::info!(user = admin.0.username(), api_key = %info.id, name = %info.name, "created api key");
+    cookies.add_private(Cookie::new(NEW_API_KEY_COOKIE_NAME, key));
+    Ok(Redirect::to("/"))
+}
+
+// html forms can't send DELETE requests
+#[post("/api_keys/<id>/revoke")]
+async fn revoke_api_key(
+    id: &str,
+    api_keys: &State<ApiKeys>,
+    audit: &State<AuditLog>,
+    admin: Admin,
+) -> Result<Redirect, Error> {
+    let result = api_keys.revoke(id).await;
+    audit.record(Actor::user(admin.0.username()), "revoke_api_key", None, Some(id), &result).await;
+    result?;
+    tracing::info!(user = admin.0.username(), api_key = id, "revoked api key");
+    Ok(Redirect::to("/"))
+}
+
+#[derive(FromForm)]
+struct CreateUserForm<'r> {
+    username: &'r str,
+    password: &'r str,
+    role: Role,
+}
+
+#[post("/users", data = "<form>")]
+async fn create_user(
+    form: Form<CreateUserForm<'_>>,
+    users: &State<Users>,
+    audit: &State<AuditLog>,
+    admin: Admin,
+) -> Result<Redirect, Flash<Redirect>> {
+    let result = users.create(form.username, form.password, form.role).await;
+    audit.record(Actor::user(admin.0.username()), "create_user", None, Some(form.username), &result).await;
+    let user = result.map_err(|e| Flash::error(Redirect::to("/"), e.message()))?;
+    tracing::info!(user = admin.0.username(), username = %user.username, role = user.role.name(), "created user");
+    Ok(Redirect::to("/"))
+}
+
+// html forms can't send DELETE requests
+#[post("/users/<username>/delete")]
+async fn delete_user(
+    username: &str,
+    users: &State<Users>,
+    sessions: &State<Arc<Sessions>>,
+    audit: &State<AuditLog>,
+    admin: Admin,
+) -> Result<Redirect, Flash<Redirect>> {
+    let result = users.delete(username).await;
+    audit.record(Actor::user(admin.0.username()), "delete_user", None, Some(username), &result).await;
+    result.map_err(|e| Flash::error(Redirect::to("/"), e.message()))?;
+    sessions.remove_user(username).await;
+    tracing::info!(user = admin.0.username(), username, "deleted user");
+    Ok(Redirect::to("/"))
+}
+
+/// the secret of a two-factor enrollment that wasn't confirmed yet
+const TOTP_ENROLLMENT_COOKIE_NAME: &str = "totp_enrollment";
+const TOTP_ISSUER: &str = "Marathon Tools Social Auth";
+
+#[derive(Debug, Serialize)]
+pub struct TotpContext {
+    username: String,
+    enabled: bool,
+    /// svg of the otpauth url while enrolling
+    qr_code: Option<String>,
+    secret: Option<String>,
+    /// only shown right after enrolling
+    recovery_codes: Vec<String>,
+    error: Option<String>,
+}
+
+#[get("/totp")]
+async fn totp_settings(
+    authenticated: Authenticated,
+    users: &State<Users>,
+    flash: Option<FlashMessage<'_>>,
+    cookies: &CookieJar<'_>,
+) -> Template {
+    let enabled = matches!(users.get(authenticated.username()).await, Some(user) if user.totp_enabled);
+
+    let (qr_code, secret) = if enabled {
+        (None, None)
+    } else {
+        // keep the secret of an enrollment that is in progress so a wrong code doesn't change the qr code
+        let secret = match cookies.get_private(TOTP_ENROLLMENT_COOKIE_NAME) {
+            Some(cookie) => cookie.value().to_string(),
+            None => {
+                let secret = totp::generate_secret();
+                cookies.add_private(Cookie::new(TOTP_ENROLLMENT_COOKIE_NAME, secret.clone()));
+                secret
+            }
+        };
+        (
+            totp::qr_code_svg(&totp::otpauth_url(TOTP_ISSUER, authenticated.username(), &secret)),
+            Some(secret),
+        )
+    };
+
+    Template::render(
+        "totp",
+        TotpContext {
+            username: authenticated.username().to_string(),
+            enabled,
+            qr_code,
+            secret,
+            recovery_codes: Vec::new(),
+            error: flash.map(|flash| flash.message().to_string()),
+        },
+    )
+}
+
+#[post("/totp/enable", data = "<totp_form>")]
+async fn enable_totp(
+    totp_form: Form<TotpForm<'_>>,
+    authenticated: Authenticated,
+    users: &State<Users>,
+    audit: &State<AuditLog>,
+    cookies: &CookieJar<'_>,
+) -> Result<Template, Flash<Redirect>> {
+    let secret = cookies
+        .get_private(TOTP_ENROLLMENT_COOKIE_NAME)
+        .ok_or_else(|| Flash::error(Redirect::to("/totp"), "the enrollment expired, scan the new qr code"))?;
+    let result = users
+        .enable_totp(authenticated.username(), secret.value(), totp_form.code)
+        .await;
+    let actor = Actor::user(authenticated.username());
+    audit.record(actor, "enable_totp", None, Some(authenticated.username()), &result).await;
+    let recovery_codes = result.map_err(|e| Flash::error(Redirect::to("/totp"), e.message()))?;
+    cookies.remove_private(Cookie::named(TOTP_ENROLLMENT_COOKIE_NAME));
+    tracing::info!(user = authenticated.username(), "enabled two-factor authentication");
+
+    Ok(Template::render(
+        "totp",
+        TotpContext {
+            username: authenticated.username().to_string(),
+            enabled: true,
+            qr_code: None,
+            secret: None,
+            recovery_codes,
+            error: None,
+        },
+    ))
+}
+
+#[post("/totp/disable", data = "<totp_form>")]
+async fn disable_totp(
+    totp_form: Form<TotpForm<'_>>,
+    authenticated: Authenticated,
+    users: &State<Users>,
+    audit: &State<AuditLog>,
+) -> Result<Redirect, Flash<Redirect>> {
+    // a stolen session alone isn't enough to turn it off
+    let result = if users.verify_second_factor(authenticated.username(), totp_form.code).await {
+        users.disable_totp(authenticated.username()).await
+    } else {
+        Err(Error::new_forbidden("the code is wrong".to_string()))
+    };
+    let actor = Actor::user(authenticated.username());
+    audit.record(actor, "disable_totp", None, Some(authenticated.username()), &result).await;
+    result.map_err(|e| Flash::error(Redirect::to("/totp"), e.message()))?;
+    tracing::info!(user = authenticated.username(), "disabled two-factor authentication");
+    Ok(Redirect::to("/totp"))
+}
+
+#[derive(Debug, Serialize)]
+pub struct AuditContext {
+    entries: Vec<AuditEntry>,
+    /// the filter values to put back into the form
+    actor: Option<String>,
+    action: Option<String>,
+    service: Option<String>,
+    target: Option<String>,
+}
+
+#[get("/audit?<filter..>")]
+async fn audit_log(filter: AuditFilter, audit: &State<AuditLog>, _admin: Admin) -> Result<Template, Error> {
+    let entries = audit.query(&filter).await?;
+    Ok(Template::render(
+        "audit",
+        AuditContext {
+            entries,
+            actor: filter.actor,
+            action: filter.action,
+            service: filter.service,
+            target: filter.target,
+        },
+    ))
+}
+
+pub(crate) fn gen_random_string(n: usize) -> String {
     thread_rng()
     .sample_iter(&Alphanumeric)
     .take(n)
@@ -0,0 +1,128 @@
+use hmac::{Hmac, Mac, NewMac};
+use qrcode::render::svg;
+use qrcode::QrCode;
+use rand::{thread_rng, RngCore};
+use sha1::Sha1;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// seconds a code of a time based one time password (RFC 6238) is valid for
+const STEP: u64 = 30;
+const DIGITS: u32 = 6;
+const SECRET_LEN: usize = 20;
+const BASE32_ALPHABET: &[u8; 32] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ234567";
+
+/// a new random secret, base32 encoded like authenticator apps expect it
+pub fn generate_secret() -> String {
+    let mut secret = [0u8; SECRET_LEN];
+    thread_rng().fill_bytes(&mut secret);
+    base32_encode(&secret)
+}
+
+/// base32 without padding
+pub fn base32_encode(bytes: &[u8]) -> String {
+    let mut encoded = String::new();
+    let mut buffer: u32 = 0;
+    let mut bits = 0;
+    for byte in bytes {
+        buffer = (buffer << 8) | u32::from(*byte);
+        bits += 8;
+        while bits >= 5 {
+            bits -= 5;
+            encoded.push(BASE32_ALPHABET[((buffer >> bits) & 0x1f) as usize] as char);
+        }
+    }
+    if bits > 0 {
+        encoded.push(BASE32_ALPHABET[((buffer << (5 - bits)) & 0x1f) as usize] as char);
+    }
+
+    encoded
+}
+
+/// decodes base32, padding, spaces and lowercase letters are accepted
+pub fn base32_decode(encoded: &str) -> Option<Vec<u8>> {
+    let mut decoded = Vec::new();
+    let mut buffer: u32 = 0;
+    let mut bits = 0;
+    for c in encoded.chars().filter(|c| *c != '=' && !c.is_whitespace()) {
+        let value = BASE32_ALPHABET
+            .iter()
+            .position(|a| *a as char == c.to_ascii_uppercase())?;
+        buffer = (buffer << 5) | value as u32;
+        bits += 5;
+        if bits >= 8 {
+            bits -= 8;
+            decoded.push((buffer >> bits) as u8);
+        }
+    }
+
+    Some(decoded)
+}
+
+/// the HOTP value (RFC 4226) of `secret` for `counter`
+pub fn hotp(secret: &[u8], counter: u64) -> u32 {
+    let mut mac = Hmac::<Sha1>::new_from_slice(secret).expect("hmac accepts keys of any length");
+    mac.update(&counter.to_be_bytes());
+    let hash = mac.finalize().into_bytes();
+
+    let offset = (hash[hash.len() - 1] & 0xf) as usize;
+    let value = u32::from_be_bytes([
+        hash[offset] & 0x7f,
+        hash[offset + 1],
+        hash[offset + 2],
+        hash[offset + 3],
+    ]);
+    value % 10u32.pow(DIGITS)
+}
+
+/// the time step `unix_time` falls into
+pub fn step(unix_time: u64) -> u64 {
+    unix_time / STEP
+}
+
+pub fn current_step() -> u64 {
+    step(
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .expect("system time is before the unix epoch")
+            .as_secs(),
+    )
+}
+
+/// the code an authenticator app shows during `step`
+pub fn code(secret: &[u8], step: u64) -> String {
+    format!("{:0width$}", hotp(secret, step), width = DIGITS as usize)
+}
+
+/// checks `code` against the steps around `now_step` to allow for clock drift and returns the
+/// step it matched, steps up to `last_step` were already used and are rejected so a code only works once
+pub fn verify(secret: &[u8], code: &str, now_step: u64, last_step: u64) -> Option<u64> {
+    let code = code.trim();
+    if code.len() != DIGITS as usize {
+        return None;
+    }
+
+    (now_step.saturating_sub(1)..=now_step + 1)
+        .filter(|step| *step > last_step)
+        .find(|step| self::code(secret, *step) == code)
+}
+
+/// the uri authenticator apps read from the qr code
+pub fn otpauth_url(issuer: &str, account: &str, secret: &str) -> String {
+    let label = format!("{}:{}", issuer, account);
+    let mut url = url::Url::parse("otpauth://totp/").expect("valid base url");
+    url.set_path(&label);
+    url.query_pairs_mut()
+        .append_pair("secret", secret)
+        .append_pair("issuer", issuer)
+        .append_pair("digits", &DIGITS.to_string())
+        .append_pair("period", &STEP.to_string());
+    url.to_string()
+}
+
+/// `text` as an svg qr code that can be put into a page as is, `None` if it's too long for one
+pub fn qr_code_svg(text: &str) -> Option<String> {
+    let code = QrCode::new(text).ok()?;
+    let image = code.render::<svg::Color>().min_dimensions(200, 200).build();
+    // the xml declaration isn't allowed inside html
+    Some(image.trim_start_matches(r#"<?xml version="1.0" standalone="yes"?>"#).to_string())
+}
@@ -1,36 +1,176 @@
+use crate::config::TwitchConfig;
 use crate::error::Error;
-use reqwest::{header, ClientBuilder, StatusCode, Url};
-use rocket::{
-    response::Redirect,
-    serde::{Deserialize, DeserializeOwned, Serialize},
-    State,
+use crate::http::HttpClient;
+use crate::metrics::Metrics;
+use crate::provider::{self, OAuthScope, SocialProvider, TokenStatus};
+use crate::store::TokenStore;
+use reqwest::{StatusCode, Url};
+use rocket::serde::{Deserialize, DeserializeOwned, Serialize};
+use std::{
+    borrow::Borrow,
+    collections::HashMap,
+    sync::Arc,
+    time::{Duration, SystemTime, UNIX_EPOCH},
 };
-use std::{borrow::Borrow};
-use tokio::{fs, sync::Mutex};
-
-const AUTHORIZE_URL: &str = "https://id.twitch.tv/oauth2/authorize";
-const TOKEN_URL: &str = "https://id.twitch.tv/oauth2/token";
-const VALIDATE_URL: &str = "https://id.twitch.tv/oauth2/validate";
-const SEARCH_CATEGORIES_URL: &str = "https://api.twitch.tv/helix/search/categories";
-const GET_USER_URL: &str = "https://api.twitch.tv/helix/users";
-const CHANNEL_URL: &str = "https://api.twitch.tv/helix/channels";
-const COMMERICAL_URL: &str = "https://api.twitch.tv/helix/channels/commercial";
-const REFRESH_URL: &str = "https://id.twitch.tv/oauth2/token";
-
-#[derive(Debug, Serialize, Deserialize)]
+use tokio::sync::Mutex;
+
+pub use social_auth_types::{RefreshStatus, TwitchAdJson};
+
+pub const DEFAULT_AUTH_BASE_URL: &str = "https://id.twitch.tv/oauth2";
+pub const DEFAULT_API_BASE_URL: &str = "https://api.twitch.tv/helix";
+// relative to the auth base url
+const AUTHORIZE_PATH: &str = "/authorize";
+const TOKEN_PATH: &str = "/token";
+const VALIDATE_PATH: &str = "/validate";
+const REFRESH_PATH: &str = "/token";
+const REVOKE_PATH: &str = "/revoke";
+// relative to the api base url
+const SEARCH_CATEGORIES_PATH: &str = "/search/categories";
+const GET_USER_PATH: &str = "/users";
+const CHANNEL_PATH: &str = "/channels";
+const COMMERICAL_PATH: &str = "/channels/commercial";
+/// how long before the token expires we already refresh it
+const REFRESH_MARGIN: u64 = 5 * 60;
+/// twitch requires apps to validate their tokens at least once an hour
+const VALIDATE_INTERVAL: u64 = 60 * 60;
+const REFRESH_TASK_TICK: Duration = Duration::from_secs(60);
+
+const MANAGE_BROADCAST_SCOPE: &str = "channel:manage:broadcast";
+const EDIT_COMMERCIAL_SCOPE: &str = "channel:edit:commercial";
+
+/// the scopes offered on the dashboard, the defaults are what the api itself needs
+pub const SCOPES: &[OAuthScope] = &[
+    OAuthScope {
+        name: MANAGE_BROADCAST_SCOPE,
+        description: "Update the title and game",
+        feature: "Channel",
+        default: true,
+    },
+    OAuthScope {
+        name: EDIT_COMMERCIAL_SCOPE,
+        description: "Run commercials",
+        feature: "Channel",
+        default: true,
+    },
+    OAuthScope {
+        name: "channel:read:subscriptions",
+        description: "Read subscribers",
+        feature: "Channel",
+        default: false,
+    },
+    OAuthScope {
+        name: "bits:read",
+        description: "Read bits leaderboards and cheers",
+        feature: "Channel",
+        default: false,
+    },
+    OAuthScope {
+        name: "channel:manage:polls",
+        description: "Manage polls",
+        feature: "Interaction",
+        default: false,
+    },
+    OAuthScope {
+        name: "channel:manage:predictions",
+        description: "Manage predictions",
+        feature: "Interaction",
+        default: false,
+    },
+    OAuthScope {
+        name: "clips:edit",
+        description: "Create clips",
+        feature: "Interaction",
+        default: false,
+    },
+    OAuthScope {
+        name: "chat:read",
+        description: "Read chat",
+        feature: "Chat",
+        default: false,
+    },
+    OAuthScope {
+        name: "chat:edit",
+        description: "Send chat messages",
+        feature: "Chat",
+        default: false,
+    },
+    OAuthScope {
+        name: "user:read:email",
+        description: "Read the email address",
+        feature: "Account",
+        default: true,
+    },
+];
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct TwitchAuthInfo {
     access_token: String,
     refresh_token: String,
     expires_in: u64,
+    /// the scopes that were granted, twitch leaves this out if there are none
+    #[serde(default)]
     scope: Vec<String>,
     token_type: String,
+    /// unix timestamp of when we got the token, `expires_in` is relative to this.
+    /// tokens saved before we tracked this are treated as expired
+    #[serde(default)]
+    obtained_at: u64,
+}
+
+impl TwitchAuthInfo {
+    fn expires_soon(&self) -> bool {
+        self.obtained_at + self.expires_in <= now() + REFRESH_MARGIN
+    }
+}
+
+/// a connected twitch account and the state of its token
+struct TwitchAccount {
+    auth_info: TwitchAuthInfo,
+    /// unix timestamp of the last successful call to the validate endpoint
+    last_validated: u64,
+    refresh_status: Option<RefreshStatus>,
 }
 
 pub struct Twitch {
     client_id: String,
     client_secret: String,
     redirect_uri: String,
-    pub auth_info: Mutex<Option<TwitchAuthInfo>>,
+    /// base of the oauth endpoints, can be pointed at a mock server
+    auth_base_url: String,
+    /// base of the helix endpoints, can be pointed at a mock server
+    api_base_url: String,
+    store: Arc<dyn TokenStore>,
+    /// connected accounts by login
+    accounts: Mutex<HashMap<String, TwitchAccount>>,
+    /// held while refreshing so the background task and requests don't use a refresh token twice
+    refreshing: Mutex<()>,
+    http: HttpClient,
+    metrics: Arc<Metrics>,
+}
+
+/// what the validate endpoint tells us about a token
+#[derive(Debug, Deserialize)]
+struct ValidateResponse {
+    login: String,
+    #[serde(default)]
+    scopes: Vec<String>,
+    /// seconds until the token expires
+    #[serde(default)]
+    expires_in: u64,
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("system time is before the unix epoch")
+        .as_secs()
+}
+
+fn missing_scope(account: &str, scope: &str) -> Error {
+    Error::new_missing_upstream_scope(format!(
+        "twitch account {} is missing the {} scope, connect it again with that scope",
+        account, scope
+    ))
 }
 
 enum TwitchRequestMethod {
@@ -39,7 +179,7 @@ enum TwitchRequestMethod {
     Post,
 }
 
-// TODO: rename this after fully moving to new error
+/// the body of the error responses of twitch
 #[derive(Debug, Serialize, Deserialize)]
 pub struct TwitchErrorJson {
     pub message: String,
@@ -47,98 +187,248 @@ pub struct TwitchErrorJson {
     pub error: String,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-pub struct TwitchAdJson {
-    length: u64,
-    message: String,
-    retry_after: u64,
+/// the error code and message of an error response of twitch
+fn twitch_error(body: &str) -> Option<(String, String)> {
+    let twitch_err: TwitchErrorJson = serde_json::from_str(body).ok()?;
+    Some((twitch_err.error, twitch_err.message))
 }
 
 impl Twitch {
-    pub async fn new(client_id: String, client_secret: String, redirect_uri: String) -> Twitch {
-        let auth_info = match fs::read_to_string("twitch_auth.json").await {
-            Ok(auth_info) => Some(
-                serde_json::from_str::<TwitchAuthInfo>(&auth_info)
-                    .expect("invalid twitch_auth.json"),
-            ),
-            Err(_) => None,
+    pub fn new(config: &TwitchConfig, store: Arc<dyn TokenStore>, http: HttpClient, metrics: Arc<Metrics>) -> Twitch {
+        Twitch {
+            client_id: config.client_id.clone(),
+            client_secret: config.client_secret.clone(),
+            redirect_uri: config.redirect_uri.clone(),
+            auth_base_url: config.auth_base_url.trim_end_matches('/').to_string(),
+            api_base_url: config.api_base_url.trim_end_matches('/').to_string(),
+            store,
+            accounts: Mutex::new(HashMap::new()),
+            refreshing: Mutex::new(()),
+            http,
+            metrics,
+        }
+    }
+
+    fn auth_url(&self, path: &str) -> String {
+        format!("{}{}", self.auth_base_url, path)
+    }
+
+    fn api_url(&self, path: &str) -> String {
+        format!("{}{}", self.api_base_url, path)
+    }
+
+    /// asks twitch who `access_token` belongs to, None if twitch doesn't accept it anymore
+    async fn validate(&self, access_token: &str) -> Result<Option<ValidateResponse>, Error> {
+        let request = self
+            .http
+            .get(self.auth_url(VALIDATE_PATH))
+            .bearer_auth(access_token);
+        let res = self.http.send(request).await?;
+
+        if res.status() == StatusCode::UNAUTHORIZED {
+            return Ok(None);
+        }
+        if !res.status().is_success() {
+            return Err(self.http.error(res, twitch_error).await);
+        }
+
+        Ok(Some(self.http.json(res).await?))
+    }
+
+    /// checks the token of `account` with twitch and refreshes it if twitch doesn't accept it anymore
+    async fn validate_token(&self, account: &str) -> Result<(), Error> {
+        tracing::debug!(account, "validating twitch token");
+        let access_token = self.access_token(account).await?;
+        if self.validate(&access_token).await?.is_some() {
+            if let Some(account) = self.accounts.lock().await.get_mut(account) {
+                account.last_validated = now();
+            }
+            return Ok(());
+        }
+
+        tracing::info!(account, "twitch token is invalid");
+        self.refresh_token(account, &access_token).await
+    }
+
+    async fn access_token(&self, account: &str) -> Result<String, Error> {
+        match self.accounts.lock().await.get(account) {
+            Some(account) => Ok(account.auth_info.access_token.clone()),
+            None => Err(Error::new_auth_not_avail("twitch")),
+        }
+    }
+
+    /// refreshes the token of `account` if it is about to expire, this doesn't talk to twitch otherwise
+    async fn ensure_fresh(&self, account: &str) -> Result<(), Error> {
+        // a refresh that is in progress has to be saved before its token is handed out
+        drop(self.refreshing.lock().await);
+        let (expires_soon, access_token) = match self.accounts.lock().await.get(account) {
+            Some(account) => (account.auth_info.expires_soon(), account.auth_info.access_token.clone()),
+            None => return Err(Error::new_auth_not_avail("twitch")),
         };
 
-        Twitch {
-            client_id,
-            client_secret,
-            redirect_uri,
-            auth_info: Mutex::new(auth_info),
+        if expires_soon {
+            self.refresh_token(account, &access_token).await?;
         }
+
+        Ok(())
     }
 
-    async fn validate_token(&self) -> Result<(), Error> {
-        println!("validating token");
-        let auth_info_lock = self.auth_info.lock().await;
-        if let Some(auth_info) = &*auth_info_lock {
-            let client = reqwest::Client::new();
-            let res = client
-                .get(VALIDATE_URL)
-                .bearer_auth(&auth_info.access_token)
-                .send()
-                .await?;
-            if res.status().is_success() {
-                println!("okay");
-                return Ok(());
+    /// gets a new token for `account` from twitch, saves it and records the outcome in its refresh status.
+    /// does nothing if `access_token` was already replaced by someone else in the meantime
+    async fn refresh_token(&self, account: &str, access_token: &str) -> Result<(), Error> {
+        let _refreshing = self.refreshing.lock().await;
+        let refresh_token = match self.accounts.lock().await.get(account) {
+            Some(account) if account.auth_info.access_token != access_token => return Ok(()),
+            Some(account) => account.auth_info.refresh_token.clone(),
+            None => return Err(Error::new_auth_not_avail("twitch")),
+        };
+
+        tracing::info!(account, "refreshing twitch token");
+        let res = self.request_new_token(&refresh_token).await;
+        self.metrics.token_refresh("twitch", res.is_ok());
+        if let Some(twitch_account) = self.accounts.lock().await.get_mut(account) {
+            twitch_account.refresh_status = Some(RefreshStatus {
+                at: now(),
+                success: res.is_ok(),
+                error: res.as_ref().err().map(|e| format!("{:?}", e)),
+            });
+            if let Ok(ref auth_info) = res {
+                twitch_account.auth_info = auth_info.clone();
+                twitch_account.last_validated = now();
             }
+        }
+        res?;
 
-            drop(auth_info_lock);
+        self.save_token(account).await
+    }
 
-            println!("refreshing token");
-            self.refresh_token().await?;
-            return Ok(());
+    async fn request_new_token(&self, refresh_token: &str) -> Result<TwitchAuthInfo, Error> {
+        let url = Url::parse_with_params(&self.auth_url(REFRESH_PATH), [
+            ("grant_type", "refresh_token"),
+            ("refresh_token", refresh_token),
+            ("client_id", &self.client_id),
+            ("client_secret", &self.client_secret)
+        ])?;
+
+        let res = self.http.send(self.http.post(url)).await?;
+
+        if !res.status().is_success() {
+            return Err(self.http.error(res, twitch_error).await);
         }
 
-        Err(Error::new_auth_not_avail("twitch"))
+        let mut auth_info: TwitchAuthInfo = self.http.json(res).await?;
+        auth_info.obtained_at = now();
+        Ok(auth_info)
+    }
+
+    async fn insert_account(&self, login: &str, auth_info: TwitchAuthInfo, last_validated: u64) -> String {
+        let login = login.to_lowercase();
+        self.accounts.lock().await.insert(
+            login.clone(),
+            TwitchAccount {
+                auth_info,
+                last_validated,
+                refresh_status: None,
+            },
+        );
+        login
+    }
+
+    pub async fn refresh_status(&self, account: &str) -> Option<RefreshStatus> {
+        self.accounts
+            .lock()
+            .await
+            .get(account)
+            .and_then(|account| account.refresh_status.clone())
     }
 
-    async fn refresh_token(&self) -> Result<(), Error> {
-        if let Some(ref mut auth_info) = *self.auth_info.lock().await {
-            let client = reqwest::Client::new();
-            let url = Url::parse_with_params(REFRESH_URL, [
-                ("grant_type", "refresh_token"),
-                ("refresh_token", &auth_info.refresh_token),
-                ("client_id", &self.client_id),
-                ("client_secret", &self.client_secret)
-            ])?;
+    /// picks the account for requests concerning the channel `login`.
+    ///
+    /// without an explicit account the channel's own account is preferred if it is connected
+    pub async fn select_account_for(&self, account: Option<&str>, login: &str) -> Result<String, Error> {
+        if account.is_none() && self.accounts.lock().await.contains_key(&login.to_lowercase()) {
+            return Ok(login.to_lowercase());
+        }
 
-            let res = client.post(url).send().await?;
+        self.select_account(account).await
+    }
 
-            if res.status().is_success() {
-                *auth_info = res.json::<TwitchAuthInfo>().await?;
+    /// keeps the tokens valid in the background so requests don't have to check them first.
+    /// runs forever, errors are recorded in the refresh status of the account
+    pub async fn refresh_task(self: Arc<Self>) {
+        let mut interval = tokio::time::interval(REFRESH_TASK_TICK);
+        loop {
+            interval.tick().await;
+            for account in self.accounts().await {
+                if let Err(e) = self.refresh_if_needed(&account).await {
+                    tracing::warn!(%account, error = ?e, "twitch token refresh failed");
+                }
             }
+        }
+    }
 
+    async fn refresh_if_needed(&self, account: &str) -> Result<(), Error> {
+        self.ensure_fresh(account).await?;
+        let last_validated = match self.accounts.lock().await.get(account) {
+            Some(account) => account.last_validated,
+            None => return Ok(()),
+        };
+
+        if last_validated + VALIDATE_INTERVAL <= now() {
+            self.validate_token(account).await?;
         }
-        println!("refreshed token success");
+
         Ok(())
     }
 
-    pub fn get_authorize_url(&self) -> String {
-        Url::parse_with_params(
-            AUTHORIZE_URL,
+    /// `scopes` have to be from `SCOPES`, the default ones are requested if it is empty
+    pub fn get_authorize_url(&self, state: &str, scopes: &[String]) -> Result<String, Error> {
+        let scope = if scopes.is_empty() {
+            SCOPES
+                .iter()
+                .filter(|scope| scope.default)
+                .map(|scope| scope.name)
+                .collect::<Vec<_>>()
+                .join(" ")
+        } else {
+            if let Some(unknown) = scopes.iter().find(|s| !SCOPES.iter().any(|scope| scope.name == s.as_str())) {
+                return Err(Error::new_bad_request(format!("unknown twitch scope {}", unknown)));
+            }
+            scopes.join(" ")
+        };
+
+        Ok(Url::parse_with_params(
+            &self.auth_url(AUTHORIZE_PATH),
             [
                 ("client_id", self.client_id.as_str()),
                 ("redirect_uri", self.redirect_uri.as_str()),
-                (
-                    "scope",
-                    "channel:manage:broadcast user:read:email channel:edit:commercial",
-                ),
+                ("scope", scope.as_str()),
                 ("response_type", "code"),
                 ("force_verify", "true"),
+                ("state", state),
             ],
-        )
-        .expect("Unable to parse twitch authorize url")
-        .to_string()
+        )?
+        .to_string())
+    }
+
+    /// fails with a 403 naming the scope if `account` didn't grant it to us
+    async fn require_scope(&self, account: &str, scope: &str) -> Result<(), Error> {
+        let granted = match self.accounts.lock().await.get(account) {
+            Some(account) => account.auth_info.scope.iter().any(|s| s == scope),
+            None => return Err(Error::new_auth_not_avail("twitch")),
+        };
+
+        if !granted {
+            return Err(missing_scope(account, scope));
+        }
+
+        Ok(())
     }
 
     // the return type of this function is kinda ugly but there's no real alternative for if theres no body in the http response
     async fn twitch_request<I, B, R, K, V>(
         &self,
+        account: &str,
         url: &str,
         method: TwitchRequestMethod,
         query: I,
@@ -152,50 +442,43 @@ impl Twitch {
         V: AsRef<str>,
         <I as IntoIterator>::Item: Borrow<(K, V)>,
     {
-        self.validate_token().await?;
-        if let Some(auth_info) = &*self.auth_info.lock().await {
-            let url = Url::parse_with_params(url, query)?;
-
-            let mut headers = header::HeaderMap::new();
-            let auth_header_value = format!("Bearer {}", auth_info.access_token);
-            headers.insert(
-                "Authorization",
-                header::HeaderValue::from_str(&auth_header_value)?,
-            );
-            headers.insert("Client-Id", header::HeaderValue::from_str(&self.client_id)?);
-
-            let client = ClientBuilder::new().default_headers(headers).build()?;
-            let response = match method {
-                TwitchRequestMethod::Get => client.get(url).send().await?,
-                TwitchRequestMethod::Patch if body.is_some() => {
-                    client.patch(url).json(&body).send().await?
-                }
-                TwitchRequestMethod::Patch => client.patch(url).send().await?,
-                TwitchRequestMethod::Post if body.is_some() => {
-                    client.post(url).json(&body).send().await?
+        self.ensure_fresh(account).await?;
+        let access_token = self.access_token(account).await?;
+        let url = Url::parse_with_params(url, query)?;
+
+        let request = match method {
+            TwitchRequestMethod::Get => self.http.get(url),
+            TwitchRequestMethod::Patch if body.is_some() => self.http.patch(url).json(&body),
+            TwitchRequestMethod::Patch => self.http.patch(url),
+            TwitchRequestMethod::Post if body.is_some() => self.http.post(url).json(&body),
+            TwitchRequestMethod::Post => self.http.post(url),
+        };
+        let request = request
+            .bearer_auth(access_token)
+            .header("Client-Id", &self.client_id);
+        let response = self.http.send(request).await?;
+
+        if !response.status().is_success() {
+            let error = self.http.error(response, twitch_error).await;
+            // the scopes of a token can change without us noticing, e.g. when the user edits the connection on twitch
+            if let Error::Upstream(upstream) = &error {
+                if let Some(scope) = upstream.message.strip_prefix("Missing scope: ") {
+                    return Err(missing_scope(account, scope));
                 }
-                TwitchRequestMethod::Post => client.post(url).send().await?,
-            };
-
-            if !response.status().is_success() {
-                let twitch_error = response.json::<TwitchErrorJson>().await?;
-                return Err(twitch_error.into());
             }
-            if let Some(content_length) = response.content_length() {
-                if content_length > 0 {
-                    return Ok(Some(response.json::<R>().await?));
-                }
+            return Err(error);
+        }
+        if let Some(content_length) = response.content_length() {
+            if content_length > 0 {
+                return Ok(Some(self.http.json(response).await?));
             }
-
-            return Ok(None);
         }
-        Err(Error::new_bad_request(
-            "no twitch auth info available".to_string(),
-        ))
+
+        Ok(None)
     }
 
     /// calls the twitch search endpoint and takes the first result as the id
-    pub async fn get_game_id_from_string(&self, game_name: &str) -> Result<String, Error> {
+    pub async fn get_game_id_from_string(&self, account: &str, game_name: &str) -> Result<String, Error> {
         #[derive(Debug, Deserialize)]
         struct InnerResponse {
             id: String,
@@ -208,7 +491,8 @@ impl Twitch {
 
         let res: Option<Response> = self
             .twitch_request(
-                SEARCH_CATEGORIES_URL,
+                account,
+                &self.api_url(SEARCH_CATEGORIES_PATH),
                 TwitchRequestMethod::Get,
                 [("query", game_name)],
                 None::<()>,
@@ -228,7 +512,7 @@ impl Twitch {
         ))
     }
 
-    pub async fn get_channel_id_from_string(&self, channel_name: &str) -> Result<String, Error> {
+    pub async fn get_channel_id_from_string(&self, account: &str, channel_name: &str) -> Result<String, Error> {
         #[derive(Debug, Deserialize)]
         struct InnerResponse {
             id: String,
@@ -241,7 +525,8 @@ impl Twitch {
 
         let res: Option<Response> = self
             .twitch_request(
-                GET_USER_URL,
+                account,
+                &self.api_url(GET_USER_PATH),
                 TwitchRequestMethod::Get,
                 [("login", channel_name)],
                 None::<()>,
@@ -266,6 +551,7 @@ impl Twitch {
 
     pub async fn update_channel(
         &self,
+        account: &str,
         channel_id: &str,
         game_id: &str,
         title: &str,
@@ -276,11 +562,13 @@ impl Twitch {
             title: &'a str,
         }
 
+        self.require_scope(account, MANAGE_BROADCAST_SCOPE).await?;
         let update_channel_body = UpdateChannelBody { game_id, title };
 
         let _res: Option<()> = self
             .twitch_request(
-                CHANNEL_URL,
+                account,
+                &self.api_url(CHANNEL_PATH),
                 TwitchRequestMethod::Patch,
                 [("broadcaster_id", channel_id)],
                 Some(update_channel_body),
@@ -292,6 +580,7 @@ impl Twitch {
 
     pub async fn run_commercial(
         &self,
+        account: &str,
         channel_id: String,
         length: u16,
     ) -> Result<TwitchAdJson, Error> {
@@ -306,6 +595,7 @@ impl Twitch {
             data: Vec<TwitchAdJson>,
         }
 
+        self.require_scope(account, EDIT_COMMERCIAL_SCOPE).await?;
         let start_commerical_body = StartCommericalBody {
             broadcaster_id: channel_id,
             length,
@@ -313,48 +603,218 @@ impl Twitch {
 
         let res: Option<Response> = self
             .twitch_request(
-                COMMERICAL_URL,
+                account,
+                &self.api_url(COMMERICAL_PATH),
                 TwitchRequestMethod::Post,
                 Vec::new() as Vec<(&str, &str)>,
                 Some(start_commerical_body),
             )
             .await?;
 
-        if let Some(mut res) = res {
-            return Ok(res.data.remove(1));
+        match res {
+            Some(res) => res
+                .data
+                .into_iter()
+                .next()
+                .ok_or_else(|| self.http.unexpected_response("the commercial response has no data")),
+            None => Err(Error::new_internal_server_error(
+                "body was none".to_string(),
+            )),
         }
-
-        Err(Error::new_internal_server_error(
-            "body was none".to_string(),
-        ))
     }
 }
 
-#[get("/authorize/callback?<code>")]
-async fn authorize_callback(twitch: &State<Twitch>, code: &str) -> Result<Redirect, Error> {
-    let client = reqwest::Client::new();
-    let res = client
-        .post(format!(
-        "{}?client_id={}&client_secret={}&code={}&grant_type=authorization_code&redirect_uri={}",
-        TOKEN_URL, twitch.client_id, twitch.client_secret, code, twitch.redirect_uri
-    ))
-        .send()
-        .await?;
-    if !StatusCode::is_success(&res.status()) {
-        let twitch_err: TwitchErrorJson = res.json().await?;
-        return Err(twitch_err.into());
+#[rocket::async_trait]
+impl SocialProvider for Twitch {
+    fn name(&self) -> &'static str {
+        "twitch"
+    }
+
+    fn display_name(&self) -> &'static str {
+        "Twitch"
+    }
+
+    fn scopes(&self) -> &'static [OAuthScope] {
+        SCOPES
+    }
+
+    async fn authorize_url(&self, state: &str, scopes: &[String]) -> Result<String, Error> {
+        self.get_authorize_url(state, scopes)
+    }
+
+    async fn authorize_callback(&self, params: &HashMap<String, String>) -> Result<String, Error> {
+        let code = params
+            .get("code")
+            .ok_or_else(|| Error::new_bad_request("missing code".to_string()))?;
+        let url = Url::parse_with_params(
+            &self.auth_url(TOKEN_PATH),
+            [
+                ("client_id", self.client_id.as_str()),
+                ("client_secret", self.client_secret.as_str()),
+                ("code", code.as_str()),
+                ("grant_type", "authorization_code"),
+                ("redirect_uri", self.redirect_uri.as_str()),
+            ],
+        )?;
+
+        let res = self.http.send(self.http.post(url)).await?;
+        if !StatusCode::is_success(&res.status()) {
+            return Err(self.http.error(res, twitch_error).await);
+        }
+
+        let mut auth_info: TwitchAuthInfo = self.http.json(res).await?;
+        auth_info.obtained_at = now();
+        let validated = self.validate(&auth_info.access_token).await?.ok_or_else(|| {
+            Error::new_internal_server_error("twitch doesn't accept the new token".to_string())
+        })?;
+
+        let account = self.insert_account(&validated.login, auth_info, now()).await;
+        self.save_token(&account).await?;
+        Ok(account)
+    }
+
+    async fn load_tokens(&self) -> Result<(), Error> {
+        for key in self.store.keys().await? {
+            if let Some(account) = provider::account_from_store_key(self.name(), &key) {
+                if let Some(bytes) = self.store.load(&key).await? {
+                    // never validated so the background task checks it on its first run
+                    self.insert_account(account, serde_json::from_slice(&bytes)?, 0)
+                        .await;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// moves the token saved before multiple accounts were supported to the account it belongs to,
+    /// only twitch knows which account that is
+    async fn migrate_legacy_token(&self) -> Result<(), Error> {
+        let legacy_key = provider::legacy_store_key(self.name());
+        let bytes = match self.store.load(&legacy_key).await? {
+            Some(bytes) => bytes,
+            None => return Ok(()),
+        };
+
+        let mut auth_info: TwitchAuthInfo = serde_json::from_slice(&bytes)?;
+        let validated = match self.validate(&auth_info.access_token).await? {
+            Some(validated) => validated,
+            None => {
+                auth_info = self.request_new_token(&auth_info.refresh_token).await?;
+                self.validate(&auth_info.access_token).await?.ok_or_else(|| {
+                    Error::new_internal_server_error(
+                        "twitch doesn't accept the refreshed token".to_string(),
+                    )
+                })?
+            }
+        };
+
+        let account = self.insert_account(&validated.login, auth_info, now()).await;
+        self.save_token(&account).await?;
+        self.store.delete(&legacy_key).await?;
+        tracing::info!(%account, "migrated saved twitch token");
+        Ok(())
+    }
+
+    async fn save_token(&self, account: &str) -> Result<(), Error> {
+        let bytes = match self.accounts.lock().await.get(account) {
+            Some(account) => serde_json::to_vec(&account.auth_info)?,
+            None => return Ok(()),
+        };
+
+        self.store
+            .save(&provider::store_key(self.name(), account), &bytes)
+            .await
+    }
+
+    async fn token_json(&self, account: &str) -> Result<Option<Vec<u8>>, Error> {
+        match self.accounts.lock().await.get(account) {
+            Some(account) => Ok(Some(serde_json::to_vec(&account.auth_info)?)),
+            None => Ok(None),
+        }
+    }
+
+    async fn refresh(&self, account: &str) -> Result<(), Error> {
+        self.ensure_fresh(account).await
+    }
+
+    async fn force_refresh(&self, account: &str) -> Result<(), Error> {
+        let access_token = self.access_token(account).await?;
+        self.refresh_token(account, &access_token).await
+    }
+
+    async fn granted_scopes(&self, account: &str) -> Vec<String> {
+        self.accounts
+            .lock()
+            .await
+            .get(account)
+            .map(|account| account.auth_info.scope.clone())
+            .unwrap_or_default()
+    }
+
+    async fn accounts(&self) -> Vec<String> {
+        let mut accounts: Vec<String> = self.accounts.lock().await.keys().cloned().collect();
+        accounts.sort();
+        accounts
+    }
+
+    async fn revoke(&self, account: &str) -> Result<(), Error> {
+        // a refresh that is in progress would save the token again after it was deleted
+        let _refreshing = self.refreshing.lock().await;
+        let access_token = self
+            .accounts
+            .lock()
+            .await
+            .get(account)
+            .map(|account| account.auth_info.access_token.clone());
+
+        if let Some(access_token) = access_token {
+            let url = Url::parse_with_params(
+                &self.auth_url(REVOKE_PATH),
+                [
+                    ("client_id", self.client_id.as_str()),
+                    ("token", access_token.as_str()),
+                ],
+            )?;
+            let res = self.http.send(self.http.post(url)).await?;
+            // twitch answers with 400 if the token is already invalid, which is fine for us
+            if !res.status().is_success() && res.status() != StatusCode::BAD_REQUEST {
+                return Err(self.http.error(res, twitch_error).await);
+            }
+        }
+
+        self.accounts.lock().await.remove(account);
+        self.store
+            .delete(&provider::store_key(self.name(), account))
+            .await
     }
 
-    let auth_info: TwitchAuthInfo = res.json().await?;
+    async fn check(&self, account: &str) -> TokenStatus {
+        let validated = match self.access_token(account).await {
+            Ok(access_token) => self.validate(&access_token).await,
+            Err(e) => Err(e),
+        };
 
-    fs::write("twitch_auth.json", serde_json::to_vec(&auth_info)?).await?;
-    let mut auth_info_mutex = twitch.auth_info.lock().await;
-    *auth_info_mutex = Some(auth_info);
-    Ok(Redirect::to("/"))
+        match validated {
+            Ok(Some(validated)) => {
+                if let Some(account) = self.accounts.lock().await.get_mut(account) {
+                    account.last_validated = now();
+                }
+                TokenStatus::valid(account, Some(now() + validated.expires_in), validated.scopes)
+            }
+            Ok(None) => TokenStatus::invalid(account, "twitch doesn't accept the token anymore"),
+            Err(e) => TokenStatus::invalid(account, &e.message()),
+        }
+    }
 }
 
+/// starts the background token refresh once the server is up, if twitch is enabled
 pub fn stage() -> rocket::fairing::AdHoc {
-    rocket::fairing::AdHoc::on_ignite("twitch", |rocket| async {
-        rocket.mount("/twitch", routes![authorize_callback])
+    rocket::fairing::AdHoc::on_liftoff("twitch token refresh", |rocket| {
+        Box::pin(async move {
+            if let Some(twitch) = rocket.state::<Arc<Twitch>>() {
+                tokio::spawn(twitch.clone().refresh_task());
+            }
+        })
     })
 }
@@ -1,83 +1,358 @@
-use rocket::{
-    response::Redirect,
-    State,
-};
+use std::time::{Duration, Instant};
+use std::{collections::HashMap, sync::Arc};
 
+use reqwest::{Method, Url};
+use rocket::serde::Deserialize;
 use tokio::sync::Mutex;
-use egg_mode::{KeyPair, Token, auth};
-
-use tokio::fs;
+use egg_mode::{KeyPair, Token};
 
+use crate::config::TwitterConfig;
 use crate::error::Error;
+use crate::http::HttpClient;
+use crate::oauth1;
+use crate::provider::{self, SocialProvider, TokenStatus};
+use crate::store::TokenStore;
+
+pub const DEFAULT_API_BASE_URL: &str = "https://api.twitter.com";
+const REQUEST_TOKEN_PATH: &str = "/oauth/request_token";
+const AUTHORIZE_PATH: &str = "/oauth/authorize";
+const ACCESS_TOKEN_PATH: &str = "/oauth/access_token";
+const VERIFY_CREDENTIALS_PATH: &str = "/1.1/account/verify_credentials.json";
+const TWEET_PATH: &str = "/1.1/statuses/update.json";
+const INVALIDATE_TOKEN_PATH: &str = "/1.1/oauth/invalidate_token";
+/// request tokens of authorizations that were never finished are dropped after this
+const REQUEST_TOKEN_LIFETIME: Duration = Duration::from_secs(10 * 60);
+
+#[derive(Debug, Deserialize)]
+pub struct TwitterErrorJson {
+    pub errors: Vec<TwitterError>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct TwitterError {
+    pub code: i64,
+    pub message: String,
+}
+
+/// the code of the first error and all messages of an error response of twitter
+fn twitter_error(body: &str) -> Option<(String, String)> {
+    let twitter_err: TwitterErrorJson = serde_json::from_str(body).ok()?;
+    let code = twitter_err.errors.first()?.code.to_string();
+    let messages: Vec<String> = twitter_err
+        .errors
+        .iter()
+        .map(|e| format!("{} ({})", e.message, e.code))
+        .collect();
+    Some((code, messages.join(", ")))
+}
 
 pub struct Twitter {
     callback_url: String,
-    request_token: Mutex<Option<KeyPair>>,
+    /// everything before the api paths, can be pointed at a mock server
+    api_base_url: String,
+    /// request tokens of authorizations in progress by their key
+    request_tokens: Mutex<HashMap<String, (KeyPair, Instant)>>,
     con_token: KeyPair,
-    pub auth_token: Mutex<Option<Token>>
+    store: Arc<dyn TokenStore>,
+    /// connected accounts by lowercase screen name
+    auth_tokens: Mutex<HashMap<String, Token>>,
+    http: HttpClient,
 }
 
-impl Twitter {
-    pub async fn new(
-        api_key: String,
-        api_secret: String,
-        callback_url: String,
-    ) -> Twitter {
-        let token = match fs::read_to_string("twitter_auth.json").await {
-            Ok(token) => {
-                Some(serde_json::from_str::<Token>(&token).expect("invalid twitter_auth.json"))
-            }
-            Err(_) => None
-        };
+/// the user credentials of a token, we only ever create access tokens
+fn access_keys(token: &Token) -> Result<&KeyPair, Error> {
+    match token {
+        Token::Access { access, .. } => Ok(access),
+        Token::Bearer(_) => Err(Error::new_internal_server_error(
+            "saved twitter token is a bearer token".to_string(),
+        )),
+    }
+}
+
+/// twitter answers the oauth endpoints with form encoded bodies
+fn parse_form_response(body: &str) -> HashMap<String, String> {
+    url::form_urlencoded::parse(body.as_bytes()).into_owned().collect()
+}
+
+fn form_value(form: &HashMap<String, String>, key: &str) -> Result<String, Error> {
+    form.get(key).cloned().ok_or_else(|| {
+        Error::new_internal_server_error(format!("twitter response is missing {}", key))
+    })
+}
 
+impl Twitter {
+    pub fn new(config: &TwitterConfig, store: Arc<dyn TokenStore>, http: HttpClient) -> Twitter {
         Twitter {
-            callback_url,
-            request_token: Mutex::new(None),
-            con_token: egg_mode::KeyPair::new(api_key, api_secret),
-            auth_token: Mutex::new(token),
+            callback_url: config.callback_url.clone(),
+            api_base_url: config.api_base_url.trim_end_matches('/').to_string(),
+            request_tokens: Mutex::new(HashMap::new()),
+            con_token: egg_mode::KeyPair::new(config.api_key.clone(), config.api_secret.clone()),
+            store,
+            auth_tokens: Mutex::new(HashMap::new()),
+            http,
         }
     }
 
-    pub async fn get_authorize_url(&self) -> Result<String, Error> {
-        let req_token = egg_mode::auth::request_token(&self.con_token, &self.callback_url).await?;
-        let redirect_url = auth::authorize_url(&req_token);
-        let mut token = self.request_token.lock().await;
-        *token = Some(req_token);
+    /// sends a signed request, `params` are sent as form body except for `oauth_` parameters
+    async fn send_signed(
+        &self,
+        method: Method,
+        path: &str,
+        token: Option<&KeyPair>,
+        params: &[(&str, &str)],
+    ) -> Result<reqwest::Response, Error> {
+        let url = Url::parse(&format!("{}{}", self.api_base_url, path))?;
+        let authorization =
+            oauth1::authorization_header(method.as_str(), &url, &self.con_token, token, params);
+        let form: Vec<&(&str, &str)> = params
+            .iter()
+            .filter(|(key, _)| !key.starts_with("oauth_"))
+            .collect();
+
+        let mut req = self
+            .http
+            .request(method, url)
+            .header("Authorization", authorization);
+        if !form.is_empty() {
+            req = req.form(&form);
+        }
 
-        Ok(redirect_url)
+        self.http.send(req).await
     }
-}
 
-#[get("/authorize")]
-async fn authorize(twitter: &State<Twitter>) -> Result<Redirect, Error> {
-    let redirect_url = twitter.get_authorize_url().await?;
-    Ok(Redirect::to(redirect_url))
-}
+    /// same as `send_signed` but turns error responses into an `Error`
+    async fn twitter_request(
+        &self,
+        method: Method,
+        path: &str,
+        token: Option<&KeyPair>,
+        params: &[(&str, &str)],
+    ) -> Result<reqwest::Response, Error> {
+        let res = self.send_signed(method, path, token, params).await?;
+        if !res.status().is_success() {
+            return Err(self.http.error(res, twitter_error).await);
+        }
 
-#[get("/authorize/callback?<oauth_token>&<oauth_verifier>")]
-// we need to allow unused variables here since oauth_token is not needed by us but provided by the twitter api callback, sadly rocket doesn't let us prefix it with _ either
-#[allow(unused_variables)] async fn authorize_callback(
-    oauth_token: &str,
-    twitter: &State<Twitter>,
-    oauth_verifier: &str,
-) -> Result<Redirect, Error> {
-    let request_token = twitter.request_token.lock().await;
-    if let Some(ref request_token) = *request_token {
-        let (token, _, _) =
-            egg_mode::auth::access_token(twitter.con_token.clone(), request_token, oauth_verifier)
-                .await?;
-        fs::write("twitter_auth.json", serde_json::to_vec(&token)?).await?;
-        let mut saved_auth_token = twitter.auth_token.lock().await;
-        *saved_auth_token = Some(token);
+        Ok(res)
+    }
+
+    /// oauth 1 has no state parameter so it is passed along in the callback url instead
+    pub async fn get_authorize_url(&self, state: &str) -> Result<String, Error> {
+        let mut callback_url = Url::parse(&self.callback_url)?;
+        callback_url.query_pairs_mut().append_pair("state", state);
+        let res = self
+            .twitter_request(
+                Method::POST,
+                REQUEST_TOKEN_PATH,
+                None,
+                &[("oauth_callback", callback_url.as_str())],
+            )
+            .await?;
+        let form = parse_form_response(&self.http.text(res).await?);
+        let req_token = KeyPair::new(
+            form_value(&form, "oauth_token")?,
+            form_value(&form, "oauth_token_secret")?,
+        );
+
+        // force_login lets the user switch accounts instead of reusing the one they are logged in with
+        let redirect_url = Url::parse_with_params(
+            &format!("{}{}", self.api_base_url, AUTHORIZE_PATH),
+            [("oauth_token", req_token.key.as_ref()), ("force_login", "true")],
+        )?;
+        let mut request_tokens = self.request_tokens.lock().await;
+        request_tokens.retain(|_, (_, created)| created.elapsed() < REQUEST_TOKEN_LIFETIME);
+        request_tokens.insert(req_token.key.to_string(), (req_token, Instant::now()));
+
+        Ok(redirect_url.to_string())
+    }
+
+    pub async fn token(&self, account: &str) -> Result<Token, Error> {
+        self.auth_tokens
+            .lock()
+            .await
+            .get(account)
+            .cloned()
+            .ok_or_else(|| Error::new_auth_not_avail("twitter"))
+    }
+
+    /// the lowercase screen name of the user `token` belongs to
+    async fn verify_credentials(&self, token: &Token) -> Result<String, Error> {
+        #[derive(Debug, Deserialize)]
+        struct Response {
+            screen_name: String,
+        }
+
+        let res = self
+            .twitter_request(
+                Method::GET,
+                VERIFY_CREDENTIALS_PATH,
+                Some(access_keys(token)?),
+                &[],
+            )
+            .await?;
+
+        let res: Response = self.http.json(res).await?;
+        Ok(res.screen_name.to_lowercase())
+    }
+
+    pub async fn tweet(&self, account: &str, status: &str) -> Result<(), Error> {
+        let token = self.token(account).await?;
+        self.twitter_request(
+            Method::POST,
+            TWEET_PATH,
+            Some(access_keys(&token)?),
+            &[("status", status)],
+        )
+        .await?;
+
+        Ok(())
     }
-    Ok(Redirect::to("/"))
 }
 
+#[rocket::async_trait]
+impl SocialProvider for Twitter {
+    fn name(&self) -> &'static str {
+        "twitter"
+    }
 
+    fn display_name(&self) -> &'static str {
+        "Twitter"
+    }
 
-pub fn stage() -> rocket::fairing::AdHoc {
-    rocket::fairing::AdHoc::on_ignite("twitter", |rocket| async {
-        rocket
-            .mount("/twitter", routes![authorize, authorize_callback])
-    })
-}
\ No newline at end of file
+    // twitter has no scopes, access is configured for the whole app
+    async fn authorize_url(&self, state: &str, _scopes: &[String]) -> Result<String, Error> {
+        self.get_authorize_url(state).await
+    }
+
+    async fn authorize_callback(&self, params: &HashMap<String, String>) -> Result<String, Error> {
+        let oauth_token = params
+            .get("oauth_token")
+            .ok_or_else(|| Error::new_bad_request("missing oauth_token".to_string()))?;
+        let oauth_verifier = params
+            .get("oauth_verifier")
+            .ok_or_else(|| Error::new_bad_request("missing oauth_verifier".to_string()))?;
+
+        let request_token = match self.request_tokens.lock().await.remove(oauth_token) {
+            Some((request_token, created)) if created.elapsed() < REQUEST_TOKEN_LIFETIME => request_token,
+            _ => return Err(Error::new_bad_request("no authorization in progress".to_string())),
+        };
+
+        let res = self
+            .twitter_request(
+                Method::POST,
+                ACCESS_TOKEN_PATH,
+                Some(&request_token),
+                &[("oauth_verifier", oauth_verifier)],
+            )
+            .await?;
+        let form = parse_form_response(&self.http.text(res).await?);
+        let token = Token::Access {
+            consumer: self.con_token.clone(),
+            access: KeyPair::new(
+                form_value(&form, "oauth_token")?,
+                form_value(&form, "oauth_token_secret")?,
+            ),
+        };
+        let account = form_value(&form, "screen_name")?.to_lowercase();
+        self.auth_tokens.lock().await.insert(account.clone(), token);
+        self.save_token(&account).await?;
+        Ok(account)
+    }
+
+    async fn load_tokens(&self) -> Result<(), Error> {
+        for key in self.store.keys().await? {
+            if let Some(account) = provider::account_from_store_key(self.name(), &key) {
+                if let Some(bytes) = self.store.load(&key).await? {
+                    self.auth_tokens
+                        .lock()
+                        .await
+                        .insert(account.to_string(), serde_json::from_slice(&bytes)?);
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// moves the token saved before multiple accounts were supported to the account it belongs to,
+    /// only twitter knows which account that is
+    async fn migrate_legacy_token(&self) -> Result<(), Error> {
+        let legacy_key = provider::legacy_store_key(self.name());
+        let bytes = match self.store.load(&legacy_key).await? {
+            Some(bytes) => bytes,
+            None => return Ok(()),
+        };
+
+        let token: Token = serde_json::from_slice(&bytes)?;
+        let account = self.verify_credentials(&token).await?;
+        self.auth_tokens.lock().await.insert(account.clone(), token);
+        self.save_token(&account).await?;
+        self.store.delete(&legacy_key).await?;
+        tracing::info!(%account, "migrated saved twitter token");
+        Ok(())
+    }
+
+    async fn save_token(&self, account: &str) -> Result<(), Error> {
+        let bytes = match self.auth_tokens.lock().await.get(account) {
+            Some(token) => serde_json::to_vec(token)?,
+            None => return Ok(()),
+        };
+
+        self.store
+            .save(&provider::store_key(self.name(), account), &bytes)
+            .await
+    }
+
+    async fn token_json(&self, account: &str) -> Result<Option<Vec<u8>>, Error> {
+        match self.auth_tokens.lock().await.get(account) {
+            Some(token) => Ok(Some(serde_json::to_vec(token)?)),
+            None => Ok(None),
+        }
+    }
+
+    // user tokens don't expire so there is nothing to refresh
+    async fn refresh(&self, _account: &str) -> Result<(), Error> {
+        Ok(())
+    }
+
+    async fn accounts(&self) -> Vec<String> {
+        let mut accounts: Vec<String> = self.auth_tokens.lock().await.keys().cloned().collect();
+        accounts.sort();
+        accounts
+    }
+
+    async fn revoke(&self, account: &str) -> Result<(), Error> {
+        if let Ok(token) = self.token(account).await {
+            let res = self
+                .send_signed(
+                    Method::POST,
+                    INVALIDATE_TOKEN_PATH,
+                    Some(access_keys(&token)?),
+                    &[],
+                )
+                .await?;
+            // twitter answers with 401 if the token is already invalid, which is fine for us
+            if !res.status().is_success() && res.status() != reqwest::StatusCode::UNAUTHORIZED {
+                return Err(self.http.error(res, twitter_error).await);
+            }
+        }
+
+        self.auth_tokens.lock().await.remove(account);
+        self.store
+            .delete(&provider::store_key(self.name(), account))
+            .await
+    }
+
+    // user tokens don't expire and twitter has no scopes
+    async fn check(&self, account: &str) -> TokenStatus {
+        let verified = match self.token(account).await {
+            Ok(token) => self.verify_credentials(&token).await,
+            Err(e) => Err(e),
+        };
+
+        match verified {
+            Ok(_) => TokenStatus::valid(account, None, vec![]),
+            Err(e) => TokenStatus::invalid(account, &e.message()),
+        }
+    }
+}
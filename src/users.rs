@@ -0,0 +1,330 @@
+use crate::api_keys::hash_secret;
+use crate::error::Error;
+use crate::store::TokenStore;
+use crate::templates::gen_random_string;
+use crate::totp;
+use argon2::password_hash::SaltString;
+use argon2::{Argon2, PasswordHash, PasswordHasher, PasswordVerifier};
+use rand::rngs::OsRng;
+use rocket::serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::sync::Mutex;
+
+const STORE_PREFIX: &str = "user.";
+/// the user that is created from AUTH_PASSWORD_HASH when there are no users yet
+pub const BOOTSTRAP_USERNAME: &str = "admin";
+const RECOVERY_CODES: usize = 10;
+const RECOVERY_CODE_LEN: usize = 10;
+/// checked when the username doesn't exist so the login takes as long as for a wrong password,
+/// it has the parameters of `hash_password` and nobody knows the password
+const DUMMY_HASH: &str = "$argon2id$v=19$m=4096,t=3,p=1$loi+gQBvcYF8VkgYVA272Q$itzEXsVLHxBm058S/aeSPcpSRcO0lhJ/K2fqqYcBF2M";
+
+/// hashes `password` with argon2id so it can be used as `AUTH_PASSWORD_HASH`
+pub fn hash_password(password: &str) -> Result<String, Error> {
+    let salt = SaltString::generate(&mut OsRng);
+    Argon2::default()
+        .hash_password(password.as_bytes(), &salt)
+        .map(|hash| hash.to_string())
+        .map_err(|e| Error::new_internal_server_error(format!("unable to hash password: {}", e)))
+}
+
+pub(crate) fn validate_hash(password_hash: &str) -> Result<(), Error> {
+    PasswordHash::new(password_hash)
+        .map(|_| ())
+        .map_err(|e| Error::new_bad_request(format!("invalid password hash: {}", e)))
+}
+
+/// What a user of the dashboard is allowed to do.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, FromFormField)]
+#[serde(rename_all = "snake_case")]
+pub enum Role {
+    /// connects and disconnects services and manages api keys and users
+    #[field(value = "admin")]
+    Admin,
+    /// can only look at the dashboard
+    #[field(value = "operator")]
+    Operator,
+}
+
+impl Role {
+    pub const ALL: [Role; 2] = [Role::Admin, Role::Operator];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Role::Admin => "admin",
+            Role::Operator => "operator",
+        }
+    }
+}
+
+/// A user as it is shown on the dashboard.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UserInfo {
+    pub username: String,
+    pub role: Role,
+    pub created_at: u64,
+    /// logging in needs a code from an authenticator app or a recovery code
+    #[serde(default)]
+    pub totp_enabled: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Totp {
+    /// base32 encoded
+    secret: String,
+    /// sha256 of the recovery codes that weren't used yet
+    recovery_codes: Vec<String>,
+    /// the last time step a code was used for, so codes can't be used twice
+    last_step: u64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct StoredUser {
+    #[serde(flatten)]
+    info: UserInfo,
+    /// argon2 hash in the PHC string format
+    password_hash: String,
+    #[serde(default)]
+    totp: Option<Totp>,
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("system time is before the unix epoch")
+        .as_secs()
+}
+
+/// The operator accounts of the dashboard, kept in the token store next to the tokens.
+pub struct Users {
+    store: Arc<dyn TokenStore>,
+    users: Mutex<HashMap<String, StoredUser>>,
+}
+
+impl Users {
+    pub fn new(store: Arc<dyn TokenStore>) -> Self {
+        Users {
+            store,
+            users: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub async fn load(&self) -> Result<(), Error> {
+        for key in self.store.keys().await? {
+            if let Some(username) = key.strip_prefix(STORE_PREFIX) {
+                if let Some(bytes) = self.store.load(&key).await? {
+                    self.users
+                        .lock()
+                        .await
+                        .insert(username.to_string(), serde_json::from_slice(&bytes)?);
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn save(&self, stored: &StoredUser) -> Result<(), Error> {
+        self.store
+            .save(
+                &format!("{}{}", STORE_PREFIX, stored.info.username),
+                &serde_json::to_vec(stored)?,
+            )
+            .await
+    }
+
+    pub async fn is_empty(&self) -> bool {
+        self.users.lock().await.is_empty()
+    }
+
+    /// creates the first admin from `password_hash` if there are no users yet, returns whether it did
+    pub async fn bootstrap(&self, password_hash: Option<&str>) -> Result<bool, Error> {
+        if !self.users.lock().await.is_empty() {
+            return Ok(false);
+        }
+
+        let password_hash = password_hash.ok_or_else(|| {
+            Error::new_bad_request("there are no users yet, set AUTH_PASSWORD_HASH to create the first admin".to_string())
+        })?;
+        self.insert(BOOTSTRAP_USERNAME, password_hash.to_string(), Role::Admin)
+            .await?;
+        Ok(true)
+    }
+
+    pub async fn create(&self, username: &str, password: &str, role: Role) -> Result<UserInfo, Error> {
+        if password.is_empty() {
+            return Err(Error::new_bad_request("a user needs a password".to_string()));
+        }
+
+        let password = password.to_string();
+        let password_hash = tokio::task::spawn_blocking(move || hash_password(&password)).await??;
+        self.insert(username, password_hash, role).await
+    }
+
+    /// adds a user with an already hashed password
+    pub async fn insert(&self, username: &str, password_hash: String, role: Role) -> Result<UserInfo, Error> {
+        let username = username.trim();
+        if username.is_empty() || !username.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-') {
+            return Err(Error::new_bad_request(
+                "usernames may only contain letters, numbers, _ and -".to_string(),
+            ));
+        }
+        validate_hash(&password_hash)?;
+
+        let mut users = self.users.lock().await;
+        if users.contains_key(username) {
+            return Err(Error::new_conflict(format!("user {} already exists", username)));
+        }
+
+        let stored = StoredUser {
+            info: UserInfo {
+                username: username.to_string(),
+                role,
+                created_at: now(),
+                totp_enabled: false,
+            },
+            password_hash,
+            totp: None,
+        };
+        self.save(&stored).await?;
+        let info = stored.info.clone();
+        users.insert(username.to_string(), stored);
+        Ok(info)
+    }
+
+    /// deletes a user, the last admin can't be deleted so nobody is locked out
+    pub async fn delete(&self, username: &str) -> Result<(), Error> {
+        let mut users = self.users.lock().await;
+        let role = match users.get(username) {
+            Some(stored) => stored.info.role,
+            None => return Err(Error::new_not_found(format!("user {} does not exist", username))),
+        };
+        let admins = users.values().filter(|stored| stored.info.role == Role::Admin).count();
+        if role == Role::Admin && admins == 1 {
+            return Err(Error::new_conflict("the last admin can't be deleted".to_string()));
+        }
+
+        self.store.delete(&format!("{}{}", STORE_PREFIX, username)).await?;
+        users.remove(username);
+        Ok(())
+    }
+
+    pub async fn get(&self, username: &str) -> Option<UserInfo> {
+        self.users
+            .lock()
+            .await
+            .get(username)
+            .map(|stored| stored.info.clone())
+    }
+
+    /// all users, oldest first
+    pub async fn list(&self) -> Vec<UserInfo> {
+        let mut users: Vec<UserInfo> = self
+            .users
+            .lock()
+            .await
+            .values()
+            .map(|stored| stored.info.clone())
+            .collect();
+        users.sort_by(|a, b| a.created_at.cmp(&b.created_at).then_with(|| a.username.cmp(&b.username)));
+        users
+    }
+
+    /// checks the password of `username`, argon2 is slow on purpose so this runs on the blocking pool
+    pub async fn verify_password(&self, username: &str, password: &str) -> Option<UserInfo> {
+        let (info, password_hash) = match self.users.lock().await.get(username) {
+            Some(stored) => (Some(stored.info.clone()), stored.password_hash.clone()),
+            None => (None, DUMMY_HASH.to_string()),
+        };
+
+        let password = password.to_string();
+        let valid = tokio::task::spawn_blocking(move || {
+            PasswordHash::new(&password_hash)
+                .map(|hash| {
+                    Argon2::default()
+                        .verify_password(password.as_bytes(), &hash)
+                        .is_ok()
+                })
+                .unwrap_or(false)
+        })
+        .await
+        .unwrap_or(false);
+
+        info.filter(|_| valid)
+    }
+
+    /// turns on two-factor authentication with `secret` once `code` shows the authenticator app
+    /// has it and returns the recovery codes, they can't be shown again later
+    pub async fn enable_totp(&self, username: &str, secret: &str, code: &str) -> Result<Vec<String>, Error> {
+        let secret_bytes = totp::base32_decode(secret)
+            .ok_or_else(|| Error::new_bad_request("invalid totp secret".to_string()))?;
+        let step = totp::verify(&secret_bytes, code, totp::current_step(), 0)
+            .ok_or_else(|| Error::new_bad_request("the code is wrong, check the time of your device".to_string()))?;
+
+        let mut users = self.users.lock().await;
+        let stored = users
+            .get_mut(username)
+            .ok_or_else(|| Error::new_not_found(format!("user {} does not exist", username)))?;
+        if stored.totp.is_some() {
+            return Err(Error::new_conflict("two-factor authentication is already enabled".to_string()));
+        }
+
+        let recovery_codes: Vec<String> = (0..RECOVERY_CODES)
+            .map(|_| gen_random_string(RECOVERY_CODE_LEN).to_lowercase())
+            .collect();
+        stored.totp = Some(Totp {
+            secret: secret.to_string(),
+            recovery_codes: recovery_codes.iter().map(|code| hash_secret(code)).collect(),
+            last_step: step,
+        });
+        stored.info.totp_enabled = true;
+        self.save(stored).await?;
+        Ok(recovery_codes)
+    }
+
+    pub async fn disable_totp(&self, username: &str) -> Result<(), Error> {
+        let mut users = self.users.lock().await;
+        let stored = users
+            .get_mut(username)
+            .ok_or_else(|| Error::new_not_found(format!("user {} does not exist", username)))?;
+        stored.totp = None;
+        stored.info.totp_enabled = false;
+        self.save(stored).await
+    }
+
+    /// checks a code from the authenticator app or a recovery code, either only works once
+    pub async fn verify_second_factor(&self, username: &str, code: &str) -> bool {
+        let mut users = self.users.lock().await;
+        let stored = match users.get_mut(username) {
+            Some(stored) => stored,
+            None => return false,
+        };
+        let totp = match stored.totp.as_mut() {
+            Some(totp) => totp,
+            None => return false,
+        };
+
+        let secret = totp::base32_decode(&totp.secret).unwrap_or_default();
+        if let Some(step) = totp::verify(&secret, code, totp::current_step(), totp.last_step) {
+            totp.last_step = step;
+        } else {
+            let hash = hash_secret(&code.trim().to_lowercase());
+            match totp.recovery_codes.iter().position(|recovery_code| *recovery_code == hash) {
+                Some(index) => {
+                    totp.recovery_codes.remove(index);
+                    tracing::warn!(user = username, left = totp.recovery_codes.len(), "used a recovery code");
+                }
+                None => return false,
+            }
+        }
+
+        if let Err(e) = self.save(stored).await {
+            // a code that couldn't be marked as used must not be accepted
+            tracing::error!(user = username, error = ?e, "unable to save second factor");
+            return false;
+        }
+        true
+    }
+}
@@ -0,0 +1,193 @@
+#[macro_use]
+extern crate rocket;
+
+mod common;
+
+use common::{api_key, now, query_param, TestApp};
+use reqwest::Url;
+use rocket::http::{Header, Status};
+use social_auth::admin::AdminCli;
+use social_auth::api_keys::Scope;
+use social_auth::audit::{Actor, AuditFilter, AuditLog, Outcome};
+
+#[rocket::async_test]
+async fn accounts_refresh_and_revoke() {
+    let app = TestApp::start().await;
+    let access_token = app.save_twitch_token("onestay", now());
+    app.save_twitter_token("onestay");
+    let cli = AdminCli::new(&app.config()).await.unwrap();
+
+    let accounts = cli.accounts().await;
+    assert_eq!(accounts.len(), 2);
+    let (service, twitch) = &accounts[1];
+    assert_eq!(*service, "twitch");
+    assert!(twitch.valid);
+    assert!(twitch.expires_at.unwrap() > now());
+
+    // the token is far from expiring but is replaced anyway
+    cli.refresh("twitch", "onestay").await.unwrap();
+    let saved = app.saved_token("twitch_auth.onestay").unwrap();
+    assert_ne!(saved["access_token"], access_token);
+    assert!(cli.refresh("twitch", "someone").await.is_err());
+    // twitter tokens don't expire
+    cli.refresh("twitter", "onestay").await.unwrap();
+
+    cli.revoke("twitch", "onestay").await.unwrap();
+    assert_eq!(*app.twitch.state.revoked.lock().unwrap(), vec![saved["access_token"].as_str().unwrap()]);
+    assert!(app.saved_token("twitch_auth.onestay").is_none());
+
+    let audit = AuditLog::open(app.store_dir.path().join("audit.log")).await.unwrap();
+    let entries = audit.query(&AuditFilter::default()).await.unwrap();
+    let actions: Vec<&str> = entries.iter().map(|entry| entry.action.as_str()).collect();
+    assert_eq!(actions, vec!["disconnect_account", "refresh_token", "refresh_token"]);
+    assert!(entries.iter().all(|entry| matches!(entry.actor, Actor::Cli)));
+}
+
+#[rocket::async_test]
+async fn authorize_twitch() {
+    let app = TestApp::start().await;
+    let cli = AdminCli::new(&app.config()).await.unwrap();
+
+    let pending = cli.start_authorization("twitch", &[]).await.unwrap();
+    let state = query_param(&pending.url, "state").unwrap();
+    assert!(cli
+        .start_authorization("twitch", &["not:a:scope".to_string()])
+        .await
+        .is_err());
+
+    // the fake twitch issues a token for whatever login is used as the code
+    let callback = format!("http://localhost:8000/twitch/authorize/callback?code=onestay&state={}\n", state);
+    assert_eq!(cli.finish_authorization(pending, &callback).await.unwrap(), "onestay");
+    assert!(app.saved_token("twitch_auth.onestay").is_some());
+
+    let pending = cli.start_authorization("twitch", &[]).await.unwrap();
+    let err = cli
+        .finish_authorization(pending, "http://localhost:8000/twitch/authorize/callback?code=charity&state=other")
+        .await
+        .unwrap_err();
+    assert_eq!(err.message(), "the callback url doesn't belong to this authorization");
+    assert!(app.saved_token("twitch_auth.charity").is_none());
+}
+
+#[rocket::async_test]
+async fn authorize_twitter() {
+    let app = TestApp::start().await;
+    let cli = AdminCli::new(&app.config()).await.unwrap();
+
+    let pending = cli.start_authorization("twitter", &[]).await.unwrap();
+    let oauth_token = query_param(&pending.url, "oauth_token").unwrap();
+
+    // twitter adds the token and verifier to the callback url we gave it
+    let callback = app.twitter.state.callbacks.lock().unwrap().last().cloned().unwrap();
+    let mut callback = Url::parse(&callback).unwrap();
+    callback
+        .query_pairs_mut()
+        .append_pair("oauth_token", &oauth_token)
+        .append_pair("oauth_verifier", "verifier");
+    assert_eq!(cli.finish_authorization(pending, callback.as_str()).await.unwrap(), "onestay");
+    assert!(app.saved_token("twitter_auth.onestay").is_some());
+}
+
+#[rocket::async_test]
+async fn api_keys() {
+    let app = TestApp::start().await;
+    let cli = AdminCli::new(&app.config()).await.unwrap();
+
+    let (info, key) = cli
+        .create_api_key("stream pc", vec![Scope::ReadAuth, Scope::Tweet], Some(10))
+        .await
+        .unwrap();
+    assert!(key.starts_with(&format!("{}.", info.id)));
+    assert!(cli.create_api_key("no scopes", vec![], None).await.is_err());
+
+    // a new cli loads the key from the store like the server would
+    let cli = AdminCli::new(&app.config()).await.unwrap();
+    let keys = cli.api_keys().await;
+    assert_eq!(keys.len(), 1);
+    assert_eq!(keys[0].name, "stream pc");
+    assert_eq!(keys[0].rate_limit, Some(10));
+
+    cli.revoke_api_key(&info.id).await.unwrap();
+    assert!(cli.api_keys().await.is_empty());
+    assert!(cli.revoke_api_key(&info.id).await.is_err());
+
+    let audit = AuditLog::open(app.store_dir.path().join("audit.log")).await.unwrap();
+    let entries = audit.query(&AuditFilter::default()).await.unwrap();
+    assert_eq!(entries[0].action, "revoke_api_key");
+    assert!(matches!(entries[0].outcome, Outcome::Failure { .. }));
+    assert_eq!(entries[1].target.as_deref(), Some(info.id.as_str()));
+}
+
+#[rocket::async_test]
+async fn export_and_import() {
+    let app = TestApp::start().await;
+    app.save_twitch_token("onestay", now());
+    app.save_twitter_token("onestay");
+    let cli = AdminCli::new(&app.config()).await.unwrap();
+    cli.create_api_key("stream pc", vec![Scope::ReadAuth], None).await.unwrap();
+    let export = cli.export().await.unwrap();
+    assert_eq!(export.entries.len(), 3);
+
+    let other = TestApp::start().await;
+    let other_cli = AdminCli::new(&other.config()).await.unwrap();
+    assert_eq!(other_cli.import(&export).await.unwrap(), 3);
+
+    assert_eq!(other.saved_token("twitch_auth.onestay"), app.saved_token("twitch_auth.onestay"));
+    let other_cli = AdminCli::new(&other.config()).await.unwrap();
+    assert_eq!(other_cli.api_keys().await[0].name, "stream pc");
+    assert_eq!(other_cli.accounts().await.len(), 2);
+}
+
+#[rocket::async_test]
+async fn running_server() {
+    let app = TestApp::start().await;
+    app.save_twitch_token("onestay", now());
+    let client = app.client().await;
+    let cli = AdminCli::new(&app.config()).await.unwrap();
+
+    // keys are checked in the store for every request
+    let (info, key) = cli.create_api_key("stream pc", vec![Scope::ReadAuth], None).await.unwrap();
+    let avail = |key: Header<'static>| client.get("/api/v1/avail").header(key);
+    assert_eq!(avail(Header::new("Authorization", key.clone())).dispatch().await.status(), Status::Ok);
+    let dashboard_key = api_key(&client).await;
+    assert_eq!(cli.api_keys().await.len(), 2);
+    cli.revoke_api_key(&info.id).await.unwrap();
+    assert_eq!(avail(Header::new("Authorization", key)).dispatch().await.status(), Status::Unauthorized);
+    assert_eq!(avail(dashboard_key).dispatch().await.status(), Status::Ok);
+
+    // the server would keep using the tokens it loaded
+    let err = cli.refresh("twitch", "onestay").await.unwrap_err();
+    assert_eq!(err.message(), "the server is running, stop it first or use the dashboard");
+    assert!(cli.revoke("twitch", "onestay").await.is_err());
+    assert!(cli.start_authorization("twitch", &[]).await.is_err());
+    let export = cli.export().await.unwrap();
+    assert!(cli.import(&export).await.is_err());
+
+    // a second server would too
+    let err = social_auth::build(app.config()).await.err().unwrap();
+    assert_eq!(
+        err.problems[0].to_string(),
+        "token_store.path: another server or social_auth-admin is using the token store"
+    );
+
+    // and so would rotating the key
+    let mut config = app.config();
+    config.token_encryption_key = Some(base64::encode([1u8; 32]));
+    let err = social_auth::rotate_key(&config).await.err().unwrap();
+    assert_eq!(
+        err.problems[0].to_string(),
+        "token_store.path: another server or social_auth-admin is using the token store"
+    );
+
+    // loading doesn't write to the store while the server runs
+    app.save_twitter_token("onestay");
+    let plaintext = app.stored_value("twitter_auth.onestay").unwrap();
+    let encrypting_cli = AdminCli::new(&config).await.unwrap();
+    assert_eq!(encrypting_cli.accounts().await.len(), 2);
+    assert_eq!(app.stored_value("twitter_auth.onestay").unwrap(), plaintext);
+    drop(encrypting_cli);
+
+    drop(client);
+    let cli = AdminCli::new(&app.config()).await.unwrap();
+    cli.refresh("twitch", "onestay").await.unwrap();
+}
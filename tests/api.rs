@@ -0,0 +1,252 @@
+#[macro_use]
+extern crate rocket;
+
+mod common;
+
+use common::{api_key, json, now, TestApp};
+use rocket::http::{ContentType, Status};
+use rocket::serde::json::json;
+
+#[rocket::async_test]
+async fn twitch_update() {
+    let app = TestApp::start().await;
+    app.save_twitch_token("onestay", now());
+    let client = app.client().await;
+    let key = api_key(&client).await;
+
+    let res = client
+        .post("/api/v1/twitch/update")
+        .header(key.clone())
+        .header(ContentType::JSON)
+        .body(r#"{"game":"Celeste","title":"any%","login":"onestay"}"#)
+        .dispatch()
+        .await;
+    assert_eq!(res.status(), Status::NoContent);
+
+    let updates = app.twitch.state.channel_updates.lock().unwrap();
+    assert_eq!(updates.len(), 1);
+    assert_eq!(updates[0].0, "1234");
+    assert_eq!(updates[0].1, json!({ "game_id": "509658", "title": "any%" }));
+}
+
+#[rocket::async_test]
+async fn twitch_login_to_id() {
+    let app = TestApp::start().await;
+    app.save_twitch_token("onestay", now());
+    let client = app.client().await;
+    let key = api_key(&client).await;
+
+    let res = client
+        .get("/api/v1/twitch/login_to_id?login=charity")
+        .header(key.clone())
+        .dispatch()
+        .await;
+    assert_eq!(res.status(), Status::Ok);
+    let body = json(res).await;
+    assert_eq!(body["data"], "5678");
+
+    let res = client
+        .get("/api/v1/twitch/login_to_id?login=nobody")
+        .header(key.clone())
+        .dispatch()
+        .await;
+    assert_eq!(res.status(), Status::BadRequest);
+}
+
+#[rocket::async_test]
+async fn twitch_commercial() {
+    let app = TestApp::start().await;
+    app.save_twitch_token("onestay", now());
+    let client = app.client().await;
+    let key = api_key(&client).await;
+
+    let res = client
+        .post("/api/v1/twitch/commercial?login=onestay&length=90")
+        .header(key.clone())
+        .dispatch()
+        .await;
+    assert_eq!(res.status(), Status::Ok);
+    let body = json(res).await;
+    assert_eq!(body["length"], 90);
+    assert_eq!(body["retry_after"], 480);
+
+    let commercials = app.twitch.state.commercials.lock().unwrap();
+    assert_eq!(commercials[0]["broadcaster_id"], "1234");
+}
+
+#[rocket::async_test]
+async fn twitch_commercial_without_data() {
+    let app = TestApp::start().await;
+    app.save_twitch_token("onestay", now());
+    let client = app.client().await;
+    let key = api_key(&client).await;
+
+    app.twitch.state.respond_next("/helix/channels/commercial", json!({ "data": [] }));
+    let res = client
+        .post("/api/v1/twitch/commercial?login=onestay&length=90")
+        .header(key)
+        .dispatch()
+        .await;
+    assert_eq!(res.status(), Status::BadGateway);
+    let body = json(res).await;
+    assert_eq!(body["code"], "upstream_error");
+    assert_eq!(body["message"], "twitch responded with 200: the commercial response has no data");
+}
+
+#[rocket::async_test]
+async fn twitch_expired_token_is_refreshed() {
+    let app = TestApp::start().await;
+    let old_token = app.save_twitch_token("onestay", 0);
+    let client = app.client().await;
+    let key = api_key(&client).await;
+
+    let res = client
+        .get("/api/v1/auth?service=twitch")
+        .header(key.clone())
+        .dispatch()
+        .await;
+    assert_eq!(res.status(), Status::Ok);
+    let token = json(res).await;
+    assert_ne!(token["access_token"], old_token);
+
+    let saved = app.saved_token("twitch_auth.onestay").unwrap();
+    assert_eq!(saved["access_token"], token["access_token"]);
+
+    let res = client
+        .get("/api/v1/twitch/refresh_status")
+        .header(key.clone())
+        .dispatch()
+        .await;
+    let status = json(res).await;
+    assert_eq!(status["data"]["success"], true);
+}
+
+#[rocket::async_test]
+async fn twitch_multiple_accounts() {
+    let app = TestApp::start().await;
+    app.save_twitch_token("onestay", now());
+    let charity_token = app.save_twitch_token("charity", now());
+    let client = app.client().await;
+    let key = api_key(&client).await;
+
+    let res = client
+        .get("/api/v1/auth?service=twitch")
+        .header(key.clone())
+        .dispatch()
+        .await;
+    assert_eq!(res.status(), Status::BadRequest);
+
+    let res = client
+        .get("/api/v1/auth?service=twitch&account=Charity")
+        .header(key.clone())
+        .dispatch()
+        .await;
+    let token = json(res).await;
+    assert_eq!(token["access_token"], charity_token);
+
+    // updating a channel uses that channel's own token
+    let res = client
+        .post("/api/v1/twitch/update")
+        .header(key.clone())
+        .header(ContentType::JSON)
+        .body(r#"{"game":"Celeste","title":"charity stream","login":"charity"}"#)
+        .dispatch()
+        .await;
+    assert_eq!(res.status(), Status::NoContent);
+    assert_eq!(app.twitch.state.channel_updates.lock().unwrap()[0].0, "5678");
+}
+
+#[rocket::async_test]
+async fn post_tweet() {
+    let app = TestApp::start().await;
+    app.save_twitter_token("onestay");
+    let client = app.client().await;
+    let key = api_key(&client).await;
+
+    let tweet = || {
+        client
+            .post("/api/v1/tweet")
+            .header(key.clone())
+            .header(ContentType::JSON)
+            .body(r#"{"body":"going live"}"#)
+    };
+
+    let res = tweet().dispatch().await;
+    assert_eq!(res.status(), Status::NoContent);
+    assert_eq!(*app.twitter.state.tweets.lock().unwrap(), vec!["going live"]);
+
+    // twitter rejects duplicates and we pass the reason on
+    let res = tweet().dispatch().await;
+    assert_eq!(res.status(), Status::BadGateway);
+    let body = json(res).await;
+    assert_eq!(body["code"], "upstream_error");
+    assert_eq!(body["upstream"]["service"], "twitter");
+    assert_eq!(body["upstream"]["status"], 403);
+    assert_eq!(body["upstream"]["code"], "187");
+    assert!(body["message"].as_str().unwrap().contains("Status is a duplicate."));
+}
+
+#[rocket::async_test]
+async fn post_tweet_without_account() {
+    let app = TestApp::start().await;
+    let client = app.client().await;
+    let key = api_key(&client).await;
+
+    let res = client
+        .post("/api/v1/tweet")
+        .header(key.clone())
+        .header(ContentType::JSON)
+        .body(r#"{"body":"going live"}"#)
+        .dispatch()
+        .await;
+    assert_eq!(res.status(), Status::Conflict);
+    assert_eq!(json(res).await["code"], "account_not_connected");
+    assert!(app.twitter.state.tweets.lock().unwrap().is_empty());
+}
+
+#[rocket::async_test]
+async fn unknown_service() {
+    let app = TestApp::start().await;
+    let client = app.client().await;
+    let key = api_key(&client).await;
+
+    let res = client
+        .get("/api/v1/auth?service=myspace")
+        .header(key.clone())
+        .dispatch()
+        .await;
+    assert_eq!(res.status(), Status::NotFound);
+}
+
+#[rocket::async_test]
+async fn twitch_missing_scope() {
+    let app = TestApp::start().await;
+    app.save_twitch_token_with_scopes("onestay", now(), &["user:read:email"]);
+    let client = app.client().await;
+    let key = api_key(&client).await;
+
+    let res = client
+        .post("/api/v1/twitch/update")
+        .header(key.clone())
+        .header(ContentType::JSON)
+        .body(r#"{"game":"Celeste","title":"any%","login":"onestay"}"#)
+        .dispatch()
+        .await;
+    assert_eq!(res.status(), Status::Forbidden);
+    let body = json(res).await;
+    assert_eq!(body["code"], "missing_upstream_scope");
+    assert!(body["message"].as_str().unwrap().contains("channel:manage:broadcast"));
+
+    let res = client
+        .post("/api/v1/twitch/commercial?login=onestay&length=90")
+        .header(key)
+        .dispatch()
+        .await;
+    assert_eq!(res.status(), Status::Forbidden);
+    let body = json(res).await;
+    assert_eq!(body["code"], "missing_upstream_scope");
+    assert!(body["message"].as_str().unwrap().contains("channel:edit:commercial"));
+
+    assert!(app.twitch.state.channel_updates.lock().unwrap().is_empty());
+    assert!(app.twitch.state.commercials.lock().unwrap().is_empty());
+}
@@ -0,0 +1,135 @@
+#[macro_use]
+extern crate rocket;
+
+mod common;
+
+use common::{api_key_with_scopes, json, login, TestApp, STORE_KINDS};
+use rocket::http::{ContentType, Header, Status};
+use social_auth::api_keys::{ApiKeys, Scope};
+
+#[rocket::async_test]
+async fn scopes_are_enforced() {
+    let app = TestApp::start().await;
+    app.save_twitter_token("onestay");
+    let client = app.client().await;
+    let key = api_key_with_scopes(&client, vec![Scope::Tweet]).await;
+
+    let res = client
+        .post("/api/v1/tweet")
+        .header(key.clone())
+        .header(ContentType::JSON)
+        .body(r#"{"body":"going live"}"#)
+        .dispatch()
+        .await;
+    assert_eq!(res.status(), Status::NoContent);
+
+    let res = client
+        .get("/api/v1/auth?service=twitter")
+        .header(key)
+        .dispatch()
+        .await;
+    assert_eq!(res.status(), Status::Forbidden);
+    let body = json(res).await;
+    assert_eq!(body["message"], "api key is missing the read_auth scope");
+}
+
+#[rocket::async_test]
+async fn keys_survive_restart() {
+    for kind in STORE_KINDS {
+        let app = TestApp::start_with_store(kind).await;
+        let client = app.client().await;
+        let key = api_key_with_scopes(&client, vec![Scope::ReadAuth]).await;
+        drop(client);
+
+        let client = app.client().await;
+        let res = client.get("/api/v1/avail").header(key).dispatch().await;
+        assert_eq!(res.status(), Status::Ok);
+
+        let keys = client.rocket().state::<ApiKeys>().unwrap().list().await;
+        assert_eq!(keys.len(), 1);
+        assert!(keys[0].last_used.is_some());
+    }
+}
+
+#[rocket::async_test]
+async fn revoked_key_is_rejected() {
+    let app = TestApp::start().await;
+    let client = app.client().await;
+    let key = api_key_with_scopes(&client, vec![Scope::ReadAuth]).await;
+    let api_keys = client.rocket().state::<ApiKeys>().unwrap();
+    let id = api_keys.list().await[0].id.clone();
+
+    // only logged in users can manage keys
+    let res = client
+        .post(format!("/api_keys/{}/revoke", id))
+        .dispatch()
+        .await;
+    assert_eq!(res.status(), Status::NotFound);
+
+    login(&client).await;
+    let res = client
+        .post(format!("/api_keys/{}/revoke", id))
+        .dispatch()
+        .await;
+    assert_eq!(res.status(), Status::SeeOther);
+    assert!(api_keys.list().await.is_empty());
+
+    let res = client.get("/api/v1/avail").header(key).dispatch().await;
+    assert_eq!(res.status(), Status::Unauthorized);
+
+    // the secret has to match as well
+    let res = client
+        .get("/api/v1/avail")
+        .header(Header::new("Authorization", format!("{}.wrong", id)))
+        .dispatch()
+        .await;
+    assert_eq!(res.status(), Status::Unauthorized);
+}
+
+#[rocket::async_test]
+async fn create_key_from_dashboard() {
+    let app = TestApp::start().await;
+    let client = app.client().await;
+    let api_keys = client.rocket().state::<ApiKeys>().unwrap();
+
+    let create = || {
+        client
+            .post("/api_keys")
+            .header(ContentType::Form)
+            .body("name=MarathonTools&scopes=read_auth&scopes=twitch_update")
+    };
+
+    let res = create().dispatch().await;
+    assert_eq!(res.status(), Status::NotFound);
+    assert!(api_keys.list().await.is_empty());
+
+    login(&client).await;
+    let res = create().dispatch().await;
+    assert_eq!(res.status(), Status::SeeOther);
+    assert!(res.cookies().get("_flash").is_none());
+    let keys = api_keys.list().await;
+    assert_eq!(keys.len(), 1);
+    assert_eq!(keys[0].name, "MarathonTools");
+    assert_eq!(keys[0].scopes, vec![Scope::ReadAuth, Scope::TwitchUpdate]);
+
+    // the key is only sent encrypted
+    let key = client.cookies().get_private("new_api_key").unwrap().value().to_string();
+    assert!(key.starts_with(&format!("{}.", keys[0].id)));
+    assert!(!client.cookies().get("new_api_key").unwrap().value().contains(&key));
+    let res = client.get("/api/v1/avail").header(Header::new("Authorization", key)).dispatch().await;
+    assert_eq!(res.status(), Status::Ok);
+
+    // and shown once
+    let res = client.get("/").dispatch().await;
+    assert_eq!(res.status(), Status::Ok);
+    assert!(client.cookies().get_private("new_api_key").is_none());
+
+    let res = client
+        .post("/api_keys")
+        .header(ContentType::Form)
+        .body("name=&scopes=tweet")
+        .dispatch()
+        .await;
+    assert_eq!(res.status(), Status::SeeOther);
+    assert_eq!(api_keys.list().await.len(), 1);
+}
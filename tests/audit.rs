@@ -0,0 +1,191 @@
+#[macro_use]
+extern crate rocket;
+
+mod common;
+
+use common::{api_key, api_key_with_scopes, cheap_password_hash, json, login, login_as, now, TestApp, PASSWORD};
+use rocket::http::{ContentType, Status};
+use social_auth::api_keys::{ApiKeys, Scope};
+use social_auth::audit::{AuditFilter, AuditLog};
+use social_auth::users::{Role, Users};
+
+#[rocket::async_test]
+async fn api_actions_are_recorded() {
+    let app = TestApp::start().await;
+    app.save_twitter_token("onestay");
+    app.save_twitch_token("onestay", now());
+    let client = app.client().await;
+    let key = api_key(&client).await;
+    let key_id = client.rocket().state::<ApiKeys>().unwrap().list().await[0].id.clone();
+
+    let res = client
+        .post("/api/v1/tweet")
+        .header(key.clone())
+        .header(ContentType::JSON)
+        .body(r#"{"body":"going live"}"#)
+        .dispatch()
+        .await;
+    assert_eq!(res.status(), Status::NoContent);
+
+    let res = client
+        .post("/api/v1/twitch/update")
+        .header(key.clone())
+        .header(ContentType::JSON)
+        .body(r#"{"game":"Celeste","title":"any%","login":"nobody","account":"onestay"}"#)
+        .dispatch()
+        .await;
+    assert_eq!(res.status(), Status::BadRequest);
+
+    let res = client.get("/api/v1/audit").header(key.clone()).dispatch().await;
+    assert_eq!(res.status(), Status::Ok);
+    let body = json(res).await;
+    let entries = body["data"].as_array().unwrap();
+    assert_eq!(entries.len(), 2);
+
+    // newest first
+    assert_eq!(entries[0]["action"], "update_channel");
+    assert_eq!(entries[0]["target"], "nobody");
+    assert_eq!(entries[0]["outcome"]["status"], "failure");
+    assert!(entries[0]["outcome"]["message"].is_string());
+
+    assert_eq!(entries[1]["action"], "tweet");
+    assert_eq!(entries[1]["service"], "twitter");
+    assert_eq!(entries[1]["target"], "onestay");
+    assert_eq!(entries[1]["actor"]["type"], "api_key");
+    assert_eq!(entries[1]["actor"]["id"], key_id.as_str());
+    assert_eq!(entries[1]["outcome"]["status"], "success");
+}
+
+#[rocket::async_test]
+async fn audit_can_be_filtered() {
+    let app = TestApp::start().await;
+    app.save_twitter_token("onestay");
+    let client = app.client().await;
+    let key = api_key(&client).await;
+
+    for body in ["one", "two", "three"] {
+        client
+            .post("/api/v1/tweet")
+            .header(key.clone())
+            .header(ContentType::JSON)
+            .body(format!(r#"{{"body":"{}"}}"#, body))
+            .dispatch()
+            .await;
+    }
+    client
+        .delete("/api/v1/auth?service=twitter")
+        .header(key.clone())
+        .dispatch()
+        .await;
+
+    let res = client
+        .get("/api/v1/audit?action=tweet&limit=2")
+        .header(key.clone())
+        .dispatch()
+        .await;
+    let body = json(res).await;
+    assert_eq!(body["data"].as_array().unwrap().len(), 2);
+
+    let res = client
+        .get(format!("/api/v1/audit?service=twitter&since={}", now() - 60))
+        .header(key.clone())
+        .dispatch()
+        .await;
+    let body = json(res).await;
+    assert_eq!(body["data"].as_array().unwrap().len(), 4);
+    assert_eq!(body["data"][0]["action"], "disconnect_account");
+
+    let res = client
+        .get("/api/v1/audit?actor=somebody-else")
+        .header(key)
+        .dispatch()
+        .await;
+    let body = json(res).await;
+    assert!(body["data"].as_array().unwrap().is_empty());
+
+    let key = api_key_with_scopes(&client, vec![Scope::Tweet]).await;
+    let res = client.get("/api/v1/audit").header(key).dispatch().await;
+    assert_eq!(res.status(), Status::Forbidden);
+}
+
+#[rocket::async_test]
+async fn dashboard_actions_are_recorded() {
+    let app = TestApp::start().await;
+    let client = app.client().await;
+    login(&client).await;
+
+    client
+        .post("/api_keys")
+        .header(ContentType::Form)
+        .body("name=obs&scopes=read_auth")
+        .dispatch()
+        .await;
+    let id = client.rocket().state::<ApiKeys>().unwrap().list().await[0].id.clone();
+    client.post(format!("/api_keys/{}/revoke", id)).dispatch().await;
+    client.post("/api_keys/missing/revoke").dispatch().await;
+
+    let entries = client
+        .rocket()
+        .state::<AuditLog>()
+        .unwrap()
+        .query(&AuditFilter {
+            actor: Some("admin".to_string()),
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+    let actions: Vec<(&str, Option<&str>)> = entries
+        .iter()
+        .map(|entry| (entry.action.as_str(), entry.target.as_deref()))
+        .collect();
+    assert_eq!(
+        actions,
+        vec![
+            ("revoke_api_key", Some("missing")),
+            ("revoke_api_key", Some(id.as_str())),
+            ("create_api_key", Some(id.as_str())),
+        ]
+    );
+}
+
+#[rocket::async_test]
+async fn audit_page_is_for_admins() {
+    let app = TestApp::start().await;
+    let client = app.client().await;
+    client
+        .rocket()
+        .state::<Users>()
+        .unwrap()
+        .insert("operator", cheap_password_hash(PASSWORD), Role::Operator)
+        .await
+        .unwrap();
+
+    login_as(&client, "operator", PASSWORD).await;
+    let res = client.get("/audit").dispatch().await;
+    assert_eq!(res.status(), Status::Forbidden);
+
+    login(&client).await;
+    let res = client.get("/audit?action=tweet").dispatch().await;
+    assert_eq!(res.status(), Status::Ok);
+}
+
+#[rocket::async_test]
+async fn audit_survives_restart() {
+    let app = TestApp::start().await;
+    app.save_twitter_token("onestay");
+    let client = app.client().await;
+    let key = api_key(&client).await;
+    client
+        .post("/api/v1/tweet")
+        .header(key.clone())
+        .header(ContentType::JSON)
+        .body(r#"{"body":"going live"}"#)
+        .dispatch()
+        .await;
+    drop(client);
+
+    let client = app.client().await;
+    let res = client.get("/api/v1/audit").header(key).dispatch().await;
+    let body = json(res).await;
+    assert_eq!(body["data"].as_array().unwrap().len(), 1);
+}
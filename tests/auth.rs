@@ -0,0 +1,270 @@
+#[macro_use]
+extern crate rocket;
+
+mod common;
+
+use common::{api_key, connect_twitch, connect_twitter, json, login, query_param, TestApp};
+use rocket::http::Status;
+use rocket::serde::json::json;
+
+#[rocket::async_test]
+async fn dashboard_requires_login() {
+    let app = TestApp::start().await;
+    let client = app.client().await;
+
+    let res = client.get("/").dispatch().await;
+    assert_eq!(res.status(), Status::SeeOther);
+    assert_eq!(res.headers().get_one("Location"), Some("/login"));
+
+    let res = client
+        .post("/login")
+        .header(rocket::http::ContentType::Form)
+        .body("username=admin&password=wrong")
+        .dispatch()
+        .await;
+    assert_eq!(res.headers().get_one("Location"), Some("/login"));
+
+    login(&client).await;
+    let res = client.get("/").dispatch().await;
+    assert_eq!(res.status(), Status::Ok);
+}
+
+#[rocket::async_test]
+async fn twitch_authorize_flow() {
+    let app = TestApp::start().await;
+    let client = app.client().await;
+    let key = api_key(&client).await;
+
+    // only a logged in user can connect accounts
+    let res = client.get("/twitch/authorize").dispatch().await;
+    assert_eq!(res.status(), Status::NotFound);
+
+    login(&client).await;
+    let res = client.get("/twitch/authorize").dispatch().await;
+    assert_eq!(res.status(), Status::SeeOther);
+    let location = res.headers().get_one("Location").unwrap();
+    assert!(location.starts_with(&format!("{}/oauth2/authorize?", app.twitch.url)));
+    assert!(location.contains("client_id=twitch-client-id"));
+
+    let res = connect_twitch(&client, "onestay").await;
+    assert_eq!(res.status(), Status::SeeOther);
+    assert_eq!(res.headers().get_one("Location"), Some("/"));
+
+    let saved = app.saved_token("twitch_auth.onestay").expect("token wasn't saved");
+    let res = client
+        .get("/api/v1/auth?service=twitch")
+        .header(key.clone())
+        .dispatch()
+        .await;
+    assert_eq!(res.status(), Status::Ok);
+    let token = json(res).await;
+    assert_eq!(token["access_token"], saved["access_token"]);
+}
+
+#[rocket::async_test]
+async fn twitch_authorize_invalid_code() {
+    let app = TestApp::start().await;
+    let client = app.client().await;
+    login(&client).await;
+
+    let res = connect_twitch(&client, "invalid").await;
+    assert_eq!(res.status(), Status::BadGateway);
+    assert!(app.saved_token("twitch_auth.invalid").is_none());
+}
+
+#[rocket::async_test]
+async fn twitch_callback_requires_state() {
+    let app = TestApp::start().await;
+    let client = app.client().await;
+    login(&client).await;
+
+    // a code the attacker got for their own account
+    let res = client
+        .get("/twitch/authorize/callback?code=attacker")
+        .dispatch()
+        .await;
+    assert_eq!(res.status(), Status::BadRequest);
+
+    let res = client
+        .get("/twitch/authorize/callback?code=attacker&state=guessed")
+        .dispatch()
+        .await;
+    assert_eq!(res.status(), Status::BadRequest);
+
+    // a state can only be used once
+    let res = client.get("/twitch/authorize").dispatch().await;
+    let state = query_param(res.headers().get_one("Location").unwrap(), "state").unwrap();
+    let callback = format!("/twitch/authorize/callback?code=onestay&state={}", state);
+    let res = client.get(&callback).dispatch().await;
+    assert_eq!(res.status(), Status::SeeOther);
+    let res = client.get(&callback).dispatch().await;
+    assert_eq!(res.status(), Status::BadRequest);
+
+    // states are bound to the login session that started the authorization
+    let res = client.get("/twitch/authorize").dispatch().await;
+    let state = query_param(res.headers().get_one("Location").unwrap(), "state").unwrap();
+    login(&client).await;
+    let res = client
+        .get(format!("/twitch/authorize/callback?code=attacker&state={}", state))
+        .dispatch()
+        .await;
+    assert_eq!(res.status(), Status::BadRequest);
+
+    // and to the service
+    let res = client.get("/twitch/authorize").dispatch().await;
+    let state = query_param(res.headers().get_one("Location").unwrap(), "state").unwrap();
+    let res = client
+        .get(format!("/twitter/authorize/callback?oauth_token=request-token-0&oauth_verifier=verifier&state={}", state))
+        .dispatch()
+        .await;
+    assert_eq!(res.status(), Status::BadRequest);
+
+    assert!(app.saved_token("twitch_auth.attacker").is_none());
+}
+
+#[rocket::async_test]
+async fn twitter_authorize_flow() {
+    let app = TestApp::start().await;
+    let client = app.client().await;
+    let key = api_key(&client).await;
+    login(&client).await;
+
+    let res = connect_twitter(&app, &client).await;
+    assert_eq!(res.status(), Status::SeeOther);
+    assert_eq!(res.headers().get_one("Location"), Some("/"));
+
+    let saved = app.saved_token("twitter_auth.onestay").expect("token wasn't saved");
+    assert_eq!(saved["Access"]["access"]["key"], "access-token");
+
+    let res = client
+        .get("/api/v1/avail")
+        .header(key.clone())
+        .dispatch()
+        .await;
+    let avail = json(res).await;
+    assert_eq!(avail["twitter"], true);
+    assert_eq!(avail["twitch"], false);
+    assert_eq!(avail["accounts"]["twitter"][0], "onestay");
+}
+
+#[rocket::async_test]
+async fn twitter_callback_requires_matching_request_token() {
+    let app = TestApp::start().await;
+    let client = app.client().await;
+    login(&client).await;
+
+    let res = client.get("/twitter/authorize").dispatch().await;
+    assert_eq!(res.status(), Status::SeeOther);
+    let callback = app.twitter.state.callbacks.lock().unwrap()[0].clone();
+    let state = query_param(&callback, "state").expect("callback url has no state");
+
+    // a request token from an authorization someone else started
+    let res = client
+        .get(format!(
+            "/twitter/authorize/callback?state={}&oauth_token=request-token-42&oauth_verifier=verifier",
+            state
+        ))
+        .dispatch()
+        .await;
+    assert_eq!(res.status(), Status::BadRequest);
+    assert!(app.saved_token("twitter_auth.onestay").is_none());
+}
+
+#[rocket::async_test]
+async fn api_requires_key() {
+    let app = TestApp::start().await;
+    let client = app.client().await;
+
+    let res = client.get("/api/v1/avail").dispatch().await;
+    assert_eq!(res.status(), Status::Unauthorized);
+    assert_eq!(json(res).await["code"], "unauthorized");
+
+    let res = client
+        .get("/api/v1/avail")
+        .header(rocket::http::Header::new("Authorization", "wrong"))
+        .dispatch()
+        .await;
+    assert_eq!(res.status(), Status::Unauthorized);
+}
+
+#[rocket::async_test]
+async fn twitch_scope_selection() {
+    let app = TestApp::start().await;
+    let client = app.client().await;
+    login(&client).await;
+
+    let res = client.get("/twitch/authorize").dispatch().await;
+    let location = res.headers().get_one("Location").unwrap();
+    assert_eq!(
+        query_param(location, "scope").unwrap(),
+        "channel:manage:broadcast channel:edit:commercial user:read:email"
+    );
+
+    let res = client
+        .get("/twitch/authorize?scopes=chat%3Aread&scopes=chat%3Aedit")
+        .dispatch()
+        .await;
+    let location = res.headers().get_one("Location").unwrap();
+    assert_eq!(query_param(location, "scope").unwrap(), "chat:read chat:edit");
+
+    let res = client
+        .get("/twitch/authorize?scopes=channel%3Amoderate")
+        .dispatch()
+        .await;
+    assert_eq!(res.status(), Status::BadRequest);
+}
+
+#[rocket::async_test]
+async fn legacy_tokens_are_migrated() {
+    let app = TestApp::start().await;
+    let (access_token, refresh_token) = app.twitch.state.issue("onestay");
+    let legacy = json!({
+        "access_token": access_token,
+        "refresh_token": refresh_token,
+        "expires_in": 14400,
+        "token_type": "bearer"
+    });
+    app.store_value("twitch_auth", legacy.to_string().as_bytes());
+    app.save_twitter_token("onestay");
+    let twitter = app.stored_value("twitter_auth.onestay").unwrap();
+    app.store_value("twitter_auth", &twitter);
+
+    let client = app.client().await;
+    let key = api_key(&client).await;
+    let res = client.get("/api/v1/avail").header(key).dispatch().await;
+    let body = json(res).await;
+    assert_eq!(body["accounts"]["twitch"], json!(["onestay"]));
+    assert_eq!(body["accounts"]["twitter"], json!(["onestay"]));
+    assert!(app.stored_value("twitch_auth").is_none());
+    assert!(app.stored_value("twitter_auth").is_none());
+    // a legacy token has no obtained_at, so the refresh task may have replaced it already
+    let saved = app.saved_token("twitch_auth.onestay").unwrap();
+    let saved = saved["access_token"].as_str().unwrap();
+    assert_eq!(app.twitch.state.access_tokens.lock().unwrap().get(saved).unwrap(), "onestay");
+}
+
+#[rocket::async_test]
+async fn failed_migration_keeps_the_legacy_token() {
+    let app = TestApp::start().await;
+    // twitch doesn't know this token anymore and twitter can't be reached
+    let revoked = json!({
+        "access_token": "revoked",
+        "refresh_token": "revoked",
+        "expires_in": 14400,
+        "token_type": "bearer"
+    });
+    app.store_value("twitch_auth", revoked.to_string().as_bytes());
+    app.save_twitter_token("onestay");
+    let twitter = app.stored_value("twitter_auth.onestay").unwrap();
+    app.store_value("twitter_auth", &twitter);
+    let mut config = app.config();
+    config.twitter.as_mut().unwrap().api_base_url = "http://127.0.0.1:1".to_string();
+
+    let client = app.client_with(config).await;
+    let key = api_key(&client).await;
+    let res = client.get("/api/v1/avail").header(key).dispatch().await;
+    assert_eq!(res.status(), Status::Ok);
+    assert_eq!(json(res).await["accounts"]["twitch"], json!([]));
+    assert!(app.stored_value("twitch_auth").is_some());
+    assert!(app.stored_value("twitter_auth").is_some());
+}
@@ -0,0 +1,125 @@
+#[macro_use]
+extern crate rocket;
+
+mod common;
+
+use common::{now, TestApp};
+use rocket::serde::json::json;
+use social_auth_client::{
+    Actor, AuditQuery, Client, ErrorCode, ErrorResponse, Outcome, PostTweetRequest, TwitchUpdateRequest,
+};
+
+#[rocket::async_test]
+async fn accounts() {
+    let app = TestApp::start().await;
+    let access_token = app.save_twitch_token("onestay", now());
+    let (url, key) = app.serve().await;
+    let client = Client::new(&url, &key).unwrap();
+
+    let avail = client.avail().await.unwrap();
+    assert!(avail.services["twitch"]);
+    assert!(!avail.services["twitter"]);
+    assert_eq!(avail.accounts["twitch"], vec!["onestay"]);
+
+    let token = client.token("twitch", None).await.unwrap();
+    assert_eq!(token["access_token"], access_token);
+
+    let status = client.status().await.unwrap();
+    assert!(status["twitch"].accounts[0].valid);
+    assert_eq!(client.twitch_refresh_status(Some("onestay")).await.unwrap().map(|s| s.success), None);
+
+    client.disconnect("twitch", Some("onestay")).await.unwrap();
+    assert!(client.avail().await.unwrap().accounts["twitch"].is_empty());
+}
+
+#[rocket::async_test]
+async fn twitch() {
+    let app = TestApp::start().await;
+    app.save_twitch_token("onestay", now());
+    let (url, key) = app.serve().await;
+    let client = Client::new(&url, &key).unwrap();
+
+    assert_eq!(client.twitch_login_to_id("charity", None).await.unwrap(), "5678");
+
+    client
+        .twitch_update(&TwitchUpdateRequest {
+            game: "Celeste".to_string(),
+            title: "any%".to_string(),
+            login: "onestay".to_string(),
+            account: None,
+        })
+        .await
+        .unwrap();
+    let updates = app.twitch.state.channel_updates.lock().unwrap().clone();
+    assert_eq!(updates[0].1, json!({ "game_id": "509658", "title": "any%" }));
+
+    let ad = client.twitch_commercial("onestay", 90, None).await.unwrap();
+    assert_eq!(ad.length, 90);
+    assert_eq!(ad.retry_after, 480);
+}
+
+#[rocket::async_test]
+async fn tweet_and_audit() {
+    let app = TestApp::start().await;
+    app.save_twitter_token("onestay");
+    let (url, key) = app.serve().await;
+    let client = Client::new(&url, &key).unwrap();
+    let tweet = PostTweetRequest {
+        body: "going live".to_string(),
+        account: None,
+    };
+
+    client.tweet(&tweet).await.unwrap();
+    assert_eq!(*app.twitter.state.tweets.lock().unwrap(), vec!["going live"]);
+
+    let entries = client
+        .audit(&AuditQuery {
+            action: Some("tweet".to_string()),
+            ..AuditQuery::default()
+        })
+        .await
+        .unwrap();
+    assert_eq!(entries.len(), 1);
+    assert!(matches!(entries[0].actor, Actor::ApiKey { .. }));
+    assert!(matches!(entries[0].outcome, Outcome::Success));
+
+    // twitter rejects duplicates
+    let err = client.tweet(&tweet).await.unwrap_err();
+    assert_eq!(err.code(), Some(ErrorCode::UpstreamError));
+    assert_eq!(err.status(), Some(502));
+    let upstream = err.upstream().unwrap();
+    assert_eq!(upstream.service, "twitter");
+    assert_eq!(upstream.status, Some(403));
+    assert_eq!(upstream.code.as_deref(), Some("187"));
+}
+
+#[rocket::async_test]
+async fn errors() {
+    let app = TestApp::start().await;
+    let (url, key) = app.serve().await;
+
+    let client = Client::new(&url, &key).unwrap();
+    let err = client.token("twitch", None).await.unwrap_err();
+    assert_eq!(err.code(), Some(ErrorCode::AccountNotConnected));
+    assert_eq!(err.status(), Some(409));
+
+    let client = Client::new(&url, "wrong").unwrap();
+    let err = client.avail().await.unwrap_err();
+    assert_eq!(err.code(), Some(ErrorCode::Unauthorized));
+
+    assert!(Client::new("not a url", &key).is_err());
+}
+
+#[test]
+fn unknown_error_codes() {
+    // a code a newer server added
+    let response: ErrorResponse = serde_json::from_value(json!({
+        "status": 418,
+        "error": "I'm a teapot",
+        "code": "teapot",
+        "message": "short and stout"
+    }))
+    .unwrap();
+    assert_eq!(response.code, ErrorCode::Unknown);
+    assert_eq!(response.status, 418);
+}
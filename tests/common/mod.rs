@@ -0,0 +1,728 @@
+// fake twitch and twitter apis and helpers to run the server against them
+#![allow(dead_code)]
+
+use rocket::fairing::AdHoc;
+use rocket::form::Form;
+use rocket::http::{ContentType, Header, Status};
+use rocket::local::asynchronous::{Client, LocalResponse};
+use rocket::request::{FromRequest, Outcome, Request};
+use rocket::response::status::Custom;
+use rocket::serde::json::{json, Json, Value};
+use rocket::{Build, Rocket, State};
+use social_auth::api_keys::{ApiKeys, Scope};
+use social_auth::http::HttpConfig;
+use social_auth::config::{TwitchConfig, TwitterConfig};
+use social_auth::Config;
+use argon2::password_hash::SaltString;
+use argon2::{Algorithm, Argon2, Params, PasswordHasher, Version};
+use percent_encoding::percent_decode_str;
+use rand::rngs::OsRng;
+use reqwest::Url;
+use std::collections::{HashMap, VecDeque};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tempfile::TempDir;
+
+pub const TWITCH_CLIENT_ID: &str = "twitch-client-id";
+pub const TWITTER_API_KEY: &str = "twitter-api-key";
+pub const PASSWORD: &str = "hunter2";
+/// the token store backends, tests of what is persisted run against each of them
+pub const STORE_KINDS: [&str; 2] = ["fs", "sqlite"];
+
+/// binds rocket to a free port and returns its base url once it accepts connections
+pub async fn launch(rocket: Rocket<Build>) -> String {
+    let port = std::net::TcpListener::bind("127.0.0.1:0")
+        .expect("no free port")
+        .local_addr()
+        .unwrap()
+        .port();
+    let (tx, rx) = tokio::sync::oneshot::channel();
+    let rocket = rocket
+        .configure(
+            rocket::Config::figment()
+                .merge(("address", "127.0.0.1"))
+                .merge(("port", port))
+                .merge(("log_level", "off")),
+        )
+        .attach(AdHoc::on_liftoff("ready", move |_| {
+            Box::pin(async move {
+                let _ = tx.send(());
+            })
+        }));
+
+    tokio::spawn(rocket.launch());
+    rx.await.expect("server didn't start");
+    format!("http://127.0.0.1:{}", port)
+}
+
+struct Bearer(String);
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for Bearer {
+    type Error = ();
+
+    async fn from_request(req: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let token = req.headers().get_one("Authorization").and_then(|h| {
+            h.strip_prefix("Bearer ")
+                .or_else(|| h.strip_prefix("OAuth "))
+        });
+        match token {
+            Some(token) => Outcome::Success(Bearer(token.to_string())),
+            None => Outcome::Failure((Status::Unauthorized, ())),
+        }
+    }
+}
+
+/// a response that replaces the next real one of a path
+struct Injected {
+    status: Status,
+    body: Value,
+    ratelimit_reset: Option<u64>,
+}
+
+#[derive(Default)]
+pub struct FakeTwitchState {
+    counter: AtomicUsize,
+    /// valid access tokens and the login they belong to
+    pub access_tokens: Mutex<HashMap<String, String>>,
+    /// valid refresh tokens and the login they belong to
+    pub refresh_tokens: Mutex<HashMap<String, String>>,
+    pub channel_updates: Mutex<Vec<(String, Value)>>,
+    pub commercials: Mutex<Vec<Value>>,
+    pub revoked: Mutex<Vec<String>>,
+    /// the X-Request-Id header of every request
+    pub request_ids: Mutex<Vec<String>>,
+    pub user_agents: Mutex<Vec<String>>,
+    /// method and path of every request
+    pub requests: Mutex<Vec<String>>,
+    /// responses that replace the next responses of a path
+    injected: Mutex<HashMap<String, VecDeque<Injected>>>,
+}
+
+impl FakeTwitchState {
+    /// answers the next request to `path` with `status` instead
+    pub fn fail_next(&self, path: &str, status: Status, ratelimit_reset: Option<u64>) {
+        let body = json!({ "error": status.reason().unwrap_or("Error"), "status": status.code, "message": "injected failure" });
+        self.inject(path, Injected { status, body, ratelimit_reset });
+    }
+
+    /// answers the next request to `path` with `body` instead
+    pub fn respond_next(&self, path: &str, body: Value) {
+        self.inject(path, Injected { status: Status::Ok, body, ratelimit_reset: None });
+    }
+
+    fn inject(&self, path: &str, response: Injected) {
+        self.injected
+            .lock()
+            .unwrap()
+            .entry(path.to_string())
+            .or_default()
+            .push_back(response);
+    }
+
+    /// how many requests there were to `path`
+    pub fn request_count(&self, method: &str, path: &str) -> usize {
+        let request = format!("{} {}", method, path);
+        self.requests.lock().unwrap().iter().filter(|r| **r == request).count()
+    }
+
+    /// makes a new token pair for `login` and returns the access and refresh token
+    pub fn issue(&self, login: &str) -> (String, String) {
+        let n = self.counter.fetch_add(1, Ordering::SeqCst);
+        let access_token = format!("access-{}", n);
+        let refresh_token = format!("refresh-{}", n);
+        self.access_tokens
+            .lock()
+            .unwrap()
+            .insert(access_token.clone(), login.to_string());
+        self.refresh_tokens
+            .lock()
+            .unwrap()
+            .insert(refresh_token.clone(), login.to_string());
+        (access_token, refresh_token)
+    }
+
+    fn token_response(&self, login: &str) -> Value {
+        let (access_token, refresh_token) = self.issue(login);
+        json!({
+            "access_token": access_token,
+            "refresh_token": refresh_token,
+            "expires_in": 14400,
+            "scope": ["channel:manage:broadcast", "user:read:email", "channel:edit:commercial"],
+            "token_type": "bearer"
+        })
+    }
+
+    fn login_for(&self, bearer: &Bearer) -> Result<String, Custom<Json<Value>>> {
+        self.access_tokens
+            .lock()
+            .unwrap()
+            .get(&bearer.0)
+            .cloned()
+            .ok_or_else(|| twitch_error(Status::Unauthorized, "Invalid OAuth token"))
+    }
+}
+
+fn twitch_error(status: Status, message: &str) -> Custom<Json<Value>> {
+    Custom(
+        status,
+        Json(json!({ "error": status.reason().unwrap_or_default(), "status": status.code, "message": message })),
+    )
+}
+
+type TwitchResult = Result<Json<Value>, Custom<Json<Value>>>;
+
+#[post("/oauth2/token?<grant_type>&<code>&<refresh_token>")]
+fn twitch_token(
+    state: &State<Arc<FakeTwitchState>>,
+    grant_type: &str,
+    code: Option<&str>,
+    refresh_token: Option<&str>,
+) -> TwitchResult {
+    match (grant_type, code, refresh_token) {
+        // the code is the login of the user that authorized us
+        ("authorization_code", Some(code), _) if code != "invalid" => {
+            Ok(Json(state.token_response(code)))
+        }
+        ("refresh_token", _, Some(refresh_token)) => {
+            let login = state.refresh_tokens.lock().unwrap().remove(refresh_token);
+            match login {
+                Some(login) => Ok(Json(state.token_response(&login))),
+                None => Err(twitch_error(Status::BadRequest, "Invalid refresh token")),
+            }
+        }
+        _ => Err(twitch_error(Status::BadRequest, "Invalid authorization code")),
+    }
+}
+
+#[get("/oauth2/validate")]
+fn twitch_validate(state: &State<Arc<FakeTwitchState>>, bearer: Bearer) -> TwitchResult {
+    let login = state.login_for(&bearer)?;
+    Ok(Json(json!({
+        "client_id": TWITCH_CLIENT_ID,
+        "login": login,
+        "scopes": [],
+        "user_id": "1234",
+        "expires_in": 14400
+    })))
+}
+
+#[post("/oauth2/revoke?<token>")]
+fn twitch_revoke(state: &State<Arc<FakeTwitchState>>, token: &str) -> Result<Status, Custom<Json<Value>>> {
+    if state.access_tokens.lock().unwrap().remove(token).is_none() {
+        return Err(twitch_error(Status::BadRequest, "Invalid token"));
+    }
+
+    state.revoked.lock().unwrap().push(token.to_string());
+    Ok(Status::Ok)
+}
+
+#[get("/helix/users?<login>")]
+fn twitch_users(state: &State<Arc<FakeTwitchState>>, bearer: Bearer, login: &str) -> TwitchResult {
+    state.login_for(&bearer)?;
+    match login {
+        "onestay" => Ok(Json(json!({ "data": [{ "id": "1234", "login": "onestay" }] }))),
+        "charity" => Ok(Json(json!({ "data": [{ "id": "5678", "login": "charity" }] }))),
+        _ => Ok(Json(json!({ "data": [] }))),
+    }
+}
+
+#[get("/helix/search/categories?<query>")]
+fn twitch_search_categories(
+    state: &State<Arc<FakeTwitchState>>,
+    bearer: Bearer,
+    query: &str,
+) -> TwitchResult {
+    state.login_for(&bearer)?;
+    Ok(Json(json!({ "data": [{ "id": "509658", "name": query }] })))
+}
+
+#[patch("/helix/channels?<broadcaster_id>", data = "<body>")]
+fn twitch_update_channel(
+    state: &State<Arc<FakeTwitchState>>,
+    bearer: Bearer,
+    broadcaster_id: &str,
+    body: Json<Value>,
+) -> Result<Status, Custom<Json<Value>>> {
+    state.login_for(&bearer)?;
+    state
+        .channel_updates
+        .lock()
+        .unwrap()
+        .push((broadcaster_id.to_string(), body.into_inner()));
+    Ok(Status::NoContent)
+}
+
+#[post("/helix/channels/commercial", data = "<body>")]
+fn twitch_commercial(
+    state: &State<Arc<FakeTwitchState>>,
+    bearer: Bearer,
+    body: Json<Value>,
+) -> TwitchResult {
+    state.login_for(&bearer)?;
+    let length = body["length"].clone();
+    state.commercials.lock().unwrap().push(body.into_inner());
+    Ok(Json(json!({
+        "data": [{ "length": length, "message": "", "retry_after": 480 }]
+    })))
+}
+
+pub struct FakeTwitch {
+    pub url: String,
+    pub state: Arc<FakeTwitchState>,
+}
+
+impl FakeTwitch {
+    pub async fn start() -> FakeTwitch {
+        let state = Arc::new(FakeTwitchState::default());
+        let recorder = state.clone();
+        let rocket = rocket::build()
+            .manage(state.clone())
+            .attach(AdHoc::on_request("record requests", move |req, _| {
+                if let Some(id) = req.headers().get_one("X-Request-Id") {
+                    recorder.request_ids.lock().unwrap().push(id.to_string());
+                }
+                if let Some(user_agent) = req.headers().get_one("User-Agent") {
+                    recorder.user_agents.lock().unwrap().push(user_agent.to_string());
+                }
+                recorder
+                    .requests
+                    .lock()
+                    .unwrap()
+                    .push(format!("{} {}", req.method(), req.uri().path()));
+                Box::pin(async {})
+            }))
+            .attach(AdHoc::on_response("inject responses", |req, res| {
+                Box::pin(async move {
+                    let state = req.rocket().state::<Arc<FakeTwitchState>>().unwrap();
+                    let injected = state
+                        .injected
+                        .lock()
+                        .unwrap()
+                        .get_mut(req.uri().path().as_str())
+                        .and_then(|injected| injected.pop_front());
+                    if let Some(Injected { status, body, ratelimit_reset }) = injected {
+                        let body = body.to_string();
+                        res.set_status(status);
+                        res.set_header(ContentType::JSON);
+                        res.set_sized_body(body.len(), std::io::Cursor::new(body));
+                        if let Some(reset) = ratelimit_reset {
+                            res.set_raw_header("Ratelimit-Reset", reset.to_string());
+                        }
+                    }
+                })
+            }))
+            .mount(
+                "/",
+                routes![
+                    twitch_token,
+                    twitch_validate,
+                    twitch_revoke,
+                    twitch_users,
+                    twitch_search_categories,
+                    twitch_update_channel,
+                    twitch_commercial
+                ],
+            );
+
+        FakeTwitch {
+            url: launch(rocket).await,
+            state,
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct FakeTwitterState {
+    counter: AtomicUsize,
+    /// request tokens that were handed out and not exchanged yet
+    request_tokens: Mutex<Vec<String>>,
+    /// the oauth_callback of every request token request
+    pub callbacks: Mutex<Vec<String>>,
+    pub tweets: Mutex<Vec<String>>,
+    pub invalidated: AtomicUsize,
+}
+
+struct OAuthHeader(String);
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for OAuthHeader {
+    type Error = ();
+
+    async fn from_request(req: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        match req.headers().get_one("Authorization") {
+            Some(h) if h.starts_with("OAuth ") && h.contains(TWITTER_API_KEY) => {
+                Outcome::Success(OAuthHeader(h.to_string()))
+            }
+            _ => Outcome::Failure((Status::Unauthorized, ())),
+        }
+    }
+}
+
+impl OAuthHeader {
+    fn param(&self, name: &str) -> Option<String> {
+        self.0
+            .trim_start_matches("OAuth ")
+            .split(", ")
+            .filter_map(|param| param.split_once('='))
+            .find(|(key, _)| *key == name)
+            .map(|(_, value)| {
+                percent_decode_str(value.trim_matches('"'))
+                    .decode_utf8_lossy()
+                    .into_owned()
+            })
+    }
+}
+
+#[post("/oauth/request_token")]
+fn twitter_request_token(state: &State<Arc<FakeTwitterState>>, oauth: OAuthHeader) -> String {
+    let callback = oauth.param("oauth_callback").expect("missing oauth_callback");
+    state.callbacks.lock().unwrap().push(callback);
+
+    let request_token = format!("request-token-{}", state.counter.fetch_add(1, Ordering::SeqCst));
+    state.request_tokens.lock().unwrap().push(request_token.clone());
+    format!(
+        "oauth_token={}&oauth_token_secret=request-secret&oauth_callback_confirmed=true",
+        request_token
+    )
+}
+
+#[post("/oauth/access_token")]
+fn twitter_access_token(state: &State<Arc<FakeTwitterState>>, oauth: OAuthHeader) -> Custom<&'static str> {
+    let mut request_tokens = state.request_tokens.lock().unwrap();
+    let known_token = oauth
+        .param("oauth_token")
+        .and_then(|token| request_tokens.iter().position(|t| *t == token));
+    let index = match known_token {
+        Some(index) => index,
+        None => return Custom(Status::Unauthorized, "Invalid request token."),
+    };
+    if oauth.param("oauth_verifier").as_deref() != Some("verifier") {
+        return Custom(Status::Unauthorized, "Error processing your OAuth request: Invalid oauth_verifier parameter");
+    }
+    request_tokens.remove(index);
+
+    Custom(
+        Status::Ok,
+        "oauth_token=access-token&oauth_token_secret=access-secret&user_id=42&screen_name=OneStay",
+    )
+}
+
+#[get("/1.1/account/verify_credentials.json")]
+fn twitter_verify_credentials(_oauth: OAuthHeader) -> Json<Value> {
+    Json(json!({ "id": 42, "screen_name": "OneStay" }))
+}
+
+#[derive(FromForm)]
+struct TweetForm {
+    status: String,
+}
+
+#[post("/1.1/statuses/update.json", data = "<form>")]
+fn twitter_tweet(
+    state: &State<Arc<FakeTwitterState>>,
+    _oauth: OAuthHeader,
+    form: Form<TweetForm>,
+) -> Custom<Json<Value>> {
+    let mut tweets = state.tweets.lock().unwrap();
+    if tweets.contains(&form.status) {
+        return Custom(
+            Status::Forbidden,
+            Json(json!({ "errors": [{ "code": 187, "message": "Status is a duplicate." }] })),
+        );
+    }
+
+    tweets.push(form.status.clone());
+    Custom(Status::Ok, Json(json!({ "id": tweets.len(), "text": form.status })))
+}
+
+#[post("/1.1/oauth/invalidate_token")]
+fn twitter_invalidate_token(state: &State<Arc<FakeTwitterState>>, _oauth: OAuthHeader) -> Json<Value> {
+    state.invalidated.fetch_add(1, Ordering::SeqCst);
+    Json(json!({ "access_token": "access-token" }))
+}
+
+pub struct FakeTwitter {
+    pub url: String,
+    pub state: Arc<FakeTwitterState>,
+}
+
+impl FakeTwitter {
+    pub async fn start() -> FakeTwitter {
+        let state = Arc::new(FakeTwitterState::default());
+        let rocket = rocket::build().manage(state.clone()).mount(
+            "/",
+            routes![
+                twitter_request_token,
+                twitter_access_token,
+                twitter_verify_credentials,
+                twitter_tweet,
+                twitter_invalidate_token
+            ],
+        );
+
+        FakeTwitter {
+            url: launch(rocket).await,
+            state,
+        }
+    }
+}
+
+/// the server under test together with the fake apis it talks to
+pub struct TestApp {
+    pub twitch: FakeTwitch,
+    pub twitter: FakeTwitter,
+    pub store_dir: TempDir,
+    /// `fs` or `sqlite`
+    pub store_kind: &'static str,
+}
+
+impl TestApp {
+    pub async fn start() -> TestApp {
+        TestApp::start_with_store("fs").await
+    }
+
+    pub async fn start_with_store(store_kind: &'static str) -> TestApp {
+        TestApp {
+            twitch: FakeTwitch::start().await,
+            twitter: FakeTwitter::start().await,
+            store_dir: TempDir::new().expect("unable to create token store dir"),
+            store_kind,
+        }
+    }
+
+    /// the directory of the fs store or the database of the sqlite store
+    pub fn store_path(&self) -> PathBuf {
+        match self.store_kind {
+            "sqlite" => self.store_dir.path().join("tokens.db"),
+            _ => self.store_dir.path().to_path_buf(),
+        }
+    }
+
+    /// what the store holds for `key`, read without going through the server
+    pub fn stored_value(&self, key: &str) -> Option<Vec<u8>> {
+        match self.store_kind {
+            "sqlite" => rusqlite::Connection::open(self.store_path())
+                .unwrap()
+                .query_row("SELECT value FROM tokens WHERE key = ?1", [key], |row| row.get(0))
+                .ok(),
+            _ => std::fs::read(self.store_path().join(format!("{}.json", key))).ok(),
+        }
+    }
+
+    pub fn store_value(&self, key: &str, value: &[u8]) {
+        match self.store_kind {
+            "sqlite" => {
+                let conn = rusqlite::Connection::open(self.store_path()).unwrap();
+                conn.execute(
+                    "CREATE TABLE IF NOT EXISTS tokens (key TEXT PRIMARY KEY, value BLOB NOT NULL)",
+                    [],
+                )
+                .unwrap();
+                conn.execute(
+                    "INSERT OR REPLACE INTO tokens (key, value) VALUES (?1, ?2)",
+                    rusqlite::params![key, value],
+                )
+                .unwrap();
+            }
+            _ => std::fs::write(self.store_path().join(format!("{}.json", key)), value).unwrap(),
+        }
+    }
+
+    pub fn config(&self) -> Config {
+        Config {
+            twitch: Some(TwitchConfig {
+                client_id: TWITCH_CLIENT_ID.to_string(),
+                client_secret: "twitch-client-secret".to_string(),
+                redirect_uri: "http://localhost:8000/twitch/authorize/callback".to_string(),
+                auth_base_url: format!("{}/oauth2", self.twitch.url),
+                api_base_url: format!("{}/helix", self.twitch.url),
+            }),
+            twitter: Some(TwitterConfig {
+                api_key: TWITTER_API_KEY.to_string(),
+                api_secret: "twitter-api-secret".to_string(),
+                callback_url: "http://localhost:8000/twitter/authorize/callback".to_string(),
+                api_base_url: self.twitter.url.clone(),
+            }),
+            password_hash: Some(cheap_password_hash(PASSWORD)),
+            session_idle_timeout: Duration::from_secs(60 * 60),
+            session_absolute_timeout: Duration::from_secs(12 * 60 * 60),
+            session_cookie_secure: true,
+            login_attempts: 5,
+            login_global_attempts: 100,
+            api_rate_limit: 60,
+            audit_log_path: self.store_dir.path().join("audit.log").to_string_lossy().into_owned(),
+            http: HttpConfig {
+                retry_base_delay: Duration::from_millis(10),
+                ..HttpConfig::default()
+            },
+            token_store: self.store_kind.to_string(),
+            token_store_path: Some(self.store_path().to_string_lossy().into_owned()),
+            token_encryption_key: None,
+            token_encryption_key_previous: vec![],
+        }
+    }
+
+    /// runs the server on a free port for clients that talk http, tokens have to be saved
+    /// before calling this. returns its base url and an api key with every scope
+    pub async fn serve(&self) -> (String, String) {
+        let rocket = social_auth::build(self.config()).await.unwrap();
+        let (_, key) = rocket
+            .state::<ApiKeys>()
+            .expect("api keys not managed")
+            .create("test", Scope::ALL.to_vec(), None)
+            .await
+            .unwrap();
+        (launch(rocket).await, key)
+    }
+
+    /// a local client for the server, tokens have to be saved before calling this
+    pub async fn client(&self) -> Client {
+        self.client_with(self.config()).await
+    }
+
+    pub async fn client_with(&self, config: Config) -> Client {
+        Client::tracked(social_auth::build(config).await.unwrap())
+            .await
+            .expect("invalid rocket instance")
+    }
+
+    /// saves a twitch token for `login` like the callback would, `obtained_at` 0 means it's expired
+    pub fn save_twitch_token(&self, login: &str, obtained_at: u64) -> String {
+        self.save_twitch_token_with_scopes(
+            login,
+            obtained_at,
+            &["channel:manage:broadcast", "user:read:email", "channel:edit:commercial"],
+        )
+    }
+
+    pub fn save_twitch_token_with_scopes(&self, login: &str, obtained_at: u64, scopes: &[&str]) -> String {
+        let (access_token, refresh_token) = self.twitch.state.issue(login);
+        let token = json!({
+            "access_token": access_token,
+            "refresh_token": refresh_token,
+            "expires_in": 14400,
+            "scope": scopes,
+            "token_type": "bearer",
+            "obtained_at": obtained_at
+        });
+        self.store_value(&format!("twitch_auth.{}", login), token.to_string().as_bytes());
+        access_token
+    }
+
+    pub fn save_twitter_token(&self, screen_name: &str) {
+        let token = json!({
+            "Access": {
+                "consumer": { "key": TWITTER_API_KEY, "secret": "twitter-api-secret" },
+                "access": { "key": "access-token", "secret": "access-secret" }
+            }
+        });
+        self.store_value(&format!("twitter_auth.{}", screen_name), token.to_string().as_bytes());
+    }
+
+    pub fn saved_token(&self, key: &str) -> Option<Value> {
+        let bytes = self.stored_value(key)?;
+        Some(serde_json::from_slice(&bytes).unwrap())
+    }
+}
+
+/// argon2 with the default parameters is slow in debug builds, the parameters are part of
+/// the hash so a cheap one is verified just the same
+pub fn cheap_password_hash(password: &str) -> String {
+    let argon2 = Argon2::new(
+        Algorithm::Argon2id,
+        Version::V0x13,
+        Params::new(1024, 1, 1, None).unwrap(),
+    );
+    argon2
+        .hash_password(password.as_bytes(), &SaltString::generate(&mut OsRng))
+        .unwrap()
+        .to_string()
+}
+
+pub fn now() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+}
+
+/// a new api key with every scope
+pub async fn api_key(client: &Client) -> Header<'static> {
+    api_key_with_scopes(client, Scope::ALL.to_vec()).await
+}
+
+pub async fn api_key_with_scopes(client: &Client, scopes: Vec<Scope>) -> Header<'static> {
+    let (_, key) = client
+        .rocket()
+        .state::<ApiKeys>()
+        .expect("api keys not managed")
+        .create("test", scopes, None)
+        .await
+        .unwrap();
+    Header::new("Authorization", key)
+}
+
+pub fn query_param(url: &str, name: &str) -> Option<String> {
+    Url::parse(url)
+        .unwrap()
+        .query_pairs()
+        .find(|(key, _)| key == name)
+        .map(|(_, value)| value.into_owned())
+}
+
+/// goes through the authorization like the browser of a logged in user would, the fake
+/// twitch issues a token for whatever login is used as the code
+pub async fn connect_twitch<'c>(client: &'c Client, login: &str) -> LocalResponse<'c> {
+    let res = client.get("/twitch/authorize").dispatch().await;
+    assert_eq!(res.status(), Status::SeeOther);
+    let location = res.headers().get_one("Location").unwrap();
+    let state = query_param(location, "state").expect("authorize url has no state");
+
+    client
+        .get(format!("/twitch/authorize/callback?code={}&state={}", login, state))
+        .dispatch()
+        .await
+}
+
+pub async fn connect_twitter<'c>(app: &TestApp, client: &'c Client) -> LocalResponse<'c> {
+    let res = client.get("/twitter/authorize").dispatch().await;
+    assert_eq!(res.status(), Status::SeeOther);
+    let location = res.headers().get_one("Location").unwrap();
+    let oauth_token = query_param(location, "oauth_token").unwrap();
+
+    // twitter adds the token and verifier to the callback url we gave it
+    let callback = app.twitter.state.callbacks.lock().unwrap().last().cloned().unwrap();
+    let mut callback = Url::parse(&callback).unwrap();
+    callback
+        .query_pairs_mut()
+        .append_pair("oauth_token", &oauth_token)
+        .append_pair("oauth_verifier", "verifier");
+    client
+        .get(format!("{}?{}", callback.path(), callback.query().unwrap()))
+        .dispatch()
+        .await
+}
+
+/// logs in as the admin that is created from the configured password hash
+pub async fn login(client: &Client) {
+    login_as(client, "admin", PASSWORD).await
+}
+
+pub async fn login_as(client: &Client, username: &str, password: &str) {
+    let res = client
+        .post("/login")
+        .header(ContentType::Form)
+        .body(format!("username={}&password={}", username, password))
+        .dispatch()
+        .await;
+    assert_eq!(res.status(), Status::SeeOther);
+    assert_eq!(res.headers().get_one("Location"), Some("/"));
+}
+
+pub async fn json(res: LocalResponse<'_>) -> Value {
+    let body = res.into_string().await.expect("response has no body");
+    serde_json::from_str(&body).expect("response isn't json")
+}
@@ -0,0 +1,228 @@
+#[macro_use]
+extern crate rocket;
+
+mod common;
+
+use common::{api_key, TestApp};
+use rocket::figment::providers::{Format, Toml};
+use rocket::figment::Figment;
+use rocket::http::{ContentType, Status};
+use social_auth::config::EnvOverrides;
+use social_auth::Config;
+
+fn from_toml(toml: &str) -> Result<Config, Vec<String>> {
+    Config::from_figment(&Figment::from(Toml::string(toml)).select(rocket::Config::DEBUG_PROFILE))
+        .map_err(|e| e.problems.iter().map(ToString::to_string).collect())
+}
+
+/// what keeps the server from starting with `config`
+async fn startup_problems(config: Config) -> Vec<String> {
+    match social_auth::build(config).await {
+        Ok(_) => panic!("the server started"),
+        Err(e) => e.problems.iter().map(ToString::to_string).collect(),
+    }
+}
+
+/// what is wrong with `toml`, config doesn't implement debug since it has secrets
+fn problems_of(toml: &str) -> Vec<String> {
+    match from_toml(toml) {
+        Ok(_) => panic!("the config is valid"),
+        Err(problems) => problems,
+    }
+}
+
+#[test]
+fn only_configured_services_are_enabled() {
+    let config = from_toml(
+        r#"
+        [twitch]
+        client_id = "id"
+        client_secret = "secret"
+
+        [session]
+        idle_timeout = 600
+        "#,
+    )
+    .unwrap();
+    let twitch = config.twitch.as_ref().unwrap();
+    assert_eq!(twitch.redirect_uri, "http://localhost:8000/twitch/authorize/callback");
+    assert!(config.twitter.is_none());
+    assert_eq!(config.enabled_services(), vec!["twitch"]);
+    assert_eq!(config.session_idle_timeout.as_secs(), 600);
+    assert_eq!(config.token_store, "fs");
+
+    // the session cookie only reaches the callback on the host it was set for
+    let config = from_toml(
+        r#"
+        [twitter]
+        api_key = "key"
+        api_secret = "secret"
+        "#,
+    )
+    .unwrap();
+    assert_eq!(
+        config.twitter.as_ref().unwrap().callback_url,
+        "http://localhost:8000/twitter/authorize/callback"
+    );
+
+    // configured but turned off
+    let config = from_toml(
+        r#"
+        [twitch]
+        enabled = false
+        client_id = "id"
+        "#,
+    )
+    .unwrap();
+    assert!(config.enabled_services().is_empty());
+}
+
+#[test]
+fn every_problem_is_reported() {
+    let problems = problems_of(
+        r#"
+        [twitch]
+        client_id = "id"
+
+        [twitter]
+        enabled = true
+        api_key = "key"
+        api_secret = "secret"
+        callback_url = "http://127.0.0.1:8000/callback"
+
+        [token_store]
+        kind = "redis"
+        encryption_key = "dG9vIHNob3J0"
+        "#,
+    );
+    assert_eq!(
+        problems,
+        vec![
+            "twitch.client_secret: is missing, set it in the config file or with TWITCH_CLIENT_SECRET",
+            "twitter.callback_url: has to point to /twitter/authorize/callback of this server",
+            "token_store.kind: has to be fs or sqlite, not \"redis\"",
+            "token_store.encryption_key: encryption key has to be 32 bytes",
+        ]
+    );
+
+    let problems = problems_of(
+        r#"
+        [twitch]
+        client_id = "id"
+        client_secret = "secret"
+        redirect_uri = "localhost/twitch/authorize/callback"
+        "#,
+    );
+    assert_eq!(
+        problems,
+        vec!["twitch.redirect_uri: \"localhost/twitch/authorize/callback\" isn't an http or https url"]
+    );
+}
+
+#[test]
+fn secret_key_is_required_outside_of_debug() {
+    let release = |toml: &str| {
+        Config::from_figment(&Figment::from(Toml::string(toml)).select(rocket::Config::RELEASE_PROFILE))
+            .err()
+            .map(|e| e.problems.iter().map(ToString::to_string).collect::<Vec<_>>())
+    };
+    let missing = "secret_key: is missing or all zeros, it is required in the release profile, generate one with `openssl rand -base64 32` and set it in the config file or with ROCKET_SECRET_KEY";
+    assert_eq!(release(""), Some(vec![missing.to_string()]));
+    let zeros = base64::encode([0u8; 64]);
+    assert_eq!(release(&format!("secret_key = \"{}\"", zeros)), Some(vec![missing.to_string()]));
+    // rocket's defaults are what the server is really configured with
+    let figment = rocket::Config::figment().select(rocket::Config::RELEASE_PROFILE);
+    assert_eq!(Config::from_figment(&figment).err().unwrap().problems[0].to_string(), missing);
+    let problems = release("secret_key = \"too short\"").unwrap();
+    assert!(problems[0].starts_with("secret_key: "), "{:?}", problems);
+    assert!(release(&format!("secret_key = \"{}\"", base64::encode([7u8; 32]))).is_none());
+}
+
+#[test]
+fn wrong_types_name_the_key() {
+    let problems = problems_of("login_attempts = \"five\"");
+    assert_eq!(problems.len(), 1);
+    assert!(problems[0].starts_with("login_attempts: "), "{}", problems[0]);
+}
+
+#[test]
+fn environment_overrides_the_file() {
+    // no other test reads the environment
+    // the environment is read when the figment is made
+    let figment = || {
+        Figment::from(Toml::string(
+            r#"
+            login_attempts = 10
+
+            [twitch]
+            client_id = "id"
+            client_secret = "from the file"
+            "#,
+        ))
+        .merge(EnvOverrides)
+        .select(rocket::Config::DEBUG_PROFILE)
+    };
+    std::env::set_var("TWITCH_CLIENT_SECRET", "0123");
+    std::env::set_var("LOGIN_ATTEMPTS", "3");
+    let config = Config::from_figment(&figment()).unwrap();
+    // a secret of only digits stays a string
+    assert_eq!(config.twitch.unwrap().client_secret, "0123");
+    assert_eq!(config.login_attempts, 3);
+
+    std::env::set_var("LOGIN_ATTEMPTS", "many");
+    let err = Config::from_figment(&figment()).err().unwrap();
+    assert_eq!(err.problems[0].key, "LOGIN_ATTEMPTS", "{}", err);
+    std::env::remove_var("TWITCH_CLIENT_SECRET");
+    std::env::remove_var("LOGIN_ATTEMPTS");
+}
+
+#[rocket::async_test]
+async fn disabled_services_have_no_routes() {
+    let app = TestApp::start().await;
+    let mut config = app.config();
+    config.twitter = None;
+    let client = app.client_with(config).await;
+    let key = api_key(&client).await;
+
+    let res = client
+        .post("/api/v1/tweet")
+        .header(key.clone())
+        .header(ContentType::JSON)
+        .body(r#"{"status":"hi"}"#)
+        .dispatch()
+        .await;
+    assert_eq!(res.status(), Status::NotFound);
+
+    let res = client.get("/api/v1/twitch/refresh_status").header(key).dispatch().await;
+    assert_ne!(res.status(), Status::NotFound);
+}
+
+#[rocket::async_test]
+async fn startup_problems_are_reported() {
+    let app = TestApp::start().await;
+
+    // the first admin can only be created from the hash
+    let mut config = app.config();
+    config.password_hash = None;
+    assert_eq!(
+        startup_problems(config).await,
+        vec!["password_hash: is missing, the first admin is created with it while there are no users, set it in the config file or with AUTH_PASSWORD_HASH"]
+    );
+
+    let mut config = app.config();
+    config.audit_log_path = app.store_dir.path().join("missing").join("audit.log").to_string_lossy().into_owned();
+    let problems = startup_problems(config).await;
+    assert!(problems[0].starts_with("audit_log_path: unable to open "), "{:?}", problems);
+
+    let mut config = app.config();
+    config.token_store = "sqlite".to_string();
+    config.token_store_path = Some(app.store_dir.path().to_string_lossy().into_owned());
+    let problems = startup_problems(config).await;
+    assert!(problems[0].starts_with("token_store.path: unable to open the token store"), "{:?}", problems);
+
+    let err = social_auth::rotate_key(&app.config()).await.err().unwrap();
+    assert_eq!(
+        err.to_string(),
+        "the configuration is invalid:\n  - token_store.encryption_key: is missing, rotate-key re-encrypts the tokens with it, set it in the config file or with TOKEN_ENCRYPTION_KEY"
+    );
+}
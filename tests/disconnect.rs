@@ -0,0 +1,93 @@
+#[macro_use]
+extern crate rocket;
+
+mod common;
+
+use common::{api_key, api_key_with_scopes, json, login, now, TestApp};
+use rocket::http::Status;
+use social_auth::api_keys::Scope;
+use std::sync::atomic::Ordering;
+
+#[rocket::async_test]
+async fn disconnect_from_dashboard() {
+    let app = TestApp::start().await;
+    let access_token = app.save_twitch_token("onestay", now());
+    app.save_twitch_token("charity", now());
+    let client = app.client().await;
+    let key = api_key(&client).await;
+
+    let res = client
+        .post("/twitch/accounts/onestay/disconnect")
+        .dispatch()
+        .await;
+    assert_eq!(res.status(), Status::NotFound);
+
+    login(&client).await;
+    let res = client
+        .post("/twitch/accounts/OneStay/disconnect")
+        .dispatch()
+        .await;
+    assert_eq!(res.status(), Status::SeeOther);
+    assert_eq!(*app.twitch.state.revoked.lock().unwrap(), vec![access_token]);
+    assert!(app.saved_token("twitch_auth.onestay").is_none());
+    assert!(app.saved_token("twitch_auth.charity").is_some());
+
+    let res = client.get("/api/v1/avail").header(key).dispatch().await;
+    let avail = json(res).await;
+    assert_eq!(avail["accounts"]["twitch"], rocket::serde::json::json!(["charity"]));
+
+    let res = client
+        .post("/twitch/accounts/onestay/disconnect")
+        .dispatch()
+        .await;
+    assert_eq!(res.status(), Status::Conflict);
+}
+
+#[rocket::async_test]
+async fn disconnect_invalid_twitch_token() {
+    let app = TestApp::start().await;
+    let access_token = app.save_twitch_token("onestay", now());
+    app.twitch.state.access_tokens.lock().unwrap().remove(&access_token);
+    let client = app.client().await;
+    let key = api_key(&client).await;
+
+    let res = client
+        .delete("/api/v1/auth?service=twitch")
+        .header(key)
+        .dispatch()
+        .await;
+    assert_eq!(res.status(), Status::NoContent);
+    assert!(app.saved_token("twitch_auth.onestay").is_none());
+}
+
+#[rocket::async_test]
+async fn disconnect_twitter_from_api() {
+    let app = TestApp::start().await;
+    app.save_twitter_token("onestay");
+    let client = app.client().await;
+
+    let key = api_key_with_scopes(&client, vec![Scope::ReadAuth]).await;
+    let res = client
+        .delete("/api/v1/auth?service=twitter")
+        .header(key)
+        .dispatch()
+        .await;
+    assert_eq!(res.status(), Status::Forbidden);
+
+    let key = api_key_with_scopes(&client, vec![Scope::ManageAccounts, Scope::ReadAuth]).await;
+    let res = client
+        .delete("/api/v1/auth?service=twitter&account=onestay")
+        .header(key.clone())
+        .dispatch()
+        .await;
+    assert_eq!(res.status(), Status::NoContent);
+    assert_eq!(app.twitter.state.invalidated.load(Ordering::SeqCst), 1);
+    assert!(app.saved_token("twitter_auth.onestay").is_none());
+
+    let res = client
+        .get("/api/v1/auth?service=twitter")
+        .header(key)
+        .dispatch()
+        .await;
+    assert_eq!(res.status(), Status::Conflict);
+}
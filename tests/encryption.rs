@@ -0,0 +1,174 @@
+#[macro_use]
+extern crate rocket;
+
+mod common;
+
+use common::{api_key, connect_twitch, json, login, now, TestApp};
+use rocket::http::Status;
+use social_auth::encryption::{EncryptedTokenStore, MasterKey};
+use social_auth::store::{FsTokenStore, TokenStore};
+use std::sync::Arc;
+use tempfile::TempDir;
+
+fn key(byte: u8) -> String {
+    base64::encode([byte; 32])
+}
+
+fn master_key(byte: u8) -> MasterKey {
+    MasterKey::from_base64(&key(byte)).unwrap()
+}
+
+fn contains(haystack: &[u8], needle: &[u8]) -> bool {
+    haystack.windows(needle.len()).any(|window| window == needle)
+}
+
+#[rocket::async_test]
+async fn values_are_encrypted() {
+    let dir = TempDir::new().unwrap();
+    let inner: Arc<dyn TokenStore> = Arc::new(FsTokenStore::new(dir.path()).unwrap());
+    let store = EncryptedTokenStore::new(inner.clone(), master_key(1), vec![]);
+
+    store.save("twitch_auth.onestay", b"secret-access-token").await.unwrap();
+    assert_eq!(store.load("twitch_auth.onestay").await.unwrap().unwrap(), b"secret-access-token");
+    let raw = inner.load("twitch_auth.onestay").await.unwrap().unwrap();
+    assert!(!contains(&raw, b"secret-access-token"));
+
+    // every value gets its own data key
+    store.save("twitter_auth.onestay", b"secret-access-token").await.unwrap();
+    assert_ne!(inner.load("twitter_auth.onestay").await.unwrap().unwrap(), raw);
+}
+
+#[rocket::async_test]
+async fn plaintext_is_encrypted_when_loaded() {
+    let dir = TempDir::new().unwrap();
+    let inner: Arc<dyn TokenStore> = Arc::new(FsTokenStore::new(dir.path()).unwrap());
+    inner.save("twitch_auth.onestay", b"{\"access_token\":\"plain\"}").await.unwrap();
+
+    let store = EncryptedTokenStore::new(inner.clone(), master_key(1), vec![]);
+    assert_eq!(
+        store.load("twitch_auth.onestay").await.unwrap().unwrap(),
+        b"{\"access_token\":\"plain\"}"
+    );
+    let raw = inner.load("twitch_auth.onestay").await.unwrap().unwrap();
+    assert!(!contains(&raw, b"plain"));
+    assert_eq!(
+        store.load("twitch_auth.onestay").await.unwrap().unwrap(),
+        b"{\"access_token\":\"plain\"}"
+    );
+}
+
+#[rocket::async_test]
+async fn wrong_key_is_an_error() {
+    let dir = TempDir::new().unwrap();
+    let inner: Arc<dyn TokenStore> = Arc::new(FsTokenStore::new(dir.path()).unwrap());
+    EncryptedTokenStore::new(inner.clone(), master_key(1), vec![])
+        .save("twitch_auth.onestay", b"token")
+        .await
+        .unwrap();
+
+    let store = EncryptedTokenStore::new(inner.clone(), master_key(2), vec![master_key(3)]);
+    let err = store.load("twitch_auth.onestay").await.unwrap_err();
+    assert_eq!(err.message(), "unable to decrypt token, is TOKEN_ENCRYPTION_KEY correct?");
+    assert!(store.rotate().await.is_err());
+
+    assert!(MasterKey::from_base64("not base64!").is_err());
+    assert!(MasterKey::from_base64(&base64::encode([1u8; 16])).is_err());
+}
+
+#[rocket::async_test]
+async fn values_only_decrypt_under_their_key() {
+    let dir = TempDir::new().unwrap();
+    let inner: Arc<dyn TokenStore> = Arc::new(FsTokenStore::new(dir.path()).unwrap());
+    let store = EncryptedTokenStore::new(inner.clone(), master_key(1), vec![]);
+    store.save("twitch_auth.onestay", b"onestay token").await.unwrap();
+
+    let raw = inner.load("twitch_auth.onestay").await.unwrap().unwrap();
+    inner.save("twitch_auth.charity", &raw).await.unwrap();
+    let err = store.load("twitch_auth.charity").await.unwrap_err();
+    assert_eq!(err.message(), "token twitch_auth.charity is corrupted or was saved under another key");
+    assert_eq!(store.load("twitch_auth.onestay").await.unwrap().unwrap(), b"onestay token");
+}
+
+#[rocket::async_test]
+async fn version_1_envelopes_are_sealed_again() {
+    use aes_gcm::aead::{Aead, NewAead};
+    use aes_gcm::{Aes256Gcm, Key, Nonce};
+
+    // sealed without the store key like before it was bound to it
+    let seal = |key: &[u8], msg: &[u8]| {
+        let mut sealed = vec![0u8; 12];
+        sealed.extend(Aes256Gcm::new(Key::from_slice(key)).encrypt(Nonce::from_slice(&[0u8; 12]), msg).unwrap());
+        base64::encode(sealed)
+    };
+    let envelope = serde_json::json!({
+        "encryption_version": 1,
+        "data_key": seal(&[1u8; 32], &[9u8; 32]),
+        "data": seal(&[9u8; 32], b"old token"),
+    });
+    let dir = TempDir::new().unwrap();
+    let inner: Arc<dyn TokenStore> = Arc::new(FsTokenStore::new(dir.path()).unwrap());
+    inner.save("twitch_auth.onestay", envelope.to_string().as_bytes()).await.unwrap();
+
+    let store = EncryptedTokenStore::new(inner.clone(), master_key(1), vec![]);
+    assert_eq!(store.load("twitch_auth.onestay").await.unwrap().unwrap(), b"old token");
+    let raw: serde_json::Value = serde_json::from_slice(&inner.load("twitch_auth.onestay").await.unwrap().unwrap()).unwrap();
+    assert_eq!(raw["encryption_version"], 2);
+    assert_eq!(store.load("twitch_auth.onestay").await.unwrap().unwrap(), b"old token");
+}
+
+#[rocket::async_test]
+async fn rotate_to_a_new_key() {
+    let dir = TempDir::new().unwrap();
+    let inner: Arc<dyn TokenStore> = Arc::new(FsTokenStore::new(dir.path()).unwrap());
+    let old = EncryptedTokenStore::new(inner.clone(), master_key(1), vec![]);
+    old.save("twitch_auth.onestay", b"twitch token").await.unwrap();
+    old.save("twitter_auth.onestay", b"twitter token").await.unwrap();
+
+    let store = EncryptedTokenStore::new(inner.clone(), master_key(2), vec![master_key(1)]);
+    assert_eq!(store.rotate().await.unwrap(), 2);
+    assert_eq!(store.rotate().await.unwrap(), 0);
+
+    // the old key isn't needed anymore
+    let store = EncryptedTokenStore::new(inner.clone(), master_key(2), vec![]);
+    assert_eq!(store.load("twitch_auth.onestay").await.unwrap().unwrap(), b"twitch token");
+    assert_eq!(store.load("twitter_auth.onestay").await.unwrap().unwrap(), b"twitter token");
+    assert!(old.load("twitch_auth.onestay").await.is_err());
+}
+
+#[rocket::async_test]
+async fn server_never_writes_plaintext_tokens() {
+    let app = TestApp::start().await;
+    // saved before encryption was turned on
+    let plaintext_token = app.save_twitch_token("onestay", now());
+    let mut config = app.config();
+    config.token_encryption_key = Some(key(1));
+    let client = app.client_with(config).await;
+    login(&client).await;
+    assert_eq!(connect_twitch(&client, "charity").await.status(), Status::SeeOther);
+
+    let key = api_key(&client).await;
+    let res = client
+        .get("/api/v1/auth?service=twitch&account=charity")
+        .header(key.clone())
+        .dispatch()
+        .await;
+    assert_eq!(res.status(), Status::Ok);
+    let access_token = json(res).await["access_token"].as_str().unwrap().to_string();
+    let res = client
+        .get("/api/v1/auth?service=twitch&account=onestay")
+        .header(key)
+        .dispatch()
+        .await;
+    assert_eq!(json(res).await["access_token"], plaintext_token.as_str());
+
+    for entry in std::fs::read_dir(app.store_dir.path()).unwrap() {
+        let path = entry.unwrap().path();
+        if path.extension() != Some("json".as_ref()) {
+            continue;
+        }
+
+        let raw = std::fs::read(&path).unwrap();
+        assert!(!contains(&raw, access_token.as_bytes()), "{:?}", path);
+        assert!(!contains(&raw, plaintext_token.as_bytes()), "{:?}", path);
+    }
+}
@@ -0,0 +1,99 @@
+#[macro_use]
+extern crate rocket;
+
+mod common;
+
+use common::{api_key, json, now, TestApp};
+use rocket::http::{ContentType, Status};
+
+#[rocket::async_test]
+async fn upstream_errors_keep_service_and_status() {
+    let app = TestApp::start().await;
+    app.save_twitch_token("onestay", now());
+    let client = app.client().await;
+    let key = api_key(&client).await;
+    app.twitch.state.fail_next("/helix/channels/commercial", Status::BadRequest, None);
+
+    let res = client
+        .post("/api/v1/twitch/commercial?login=onestay&length=90")
+        .header(key)
+        .dispatch()
+        .await;
+    assert_eq!(res.status(), Status::BadGateway);
+    let body = json(res).await;
+    assert_eq!(body["status"], 502);
+    assert_eq!(body["code"], "upstream_error");
+    assert_eq!(body["upstream"]["service"], "twitch");
+    assert_eq!(body["upstream"]["status"], 400);
+    assert_eq!(body["upstream"]["code"], "Bad Request");
+    assert!(body["message"].as_str().unwrap().contains("injected failure"));
+}
+
+#[rocket::async_test]
+async fn upstream_rejecting_the_token() {
+    let app = TestApp::start().await;
+    app.save_twitch_token("onestay", now());
+    let client = app.client().await;
+    let key = api_key(&client).await;
+    app.twitch.state.fail_next("/helix/channels/commercial", Status::Unauthorized, None);
+
+    let res = client
+        .post("/api/v1/twitch/commercial?login=onestay&length=90")
+        .header(key)
+        .dispatch()
+        .await;
+    assert_eq!(res.status(), Status::BadGateway);
+    assert_eq!(json(res).await["code"], "upstream_unauthorized");
+}
+
+#[rocket::async_test]
+async fn upstream_rate_limit_is_passed_on() {
+    let app = TestApp::start().await;
+    app.save_twitch_token("onestay", now());
+    let client = app.client().await;
+    let key = api_key(&client).await;
+    app.twitch.state.fail_next("/helix/channels/commercial", Status::TooManyRequests, Some(now() + 60));
+
+    let res = client
+        .post("/api/v1/twitch/commercial?login=onestay&length=90")
+        .header(key)
+        .dispatch()
+        .await;
+    assert_eq!(res.status(), Status::TooManyRequests);
+    let retry_after: u64 = res.headers().get_one("Retry-After").unwrap().parse().unwrap();
+    assert!((55..=60).contains(&retry_after));
+    let body = json(res).await;
+    assert_eq!(body["code"], "upstream_rate_limited");
+    assert_eq!(body["upstream"]["status"], 429);
+}
+
+#[rocket::async_test]
+async fn client_errors_have_codes() {
+    let app = TestApp::start().await;
+    let client = app.client().await;
+    let key = api_key(&client).await;
+
+    let res = client.get("/api/v1/nothing").header(key.clone()).dispatch().await;
+    assert_eq!(res.status(), Status::NotFound);
+    assert_eq!(json(res).await["code"], "not_found");
+
+    let res = client
+        .post("/api/v1/tweet")
+        .header(key.clone())
+        .header(ContentType::JSON)
+        .body(r#"{"status":"going live"}"#)
+        .dispatch()
+        .await;
+    assert_eq!(res.status(), Status::BadRequest);
+    let body = json(res).await;
+    assert_eq!(body["code"], "invalid_request");
+    assert!(body.get("upstream").is_none());
+
+    let res = client
+        .get("/api/v1/auth?service=twitch")
+        .header(key)
+        .dispatch()
+        .await;
+    assert_eq!(res.status(), Status::Conflict);
+    assert_eq!(json(res).await["code"], "account_not_connected");
+}
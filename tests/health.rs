@@ -0,0 +1,81 @@
+#[macro_use]
+extern crate rocket;
+
+mod common;
+
+use common::{api_key, api_key_with_scopes, json, now, TestApp};
+use rocket::http::Status;
+use social_auth::api_keys::Scope;
+
+#[rocket::async_test]
+async fn healthz_and_readyz() {
+    let app = TestApp::start().await;
+    let client = app.client().await;
+
+    let res = client.get("/healthz").dispatch().await;
+    assert_eq!(res.status(), Status::Ok);
+    assert_eq!(json(res).await["status"], "ok");
+
+    let res = client.get("/readyz").dispatch().await;
+    assert_eq!(res.status(), Status::Ok);
+    assert_eq!(json(res).await["status"], "ready");
+}
+
+#[rocket::async_test]
+async fn readyz_fails_without_token_store() {
+    let app = TestApp::start().await;
+    let client = app.client().await;
+    std::fs::remove_dir_all(app.store_dir.path()).unwrap();
+
+    let res = client.get("/readyz").dispatch().await;
+    assert_eq!(res.status(), Status::ServiceUnavailable);
+    let body = json(res).await;
+    assert_eq!(body["status"], "unavailable");
+    assert!(body["error"].as_str().unwrap().starts_with("token store"));
+
+    // the process itself is still fine
+    let res = client.get("/healthz").dispatch().await;
+    assert_eq!(res.status(), Status::Ok);
+}
+
+#[rocket::async_test]
+async fn status_checks_tokens_with_the_services() {
+    let app = TestApp::start().await;
+    app.save_twitch_token("onestay", now());
+    let revoked = app.save_twitch_token("charity", now());
+    // the refresh token is gone too so the background refresh can't fix it
+    let login = app.twitch.state.access_tokens.lock().unwrap().remove(&revoked).unwrap();
+    app.twitch.state.refresh_tokens.lock().unwrap().retain(|_, owner| *owner != login);
+    app.save_twitter_token("onestay");
+    let client = app.client().await;
+    let key = api_key(&client).await;
+
+    let res = client.get("/api/v1/status").header(key).dispatch().await;
+    assert_eq!(res.status(), Status::Ok);
+    let body = json(res).await;
+
+    let twitch = &body["data"]["twitch"];
+    assert_eq!(twitch["accounts"][0]["account"], "charity");
+    assert_eq!(twitch["accounts"][0]["valid"], false);
+    assert!(twitch["accounts"][0]["error"].is_string());
+    assert_eq!(twitch["accounts"][1]["account"], "onestay");
+    assert_eq!(twitch["accounts"][1]["valid"], true);
+    assert!(twitch["accounts"][1]["expires_at"].as_u64().unwrap() > now());
+    assert!(twitch["last_success"].as_u64().unwrap() >= now() - 60);
+
+    let twitter = &body["data"]["twitter"];
+    assert_eq!(twitter["accounts"][0]["account"], "onestay");
+    assert_eq!(twitter["accounts"][0]["valid"], true);
+    assert!(twitter["accounts"][0]["expires_at"].is_null());
+    assert!(twitter["last_success"].is_u64());
+}
+
+#[rocket::async_test]
+async fn status_needs_read_auth() {
+    let app = TestApp::start().await;
+    let client = app.client().await;
+    let key = api_key_with_scopes(&client, vec![Scope::Tweet]).await;
+
+    let res = client.get("/api/v1/status").header(key).dispatch().await;
+    assert_eq!(res.status(), Status::Forbidden);
+}
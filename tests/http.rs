@@ -0,0 +1,108 @@
+#[macro_use]
+extern crate rocket;
+
+mod common;
+
+use common::{api_key, now, TestApp};
+use rocket::http::Status;
+
+#[rocket::async_test]
+async fn server_errors_of_idempotent_requests_are_retried() {
+    let app = TestApp::start().await;
+    app.save_twitch_token("onestay", now());
+    let client = app.client().await;
+    let key = api_key(&client).await;
+    app.twitch.state.fail_next("/helix/users", Status::ServiceUnavailable, None);
+    app.twitch.state.fail_next("/helix/users", Status::BadGateway, None);
+
+    let res = client
+        .get("/api/v1/twitch/login_to_id?login=charity")
+        .header(key)
+        .dispatch()
+        .await;
+    assert_eq!(res.status(), Status::Ok);
+    assert_eq!(app.twitch.state.request_count("GET", "/helix/users"), 3);
+}
+
+#[rocket::async_test]
+async fn retrying_gives_up() {
+    let app = TestApp::start().await;
+    app.save_twitch_token("onestay", now());
+    let client = app.client().await;
+    let key = api_key(&client).await;
+    for _ in 0..3 {
+        app.twitch.state.fail_next("/helix/users", Status::ServiceUnavailable, None);
+    }
+
+    let res = client
+        .get("/api/v1/twitch/login_to_id?login=charity")
+        .header(key)
+        .dispatch()
+        .await;
+    assert_ne!(res.status(), Status::Ok);
+    // the first attempt and two retries
+    assert_eq!(app.twitch.state.request_count("GET", "/helix/users"), 3);
+}
+
+#[rocket::async_test]
+async fn server_errors_of_other_requests_are_not_retried() {
+    let app = TestApp::start().await;
+    app.save_twitch_token("onestay", now());
+    let client = app.client().await;
+    let key = api_key(&client).await;
+    app.twitch.state.fail_next("/helix/channels/commercial", Status::ServiceUnavailable, None);
+
+    let res = client
+        .post("/api/v1/twitch/commercial?login=onestay&length=90")
+        .header(key)
+        .dispatch()
+        .await;
+    assert_ne!(res.status(), Status::Ok);
+    assert_eq!(app.twitch.state.request_count("POST", "/helix/channels/commercial"), 1);
+}
+
+#[rocket::async_test]
+async fn rate_limited_requests_wait_for_the_reset() {
+    let app = TestApp::start().await;
+    app.save_twitch_token("onestay", now());
+    let client = app.client().await;
+    let key = api_key(&client).await;
+    app.twitch.state.fail_next("/helix/channels/commercial", Status::TooManyRequests, Some(now()));
+
+    let res = client
+        .post("/api/v1/twitch/commercial?login=onestay&length=90")
+        .header(key.clone())
+        .dispatch()
+        .await;
+    assert_eq!(res.status(), Status::Ok);
+    assert_eq!(app.twitch.state.request_count("POST", "/helix/channels/commercial"), 2);
+
+    // nobody waits a minute for a response
+    app.twitch.state.fail_next("/helix/channels/commercial", Status::TooManyRequests, Some(now() + 60));
+    let res = client
+        .post("/api/v1/twitch/commercial?login=onestay&length=90")
+        .header(key)
+        .dispatch()
+        .await;
+    assert_ne!(res.status(), Status::Ok);
+    assert_eq!(app.twitch.state.request_count("POST", "/helix/channels/commercial"), 3);
+}
+
+#[rocket::async_test]
+async fn requests_have_a_user_agent() {
+    let app = TestApp::start().await;
+    app.save_twitch_token("onestay", now());
+    let client = app.client().await;
+    let key = api_key(&client).await;
+
+    let res = client
+        .get("/api/v1/twitch/login_to_id?login=charity")
+        .header(key)
+        .dispatch()
+        .await;
+    assert_eq!(res.status(), Status::Ok);
+
+    let user_agents = app.twitch.state.user_agents.lock().unwrap();
+    assert!(!user_agents.is_empty());
+    assert!(user_agents.iter().all(|user_agent| user_agent.starts_with("social_auth/")));
+}
@@ -0,0 +1,60 @@
+#[macro_use]
+extern crate rocket;
+
+mod common;
+
+use common::{api_key, now, TestApp};
+use rocket::http::{Header, Status};
+
+#[rocket::async_test]
+async fn responses_have_a_request_id() {
+    let app = TestApp::start().await;
+    let client = app.client().await;
+
+    let res = client.get("/login").dispatch().await;
+    let id = res.headers().get_one("X-Request-Id").expect("no request id");
+    assert!(!id.is_empty());
+
+    let other = client.get("/login").dispatch().await;
+    assert_ne!(other.headers().get_one("X-Request-Id"), Some(id));
+}
+
+#[rocket::async_test]
+async fn request_id_of_the_client_is_kept() {
+    let app = TestApp::start().await;
+    let client = app.client().await;
+
+    let res = client
+        .get("/login")
+        .header(Header::new("X-Request-Id", "deploy-check_1"))
+        .dispatch()
+        .await;
+    assert_eq!(res.headers().get_one("X-Request-Id"), Some("deploy-check_1"));
+
+    let res = client
+        .get("/login")
+        .header(Header::new("X-Request-Id", "not a valid id\n"))
+        .dispatch()
+        .await;
+    let id = res.headers().get_one("X-Request-Id").expect("no request id");
+    assert_ne!(id, "not a valid id\n");
+}
+
+#[rocket::async_test]
+async fn request_id_is_sent_upstream() {
+    let app = TestApp::start().await;
+    app.save_twitch_token("onestay", now());
+    let client = app.client().await;
+    let key = api_key(&client).await;
+
+    let res = client
+        .get("/api/v1/twitch/login_to_id?login=charity")
+        .header(key)
+        .header(Header::new("X-Request-Id", "trace-me"))
+        .dispatch()
+        .await;
+    assert_eq!(res.status(), Status::Ok);
+
+    let request_ids = app.twitch.state.request_ids.lock().unwrap();
+    assert!(request_ids.iter().any(|id| id == "trace-me"));
+}
@@ -0,0 +1,115 @@
+#[macro_use]
+extern crate rocket;
+
+mod common;
+
+use common::{api_key, login, now, TestApp};
+use rocket::http::Status;
+use rocket::local::asynchronous::Client;
+
+async fn scrape(client: &Client) -> String {
+    let res = client.get("/metrics").dispatch().await;
+    assert_eq!(res.status(), Status::Ok);
+    res.into_string().await.unwrap()
+}
+
+/// the value of the series `name` that has all of `labels`
+fn value(metrics: &str, name: &str, labels: &[&str]) -> Option<f64> {
+    metrics
+        .lines()
+        .filter(|line| !line.starts_with('#'))
+        .filter(|line| line.starts_with(&format!("{}{{", name)) || line.starts_with(&format!("{} ", name)))
+        .find(|line| labels.iter().all(|label| line.contains(label)))
+        .and_then(|line| line.rsplit(' ').next())
+        .and_then(|value| value.parse().ok())
+}
+
+#[rocket::async_test]
+async fn api_and_upstream_requests_are_counted() {
+    let app = TestApp::start().await;
+    app.save_twitch_token("onestay", now());
+    let client = app.client().await;
+    let key = api_key(&client).await;
+
+    for _ in 0..2 {
+        let res = client
+            .get("/api/v1/twitch/login_to_id?login=charity")
+            .header(key.clone())
+            .dispatch()
+            .await;
+        assert_eq!(res.status(), Status::Ok);
+    }
+    client.get("/login").dispatch().await;
+
+    let metrics = scrape(&client).await;
+    let route = r#"route="/api/v1/twitch/login_to_id""#;
+    assert_eq!(
+        value(&metrics, "social_auth_api_requests_total", &[route, r#"method="GET""#, r#"status="200""#]),
+        Some(2.0)
+    );
+    assert_eq!(
+        value(&metrics, "social_auth_api_request_duration_seconds_count", &[route]),
+        Some(2.0)
+    );
+    // the dashboard isn't part of the api metrics
+    assert!(!metrics.contains(r#"route="/login""#));
+
+    let endpoint = r#"endpoint="/helix/users""#;
+    assert_eq!(
+        value(&metrics, "social_auth_upstream_requests_total", &[endpoint, r#"service="twitch""#, r#"status="200""#]),
+        Some(2.0)
+    );
+    assert_eq!(
+        value(&metrics, "social_auth_upstream_request_duration_seconds_count", &[endpoint]),
+        Some(2.0)
+    );
+}
+
+#[rocket::async_test]
+async fn token_refreshes_are_counted() {
+    let app = TestApp::start().await;
+    app.save_twitch_token("onestay", 0);
+    let client = app.client().await;
+    let key = api_key(&client).await;
+
+    let res = client
+        .get("/api/v1/twitch/login_to_id?login=charity")
+        .header(key)
+        .dispatch()
+        .await;
+    assert_eq!(res.status(), Status::Ok);
+
+    let metrics = scrape(&client).await;
+    assert_eq!(
+        value(&metrics, "social_auth_token_refreshes_total", &[r#"service="twitch""#, r#"outcome="success""#]),
+        Some(1.0)
+    );
+    assert_eq!(
+        value(&metrics, "social_auth_token_refreshes_total", &[r#"outcome="failure""#]),
+        None
+    );
+}
+
+#[rocket::async_test]
+async fn sessions_and_accounts_are_reported() {
+    let app = TestApp::start().await;
+    app.save_twitch_token("onestay", now());
+    app.save_twitch_token("charity", now());
+    app.save_twitter_token("onestay");
+    let client = app.client().await;
+
+    let metrics = scrape(&client).await;
+    assert_eq!(value(&metrics, "social_auth_active_sessions", &[]), Some(0.0));
+    assert_eq!(
+        value(&metrics, "social_auth_connected_accounts", &[r#"service="twitch""#]),
+        Some(2.0)
+    );
+    assert_eq!(
+        value(&metrics, "social_auth_connected_accounts", &[r#"service="twitter""#]),
+        Some(1.0)
+    );
+
+    login(&client).await;
+    let metrics = scrape(&client).await;
+    assert_eq!(value(&metrics, "social_auth_active_sessions", &[]), Some(1.0));
+}
@@ -0,0 +1,71 @@
+use egg_mode::KeyPair;
+use reqwest::Url;
+use social_auth::oauth1::signed_authorization_header;
+
+/// the parameters of an `Authorization: OAuth ...` header, still percent encoded
+fn header_params(header: &str) -> Vec<(String, String)> {
+    header
+        .strip_prefix("OAuth ")
+        .expect("not an oauth header")
+        .split(", ")
+        .map(|param| {
+            let (key, value) = param.split_once('=').unwrap();
+            (key.to_string(), value.trim_matches('"').to_string())
+        })
+        .collect()
+}
+
+fn param<'a>(params: &'a [(String, String)], name: &str) -> Option<&'a str> {
+    params.iter().find(|(key, _)| key == name).map(|(_, value)| value.as_str())
+}
+
+/// the example of https://developer.twitter.com/en/docs/authentication/oauth-1-0a/creating-a-signature,
+/// the status has reserved characters and the query parameter has to be sorted in between the others
+#[test]
+fn twitter_signature_example() {
+    let url = Url::parse("https://api.twitter.com/1.1/statuses/update.json?include_entities=true").unwrap();
+    let consumer = KeyPair::new("xvz1evFS4wEEPTGEFPHBog", "kAcSOqF21Fu85e7zjz7ZN2U4ZRhfV3WpwPAoE3Z7kBw");
+    let token = KeyPair::new(
+        "370773112-GmHxMAgYyLbNEtIKZeRNFsMKPR9EyMZeS9weJAEb",
+        "LswwdoUaIvS8ltyTt5jkRh4J50vUPVVHtR2YPi5kE",
+    );
+    let header = signed_authorization_header(
+        "post",
+        &url,
+        &consumer,
+        Some(&token),
+        &[("status", "Hello Ladies + Gentlemen, a signed OAuth request!")],
+        "kYjzVBB8Y0ZFabxSWbWovY3uYSQ2pTgmZeNu2VS4cg",
+        1318622958,
+    );
+
+    let params = header_params(&header);
+    assert_eq!(param(&params, "oauth_signature"), Some("hCtSmYh%2BiHYCEqBWrE7C7hYmtUk%3D"));
+    assert_eq!(param(&params, "oauth_consumer_key"), Some("xvz1evFS4wEEPTGEFPHBog"));
+    assert_eq!(param(&params, "oauth_token"), Some("370773112-GmHxMAgYyLbNEtIKZeRNFsMKPR9EyMZeS9weJAEb"));
+    assert_eq!(param(&params, "oauth_signature_method"), Some("HMAC-SHA1"));
+    assert_eq!(param(&params, "oauth_timestamp"), Some("1318622958"));
+    assert_eq!(param(&params, "oauth_version"), Some("1.0"));
+    // the body isn't part of the header, only of the signature
+    assert_eq!(param(&params, "status"), None);
+}
+
+#[test]
+fn oauth_params_are_signed_and_sent() {
+    let url = Url::parse("https://api.twitter.com/oauth/request_token").unwrap();
+    let consumer = KeyPair::new("key", "secret");
+    let sign = |callback: &str| {
+        signed_authorization_header("POST", &url, &consumer, None, &[("oauth_callback", callback)], "nonce", 1)
+    };
+
+    let header = sign("http://127.0.0.1:8000/twitter/authorize/callback?a=b&c=d");
+    let params = header_params(&header);
+    assert_eq!(
+        param(&params, "oauth_callback"),
+        Some("http%3A%2F%2F127.0.0.1%3A8000%2Ftwitter%2Fauthorize%2Fcallback%3Fa%3Db%26c%3Dd")
+    );
+    assert_eq!(param(&params, "oauth_token"), None);
+    // the callback is part of the signature
+    let other = header_params(&sign("http://127.0.0.1:8000/twitter/authorize/callback"));
+    assert_ne!(param(&params, "oauth_signature"), param(&other, "oauth_signature"));
+}
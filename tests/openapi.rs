@@ -0,0 +1,80 @@
+#[macro_use]
+extern crate rocket;
+
+mod common;
+
+use common::{json, TestApp};
+use rocket::http::{ContentType, Status};
+
+#[rocket::async_test]
+async fn openapi_document() {
+    let app = TestApp::start().await;
+    let client = app.client().await;
+
+    // clients need it before they have a key
+    let res = client.get("/api/v1/openapi.json").dispatch().await;
+    assert_eq!(res.status(), Status::Ok);
+    let doc = json(res).await;
+    assert!(doc["openapi"].as_str().unwrap().starts_with("3."));
+
+    let scheme = &doc["components"]["securitySchemes"]["api_key"];
+    assert_eq!(scheme["type"], "apiKey");
+    assert_eq!(scheme["in"], "header");
+    assert_eq!(scheme["name"], "Authorization");
+
+    let tweet = &doc["paths"]["/api/v1/tweet"]["post"];
+    assert_eq!(tweet["requestBody"]["content"]["application/json"]["schema"]["$ref"], "#/components/schemas/PostTweetRequest");
+    assert_eq!(tweet["responses"]["401"]["content"]["application/json"]["schema"]["$ref"], "#/components/schemas/ErrorResponse");
+    assert!(tweet["security"][0].get("api_key").is_some());
+
+    let commercial = &doc["paths"]["/api/v1/twitch/commercial"]["post"];
+    assert_eq!(commercial["responses"]["200"]["content"]["application/json"]["schema"]["$ref"], "#/components/schemas/TwitchAdJson");
+
+    let schemas = &doc["components"]["schemas"];
+    for schema in ["ErrorResponse", "ErrorCode", "TwitchAdJson", "StringResponse", "StatusResponse"] {
+        assert!(schemas.get(schema).is_some(), "{} is missing", schema);
+    }
+    let codes = schemas["ErrorCode"]["enum"].as_array().unwrap();
+    assert!(codes.iter().any(|code| code == "upstream_timeout"));
+    assert!(!codes.iter().any(|code| code == "unknown"));
+}
+
+#[rocket::async_test]
+async fn every_api_route_is_documented() {
+    let app = TestApp::start().await;
+    let client = app.client().await;
+    let doc = json(client.get("/api/v1/openapi.json").dispatch().await).await;
+
+    for route in client.rocket().routes() {
+        let path = route.uri.path();
+        if !path.starts_with("/api/v1/") || path == "/api/v1/openapi.json" || path == "/api/v1/docs" {
+            continue;
+        }
+        let method = route.method.as_str().to_lowercase();
+        assert!(
+            doc["paths"][path].get(&method).is_some(),
+            "{} {} isn't documented",
+            route.method,
+            path
+        );
+    }
+}
+
+#[rocket::async_test]
+async fn docs_page() {
+    let app = TestApp::start().await;
+    let client = app.client().await;
+
+    let res = client.get("/api/v1/docs").dispatch().await;
+    assert_eq!(res.status(), Status::Ok);
+    assert_eq!(res.content_type(), Some(ContentType::HTML));
+    let page = res.into_string().await.unwrap();
+    assert!(page.contains("/api/v1/openapi.json"));
+    // scripts are served by us, not a cdn
+    assert!(page.contains(r#"<script type="module" src="/rapidoc-min.js">"#));
+    assert!(!page.contains("https://"));
+
+    let res = client.get("/rapidoc-min.js").dispatch().await;
+    assert_eq!(res.status(), Status::Ok);
+    assert_eq!(res.content_type(), Some(ContentType::JavaScript));
+}
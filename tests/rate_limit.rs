@@ -0,0 +1,114 @@
+#[macro_use]
+extern crate rocket;
+
+mod common;
+
+use common::{api_key, json, TestApp, PASSWORD};
+use rocket::http::{ContentType, Header, Status};
+use rocket::local::asynchronous::Client;
+use social_auth::api_keys::{ApiKeys, Scope};
+use std::net::SocketAddr;
+use std::time::Duration;
+
+/// returns where the login redirected to or the Retry-After header if it was rejected
+async fn try_login(client: &Client, remote: &str, password: &str) -> Result<String, String> {
+    let res = client
+        .post("/login")
+        .remote(remote.parse::<SocketAddr>().unwrap())
+        .header(ContentType::Form)
+        .body(format!("username=admin&password={}", password))
+        .dispatch()
+        .await;
+    if res.status() == Status::TooManyRequests {
+        return Err(res.headers().get_one("Retry-After").unwrap().to_string());
+    }
+    Ok(res.headers().get_one("Location").unwrap().to_string())
+}
+
+#[rocket::async_test]
+async fn failed_logins_lock_out_the_ip() {
+    let app = TestApp::start().await;
+    let mut config = app.config();
+    config.login_attempts = 3;
+    let client = app.client_with(config).await;
+
+    for _ in 0..3 {
+        assert_eq!(try_login(&client, "10.0.0.1:1234", "wrong").await, Ok("/login".to_string()));
+    }
+
+    // even the right password is rejected while locked out
+    assert_eq!(try_login(&client, "10.0.0.1:1234", PASSWORD).await, Err("1".to_string()));
+
+    // other ips are not affected
+    assert_eq!(try_login(&client, "10.0.0.2:1234", PASSWORD).await, Ok("/".to_string()));
+
+    tokio::time::sleep(Duration::from_millis(1100)).await;
+    assert_eq!(try_login(&client, "10.0.0.1:1234", "wrong").await, Ok("/login".to_string()));
+
+    // the lockout doubles with every failure after the free attempts
+    assert_eq!(try_login(&client, "10.0.0.1:1234", PASSWORD).await, Err("2".to_string()));
+}
+
+#[rocket::async_test]
+async fn failed_logins_of_all_ips_lock_the_login() {
+    let app = TestApp::start().await;
+    let mut config = app.config();
+    config.login_global_attempts = 4;
+    let client = app.client_with(config).await;
+
+    for i in 0..4 {
+        try_login(&client, &format!("10.0.0.{}:1234", i), "wrong").await.unwrap();
+    }
+
+    assert!(try_login(&client, "10.0.1.1:1234", PASSWORD).await.is_err());
+}
+
+#[rocket::async_test]
+async fn api_keys_are_rate_limited() {
+    let app = TestApp::start().await;
+    let mut config = app.config();
+    config.api_rate_limit = 3;
+    let client = app.client_with(config).await;
+    let key = api_key(&client).await;
+
+    for _ in 0..3 {
+        let res = client.get("/api/v1/avail").header(key.clone()).dispatch().await;
+        assert_eq!(res.status(), Status::Ok);
+    }
+
+    let res = client.get("/api/v1/avail").header(key.clone()).dispatch().await;
+    assert_eq!(res.status(), Status::TooManyRequests);
+    let retry_after: u64 = res.headers().get_one("Retry-After").unwrap().parse().unwrap();
+    assert!(retry_after > 0 && retry_after <= 20);
+    let body = json(res).await;
+    assert_eq!(body["status"], 429);
+
+    // every key has its own bucket
+    let other = api_key(&client).await;
+    let res = client.get("/api/v1/avail").header(other).dispatch().await;
+    assert_eq!(res.status(), Status::Ok);
+}
+
+#[rocket::async_test]
+async fn key_limit_overrides_default() {
+    let app = TestApp::start().await;
+    let mut config = app.config();
+    config.api_rate_limit = 1;
+    let client = app.client_with(config).await;
+    let (_, key) = client
+        .rocket()
+        .state::<ApiKeys>()
+        .unwrap()
+        .create("busy", vec![Scope::ReadAuth], Some(5))
+        .await
+        .unwrap();
+
+    for _ in 0..5 {
+        let res = client
+            .get("/api/v1/avail")
+            .header(Header::new("Authorization", key.clone()))
+            .dispatch()
+            .await;
+        assert_eq!(res.status(), Status::Ok);
+    }
+}
@@ -0,0 +1,50 @@
+#[macro_use]
+extern crate rocket;
+
+mod common;
+
+use common::{launch, now, TestApp};
+use rocket::serde::json::Value;
+use social_auth::api_keys::{ApiKeys, Scope};
+use std::time::Duration;
+
+#[rocket::async_test]
+async fn background_task_replaces_invalid_token() {
+    let app = TestApp::start().await;
+    // not expired yet but twitch doesn't accept it anymore
+    let old_token = app.save_twitch_token("onestay", now());
+    app.twitch.state.access_tokens.lock().unwrap().remove(&old_token);
+
+    let rocket = social_auth::build(app.config()).await.unwrap();
+    let (_, api_key) = rocket
+        .state::<ApiKeys>()
+        .unwrap()
+        .create("test", vec![Scope::ReadAuth], None)
+        .await
+        .unwrap();
+    let url = launch(rocket).await;
+
+    let client = reqwest::Client::new();
+    for _ in 0..50 {
+        let status: Value = client
+            .get(format!("{}/api/v1/twitch/refresh_status", url))
+            .header("Authorization", &api_key)
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+
+        if !status["data"].is_null() {
+            assert_eq!(status["data"]["success"], true);
+            let saved = app.saved_token("twitch_auth.onestay").unwrap();
+            assert_ne!(saved["access_token"], old_token);
+            return;
+        }
+
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+
+    panic!("token wasn't refreshed");
+}
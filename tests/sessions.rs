@@ -0,0 +1,84 @@
+#[macro_use]
+extern crate rocket;
+
+mod common;
+
+use common::{login, TestApp, PASSWORD};
+use rocket::http::{ContentType, Status};
+use std::time::Duration;
+
+#[rocket::async_test]
+async fn logout_ends_session() {
+    let app = TestApp::start().await;
+    let client = app.client().await;
+
+    login(&client).await;
+    let res = client.get("/").dispatch().await;
+    assert_eq!(res.status(), Status::Ok);
+
+    let res = client.post("/logout").dispatch().await;
+    assert_eq!(res.headers().get_one("Location"), Some("/login"));
+
+    let res = client.get("/").dispatch().await;
+    assert_eq!(res.headers().get_one("Location"), Some("/login"));
+}
+
+#[rocket::async_test]
+async fn session_cookie_is_private() {
+    let app = TestApp::start().await;
+    let client = app.client().await;
+
+    let res = client
+        .post("/login")
+        .header(ContentType::Form)
+        .body(format!("username=admin&password={}", PASSWORD))
+        .dispatch()
+        .await;
+    let set_cookie = res.headers().get_one("Set-Cookie").unwrap().to_string();
+    assert!(set_cookie.contains("HttpOnly"));
+    assert!(set_cookie.contains("Secure"));
+    assert!(set_cookie.contains("SameSite=Lax"));
+
+    // the encrypted value can't be used as a session token
+    let cookie = client.cookies().get("session").unwrap().clone();
+    let plain = client.cookies().get_private("session").unwrap();
+    assert_ne!(cookie.value(), plain.value());
+}
+
+#[rocket::async_test]
+async fn idle_session_expires() {
+    let app = TestApp::start().await;
+    let mut config = app.config();
+    config.session_idle_timeout = Duration::from_millis(500);
+    let client = app.client_with(config).await;
+
+    login(&client).await;
+    for _ in 0..3 {
+        tokio::time::sleep(Duration::from_millis(300)).await;
+        let res = client.get("/").dispatch().await;
+        assert_eq!(res.status(), Status::Ok);
+    }
+
+    tokio::time::sleep(Duration::from_millis(600)).await;
+    let res = client.get("/").dispatch().await;
+    assert_eq!(res.headers().get_one("Location"), Some("/login"));
+}
+
+#[rocket::async_test]
+async fn session_expires_even_when_used() {
+    let app = TestApp::start().await;
+    let mut config = app.config();
+    config.session_absolute_timeout = Duration::from_millis(700);
+    let client = app.client_with(config).await;
+
+    login(&client).await;
+    for _ in 0..2 {
+        tokio::time::sleep(Duration::from_millis(300)).await;
+        let res = client.get("/").dispatch().await;
+        assert_eq!(res.status(), Status::Ok);
+    }
+
+    tokio::time::sleep(Duration::from_millis(300)).await;
+    let res = client.get("/").dispatch().await;
+    assert_eq!(res.headers().get_one("Location"), Some("/login"));
+}
@@ -0,0 +1,79 @@
+#[macro_use]
+extern crate rocket;
+
+mod common;
+
+use common::{api_key, connect_twitch, json, login, TestApp, STORE_KINDS};
+use rocket::http::Status;
+use rocket::serde::json::json;
+use social_auth::store;
+use tempfile::TempDir;
+
+/// a store of `kind` in `dir`, like the server opens it
+fn open(kind: &str, dir: &TempDir) -> std::sync::Arc<dyn store::TokenStore> {
+    let path = match kind {
+        "sqlite" => dir.path().join("tokens.db"),
+        _ => dir.path().to_path_buf(),
+    };
+    store::from_config(kind, Some(path.to_string_lossy().into_owned())).unwrap()
+}
+
+#[rocket::async_test]
+async fn save_load_and_delete() {
+    for kind in STORE_KINDS {
+        let dir = TempDir::new().unwrap();
+        let store = open(kind, &dir);
+        assert_eq!(store.load("twitch_auth.onestay").await.unwrap(), None);
+
+        store.save("twitch_auth.onestay", b"first").await.unwrap();
+        store.save("twitch_auth.onestay", b"second").await.unwrap();
+        store.save("twitter_auth.onestay", b"\x00binary\xff").await.unwrap();
+        assert_eq!(store.load("twitch_auth.onestay").await.unwrap().unwrap(), b"second", "{}", kind);
+        assert_eq!(store.load("twitter_auth.onestay").await.unwrap().unwrap(), b"\x00binary\xff", "{}", kind);
+
+        let mut keys = store.keys().await.unwrap();
+        keys.sort();
+        assert_eq!(keys, vec!["twitch_auth.onestay", "twitter_auth.onestay"], "{}", kind);
+
+        store.delete("twitch_auth.onestay").await.unwrap();
+        // deleting twice is fine
+        store.delete("twitch_auth.onestay").await.unwrap();
+        assert_eq!(store.load("twitch_auth.onestay").await.unwrap(), None, "{}", kind);
+        assert_eq!(store.keys().await.unwrap(), vec!["twitter_auth.onestay"], "{}", kind);
+    }
+}
+
+#[rocket::async_test]
+async fn values_survive_reopening() {
+    for kind in STORE_KINDS {
+        let dir = TempDir::new().unwrap();
+        open(kind, &dir).save("api_keys", b"[]").await.unwrap();
+
+        let store = open(kind, &dir);
+        assert_eq!(store.load("api_keys").await.unwrap().unwrap(), b"[]", "{}", kind);
+    }
+}
+
+#[rocket::async_test]
+async fn accounts_survive_restart() {
+    for kind in STORE_KINDS {
+        let app = TestApp::start_with_store(kind).await;
+        app.save_twitter_token("onestay");
+        let client = app.client().await;
+        login(&client).await;
+        assert_eq!(connect_twitch(&client, "charity").await.status(), Status::SeeOther);
+        drop(client);
+        let saved = app.saved_token("twitch_auth.charity").unwrap();
+
+        let client = app.client().await;
+        let key = api_key(&client).await;
+        let res = client.get("/api/v1/avail").header(key.clone()).dispatch().await;
+        let body = json(res).await;
+        assert_eq!(body["accounts"]["twitch"], json!(["charity"]), "{}", kind);
+        assert_eq!(body["accounts"]["twitter"], json!(["onestay"]), "{}", kind);
+
+        let res = client.get("/api/v1/auth?service=twitch").header(key).dispatch().await;
+        assert_eq!(res.status(), Status::Ok);
+        assert_eq!(json(res).await["access_token"], saved["access_token"], "{}", kind);
+    }
+}
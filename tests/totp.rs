@@ -0,0 +1,156 @@
+#[macro_use]
+extern crate rocket;
+
+mod common;
+
+use common::{login, TestApp, PASSWORD};
+use rocket::http::{ContentType, Status};
+use rocket::local::asynchronous::Client;
+use social_auth::totp;
+use social_auth::users::Users;
+
+async fn post_form(client: &Client, uri: &'static str, body: String) -> Option<String> {
+    let res = client
+        .post(uri)
+        .header(ContentType::Form)
+        .body(body)
+        .dispatch()
+        .await;
+    res.headers().get_one("Location").map(String::from)
+}
+
+/// enables two-factor authentication for admin and returns the secret and the recovery codes
+async fn enable(client: &Client) -> (Vec<u8>, Vec<String>) {
+    let secret = totp::generate_secret();
+    let bytes = totp::base32_decode(&secret).unwrap();
+    let recovery_codes = client
+        .rocket()
+        .state::<Users>()
+        .unwrap()
+        .enable_totp("admin", &secret, &totp::code(&bytes, totp::current_step()))
+        .await
+        .unwrap();
+    (bytes, recovery_codes)
+}
+
+#[test]
+fn rfc_test_vectors() {
+    let secret = b"12345678901234567890";
+    let expected = [755224, 287082, 359152, 969429, 338314];
+    for (counter, value) in expected.iter().enumerate() {
+        assert_eq!(totp::hotp(secret, counter as u64), *value);
+    }
+    // RFC 6238 with 6 instead of 8 digits
+    assert_eq!(totp::code(secret, totp::step(59)), "287082");
+    assert_eq!(totp::code(secret, totp::step(1111111109)), "081804");
+
+    let encoded = totp::base32_encode(secret);
+    assert_eq!(encoded, "GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ");
+    assert_eq!(totp::base32_decode(&encoded.to_lowercase()).unwrap(), secret);
+}
+
+#[test]
+fn qr_code_is_inline_svg() {
+    let url = totp::otpauth_url("Issuer", "admin", "GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ");
+    let svg = totp::qr_code_svg(&url).unwrap();
+    assert!(svg.starts_with("<svg"), "{}", svg);
+    assert!(totp::qr_code_svg(&"a".repeat(8000)).is_none());
+}
+
+#[rocket::async_test]
+async fn enroll_from_dashboard() {
+    let app = TestApp::start().await;
+    let client = app.client().await;
+    login(&client).await;
+
+    let res = client.get("/totp").dispatch().await;
+    assert_eq!(res.status(), Status::Ok);
+    let secret = client.cookies().get_private("totp_enrollment").unwrap().value().to_string();
+    let bytes = totp::base32_decode(&secret).unwrap();
+
+    // the secret stays the same until the enrollment is done
+    client.get("/totp").dispatch().await;
+    assert_eq!(client.cookies().get_private("totp_enrollment").unwrap().value(), secret);
+
+    let location = post_form(&client, "/totp/enable", "code=000000".to_string()).await;
+    assert_eq!(location.as_deref(), Some("/totp"));
+    assert!(!client.rocket().state::<Users>().unwrap().get("admin").await.unwrap().totp_enabled);
+
+    let code = totp::code(&bytes, totp::current_step());
+    let res = client
+        .post("/totp/enable")
+        .header(ContentType::Form)
+        .body(format!("code={}", code))
+        .dispatch()
+        .await;
+    assert_eq!(res.status(), Status::Ok);
+    assert!(client.rocket().state::<Users>().unwrap().get("admin").await.unwrap().totp_enabled);
+    assert!(client.cookies().get_private("totp_enrollment").is_none());
+}
+
+#[rocket::async_test]
+async fn login_needs_second_factor() {
+    let app = TestApp::start().await;
+    let client = app.client().await;
+    let (secret, _) = enable(&client).await;
+
+    let location = post_form(&client, "/login", format!("username=admin&password={}", PASSWORD)).await;
+    assert_eq!(location.as_deref(), Some("/login/totp"));
+    let res = client.get("/").dispatch().await;
+    assert_eq!(res.headers().get_one("Location"), Some("/login"));
+
+    let location = post_form(&client, "/login/totp", "code=000000".to_string()).await;
+    assert_eq!(location.as_deref(), Some("/login/totp"));
+
+    // the code used for enrolling can't be used again, the next one works
+    let used = totp::code(&secret, totp::current_step());
+    let next = totp::code(&secret, totp::current_step() + 1);
+    if used != next {
+        let location = post_form(&client, "/login/totp", format!("code={}", used)).await;
+        assert_eq!(location.as_deref(), Some("/login/totp"));
+    }
+    let location = post_form(&client, "/login/totp", format!("code={}", next)).await;
+    assert_eq!(location.as_deref(), Some("/"));
+
+    let res = client.get("/").dispatch().await;
+    assert_eq!(res.status(), Status::Ok);
+}
+
+#[rocket::async_test]
+async fn second_step_needs_the_password() {
+    let app = TestApp::start().await;
+    let client = app.client().await;
+    let (secret, _) = enable(&client).await;
+
+    let res = client.get("/login/totp").dispatch().await;
+    assert_eq!(res.headers().get_one("Location"), Some("/login"));
+    let code = totp::code(&secret, totp::current_step() + 1);
+    let location = post_form(&client, "/login/totp", format!("code={}", code)).await;
+    assert_eq!(location.as_deref(), Some("/login"));
+}
+
+#[rocket::async_test]
+async fn recovery_codes_work_once() {
+    let app = TestApp::start().await;
+    let client = app.client().await;
+    let (_, recovery_codes) = enable(&client).await;
+    assert_eq!(recovery_codes.len(), 10);
+
+    post_form(&client, "/login", format!("username=admin&password={}", PASSWORD)).await;
+    let location = post_form(&client, "/login/totp", format!("code={}", recovery_codes[0])).await;
+    assert_eq!(location.as_deref(), Some("/"));
+    client.post("/logout").dispatch().await;
+
+    post_form(&client, "/login", format!("username=admin&password={}", PASSWORD)).await;
+    let location = post_form(&client, "/login/totp", format!("code={}", recovery_codes[0])).await;
+    assert_eq!(location.as_deref(), Some("/login/totp"));
+    let location = post_form(&client, "/login/totp", format!("code={}", recovery_codes[1])).await;
+    assert_eq!(location.as_deref(), Some("/"));
+
+    // disabling needs a code as well
+    let location = post_form(&client, "/totp/disable", "code=000000".to_string()).await;
+    assert_eq!(location.as_deref(), Some("/totp"));
+    assert!(client.rocket().state::<Users>().unwrap().get("admin").await.unwrap().totp_enabled);
+    post_form(&client, "/totp/disable", format!("code={}", recovery_codes[2])).await;
+    assert!(!client.rocket().state::<Users>().unwrap().get("admin").await.unwrap().totp_enabled);
+}
@@ -0,0 +1,162 @@
+#[macro_use]
+extern crate rocket;
+
+mod common;
+
+use common::{cheap_password_hash, login, login_as, TestApp, PASSWORD, STORE_KINDS};
+use rocket::http::{ContentType, Cookie, Status};
+use rocket::local::asynchronous::Client;
+use social_auth::users::{Role, Users};
+
+async fn add_user(client: &Client, username: &str, role: Role) {
+    client
+        .rocket()
+        .state::<Users>()
+        .unwrap()
+        .insert(username, cheap_password_hash(PASSWORD), role)
+        .await
+        .unwrap();
+}
+
+#[rocket::async_test]
+async fn operators_can_only_view() {
+    let app = TestApp::start().await;
+    let client = app.client().await;
+    add_user(&client, "operator", Role::Operator).await;
+    login_as(&client, "operator", PASSWORD).await;
+
+    let res = client.get("/").dispatch().await;
+    assert_eq!(res.status(), Status::Ok);
+
+    let res = client
+        .post("/api_keys")
+        .header(ContentType::Form)
+        .body("name=test&scopes=read_auth")
+        .dispatch()
+        .await;
+    assert_eq!(res.status(), Status::Forbidden);
+
+    let res = client.get("/twitch/authorize").dispatch().await;
+    assert_eq!(res.status(), Status::Forbidden);
+
+    let res = client.post("/twitch/accounts/onestay/disconnect").dispatch().await;
+    assert_eq!(res.status(), Status::Forbidden);
+
+    let res = client
+        .post("/users")
+        .header(ContentType::Form)
+        .body("username=mallory&password=pw&role=admin")
+        .dispatch()
+        .await;
+    assert_eq!(res.status(), Status::Forbidden);
+    assert!(client.rocket().state::<Users>().unwrap().get("mallory").await.is_none());
+}
+
+#[rocket::async_test]
+async fn admins_manage_users() {
+    let app = TestApp::start().await;
+    let client = app.client().await;
+    login(&client).await;
+
+    let res = client
+        .post("/users")
+        .header(ContentType::Form)
+        .body("username=alice&password=secret&role=operator")
+        .dispatch()
+        .await;
+    assert_eq!(res.headers().get_one("Location"), Some("/"));
+    let alice = client.rocket().state::<Users>().unwrap().get("alice").await.unwrap();
+    assert_eq!(alice.role, Role::Operator);
+
+    // usernames are unique
+    let res = client
+        .post("/users")
+        .header(ContentType::Form)
+        .body("username=alice&password=other&role=admin")
+        .dispatch()
+        .await;
+    assert!(res.cookies().get("_flash").is_some());
+    assert_eq!(client.rocket().state::<Users>().unwrap().list().await.len(), 2);
+
+    login_as(&client, "alice", "secret").await;
+    let res = client.get("/").dispatch().await;
+    assert_eq!(res.status(), Status::Ok);
+}
+
+#[rocket::async_test]
+async fn deleted_users_are_logged_out() {
+    let app = TestApp::start().await;
+    // untracked so both sessions can be used side by side
+    let client = Client::untracked(social_auth::build(app.config()).await.unwrap()).await.unwrap();
+    add_user(&client, "operator", Role::Operator).await;
+
+    let mut sessions: Vec<Cookie<'static>> = Vec::new();
+    for username in ["admin", "operator"] {
+        let res = client
+            .post("/login")
+            .header(ContentType::Form)
+            .body(format!("username={}&password={}", username, PASSWORD))
+            .dispatch()
+            .await;
+        sessions.push(res.cookies().get("session").unwrap().clone().into_owned());
+    }
+
+    let res = client.get("/").cookie(sessions[1].clone()).dispatch().await;
+    assert_eq!(res.status(), Status::Ok);
+
+    let res = client
+        .post("/users/operator/delete")
+        .cookie(sessions[0].clone())
+        .dispatch()
+        .await;
+    assert_eq!(res.headers().get_one("Location"), Some("/"));
+
+    let res = client.get("/").cookie(sessions[1].clone()).dispatch().await;
+    assert_eq!(res.headers().get_one("Location"), Some("/login"));
+
+    // the last admin stays
+    let res = client
+        .post("/users/admin/delete")
+        .cookie(sessions[0].clone())
+        .dispatch()
+        .await;
+    assert!(res.cookies().get("_flash").is_some());
+    assert!(client.rocket().state::<Users>().unwrap().get("admin").await.is_some());
+}
+
+#[rocket::async_test]
+async fn users_survive_restart() {
+    for kind in STORE_KINDS {
+        let app = TestApp::start_with_store(kind).await;
+        let client = app.client().await;
+        add_user(&client, "operator", Role::Operator).await;
+        drop(client);
+
+        // the password hash is only needed to create the first admin
+        let mut config = app.config();
+        config.password_hash = None;
+        let client = app.client_with(config).await;
+        login(&client).await;
+        login_as(&client, "operator", PASSWORD).await;
+    }
+}
+
+#[rocket::async_test]
+async fn unknown_users_take_as_long_as_wrong_passwords() {
+    let app = TestApp::start().await;
+    let client = app.client().await;
+    let users = client.rocket().state::<Users>().unwrap();
+    // hashed with the parameters the dashboard uses, not the cheap ones of the other tests
+    users.create("operator", PASSWORD, Role::Operator).await.unwrap();
+
+    let start = std::time::Instant::now();
+    assert!(users.verify_password("operator", "wrong").await.is_none());
+    let wrong_password = start.elapsed();
+
+    let start = std::time::Instant::now();
+    assert!(users.verify_password("nobody", PASSWORD).await.is_none());
+    let unknown_user = start.elapsed();
+
+    // generous since timing isn't exact, looking the user up alone is orders of magnitude faster
+    assert!(unknown_user * 4 > wrong_password, "{:?} vs {:?}", unknown_user, wrong_password);
+}